@@ -0,0 +1,128 @@
+//! The control law: a PID on the heading error for the turn, with the derivative taken from the IMU yaw rate, and
+//! feed-forward on the speed setpoint for the forward throttle. The two are mixed into the thruster throttles.
+use kingfisher_data_types::dds_topics::ControllerGains;
+
+/// Steps of the microcontroller's throttle, which goes from -127 to 127
+const THROTTLE_STEPS: f64 = 127.0;
+
+/// What the controller knows about the vehicle, None when it's unknown or too old.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Measurement {
+    /// Degrees from true north
+    pub heading: Option<f64>,
+    /// Degrees per second, positive turning to starboard
+    pub yaw_rate: Option<f64>,
+    /// Speed over ground (m/s)
+    pub speed: Option<f64>,
+}
+
+/// Throttles from -1 (full reverse) to 1 (full ahead).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Thrust {
+    pub port: f64,
+    pub starboard: f64,
+}
+
+pub struct Controller {
+    gains: ControllerGains,
+    /// Turn throttle of the integral term, kept within the integral limit
+    integral: f64,
+}
+
+impl Controller {
+    pub fn new(gains: ControllerGains) -> Self {
+        Controller { gains, integral: 0.0 }
+    }
+
+    pub fn gains(&self) -> &ControllerGains {
+        &self.gains
+    }
+
+    /// Swap the gains while running. The integral is kept, clamped to the new limit.
+    pub fn set_gains(&mut self, gains: ControllerGains) {
+        self.integral = self.integral.clamp(-gains.integral_limit as f64, gains.integral_limit as f64);
+        self.gains = gains;
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    /// Forget the integral, e.g. when someone else took over the thrusters.
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+    }
+
+    /// Throttles for the setpoint, `dt` seconds after the last update. Without a heading the vehicle goes straight,
+    /// which gives the GPS a course to work with, and only the yaw rate is damped.
+    pub fn update(&mut self, heading: f64, speed: f64, measurement: &Measurement, dt: f64) -> Thrust {
+        let gains = &self.gains;
+        let max = gains.max_throttle as f64;
+        let damping = -gains.heading_kd as f64 * measurement.yaw_rate.unwrap_or(0.0);
+
+        let (turn, error) = match measurement.heading {
+            Some(current) => {
+                let error = heading_error(heading, current);
+                let turn = gains.heading_kp as f64 * error + self.integral + damping;
+                // Anti-windup: stop integrating while the turn is saturated in the direction the error pushes it.
+                let saturated = turn.abs() >= max && turn.signum() == error.signum();
+                if !saturated {
+                    let limit = gains.integral_limit as f64;
+                    self.integral = (self.integral + gains.heading_ki as f64 * error * dt).clamp(-limit, limit);
+                }
+                (turn, error)
+            }
+            None => (damping, 0.0),
+        };
+        let turn = turn.clamp(-max, max);
+
+        let speed_error = measurement.speed.map(|measured| speed - measured).unwrap_or(0.0);
+        let forward = gains.speed_feed_forward as f64 * speed + gains.speed_kp as f64 * speed_error;
+        // Slow down for large heading errors, down to turning on the spot when facing away from the setpoint.
+        let forward = forward * error.to_radians().cos().max(0.0);
+        // The turn comes first, the forward throttle gets what's left of the limit.
+        let forward = forward.clamp(0.0, max - turn.abs());
+
+        Thrust {
+            port: quantize(forward + turn),
+            starboard: quantize(forward - turn),
+        }
+    }
+}
+
+/// Check gains before using them.
+pub fn check_gains(gains: &ControllerGains) -> Result<(), String> {
+    let values = [
+        ("heading_kp", gains.heading_kp),
+        ("heading_ki", gains.heading_ki),
+        ("heading_kd", gains.heading_kd),
+        ("integral_limit", gains.integral_limit),
+        ("speed_feed_forward", gains.speed_feed_forward),
+        ("speed_kp", gains.speed_kp),
+    ];
+    for (name, value) in values {
+        if !value.is_finite() || value < 0.0 {
+            return Err(format!("{} must be a positive number or zero", name));
+        }
+    }
+    if !(gains.max_throttle > 0.0 && gains.max_throttle <= 1.0) {
+        return Err("max_throttle must be above 0 and at most 1".to_string());
+    }
+    Ok(())
+}
+
+/// Difference between two headings (degrees), from -180 to 180. Positive means turning to starboard.
+pub fn heading_error(setpoint: f64, current: f64) -> f64 {
+    let error = (setpoint - current).rem_euclid(360.0);
+    if error > 180.0 {
+        error - 360.0
+    } else {
+        error
+    }
+}
+
+/// Round a throttle to what the microcontroller can apply, so the status shows what the thrusters get. -1 to 1
+/// maps onto -127 to 127, the i8 range without -128.
+fn quantize(throttle: f64) -> f64 {
+    (throttle.clamp(-1.0, 1.0) * THROTTLE_STEPS).round() / THROTTLE_STEPS
+}
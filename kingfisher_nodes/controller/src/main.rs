@@ -0,0 +1,554 @@
+//! Steers the vehicle: turns the heading and speed setpoint of the mission node into thruster throttles on the
+//! microcontroller control topic.
+//!
+//! The heading comes from the GPS course, which is only good while moving, and is carried on between fixes with the
+//! IMU yaw rate. The gains can be changed while running over the controller gains topic, the current ones are in the
+//! controller status.
+mod control;
+
+use clap::Parser;
+use control::{check_gains, Controller, Measurement, Thrust};
+use dust_dds::{
+    dds_async::{data_reader::DataReaderAsync, data_writer::DataWriterAsync},
+    infrastructure::{error::DdsError, qos::QosKind, status::NO_STATUS},
+};
+use kingfisher_data_types::dds_topics::{
+    ControllerGains, ControllerStatus, ControllerTuning, GpsData, GpsFix, ImuData, MicroControl, MicroStatus,
+    NavigationSetpoint
+};
+use kingfisher_data_types::qos_profiles::CONTROL_DEADLINE;
+use kingfisher_data_types::topic_registry::{
+    publish, subscribe, ControllerStatusTopic, ControllerTuningTopic, GpsTopic, ImuTopic, MicroControlTopic,
+    MicroStatusTopic, NavigationSetpointTopic, Topic
+};
+use kingfisher_node::{Node, NodeArgs};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+/// Identifies the controller to the microcontroller node
+const SOURCE: &str = "controller";
+/// Most samples taken from a reader at once
+const MAX_SAMPLES: i32 = 20;
+/// The status is published at least this often, and straight away when the controller engages or lets go
+const STATUS_PERIOD: Duration = Duration::from_secs(1);
+
+/// Parser for command line parameters
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct CommandLineParameters {
+    #[command(flatten)]
+    node: NodeArgs,
+}
+
+#[derive(Deserialize)]
+struct Settings {
+    /// How often the throttles are updated and sent (Hz)
+    #[serde(default = "default_update_rate")]
+    update_rate: f64,
+    /// The GPS course is only used above this speed (m/s)
+    #[serde(default = "default_min_course_speed")]
+    min_course_speed: f64,
+    /// The heading is dropped when there was no usable course for this long (s)
+    #[serde(default = "default_course_timeout")]
+    course_timeout: f64,
+    /// GPS speeds and IMU yaw rates older than this aren't used (s)
+    #[serde(default = "default_gps_timeout")]
+    gps_timeout: f64,
+    #[serde(default = "default_imu_timeout")]
+    imu_timeout: f64,
+    /// Gyroscope axis that points down through the vehicle, and the factor that turns its readings into degrees
+    /// per second positive to starboard. Make the factor negative if the IMU is mounted upside down.
+    #[serde(default = "default_gyro_axis")]
+    gyro_axis: usize,
+    #[serde(default = "default_gyro_scale")]
+    gyro_scale: f64,
+    /// Navigation lights while driving
+    #[serde(default = "default_lights")]
+    lights: bool,
+    #[serde(default)]
+    gains: GainSettings,
+}
+
+/// Gains at start, see `ControllerGains`.
+#[derive(Deserialize)]
+#[serde(default)]
+struct GainSettings {
+    heading_kp: f32,
+    heading_ki: f32,
+    heading_kd: f32,
+    integral_limit: f32,
+    speed_feed_forward: f32,
+    speed_kp: f32,
+    max_throttle: f32,
+}
+
+impl Default for GainSettings {
+    fn default() -> Self {
+        GainSettings {
+            heading_kp: 0.02,
+            heading_ki: 0.002,
+            heading_kd: 0.01,
+            integral_limit: 0.2,
+            speed_feed_forward: 0.4,
+            speed_kp: 0.1,
+            max_throttle: 0.8,
+        }
+    }
+}
+
+impl From<&GainSettings> for ControllerGains {
+    fn from(gains: &GainSettings) -> Self {
+        ControllerGains {
+            heading_kp: gains.heading_kp,
+            heading_ki: gains.heading_ki,
+            heading_kd: gains.heading_kd,
+            integral_limit: gains.integral_limit,
+            speed_feed_forward: gains.speed_feed_forward,
+            speed_kp: gains.speed_kp,
+            max_throttle: gains.max_throttle,
+        }
+    }
+}
+
+fn default_update_rate() -> f64 {
+    10.0
+}
+
+fn default_min_course_speed() -> f64 {
+    0.5
+}
+
+fn default_course_timeout() -> f64 {
+    5.0
+}
+
+fn default_gps_timeout() -> f64 {
+    2.0
+}
+
+fn default_imu_timeout() -> f64 {
+    0.5
+}
+
+fn default_gyro_axis() -> usize {
+    2
+}
+
+fn default_gyro_scale() -> f64 {
+    1.0
+}
+
+fn default_lights() -> bool {
+    true
+}
+
+impl Settings {
+    fn check(&self) -> Result<(), String> {
+        let positive = [
+            ("update_rate", self.update_rate),
+            ("course_timeout", self.course_timeout),
+            ("gps_timeout", self.gps_timeout),
+            ("imu_timeout", self.imu_timeout),
+        ];
+        for (name, value) in positive {
+            if value.is_nan() || value <= 0.0 {
+                return Err(format!("{} must be positive", name));
+            }
+        }
+        // Slower than the deadline and the microcontroller node would stop the motors between commands.
+        if self.update_rate <= 1.0 / CONTROL_DEADLINE {
+            return Err(format!("update_rate must be above {} Hz", 1.0 / CONTROL_DEADLINE));
+        }
+        if self.min_course_speed.is_nan() || self.min_course_speed < 0.0 {
+            return Err("min_course_speed can't be negative".to_string());
+        }
+        if self.gyro_axis > 2 {
+            return Err("gyro_axis must be 0, 1 or 2".to_string());
+        }
+        if !self.gyro_scale.is_finite() {
+            return Err("gyro_scale must be a number".to_string());
+        }
+        check_gains(&ControllerGains::from(&self.gains)).map_err(|e| format!("Invalid gains: {}", e))
+    }
+}
+
+/// The latest value of a measurement and when it arrived.
+struct Latest<T> {
+    value: Option<(T, Instant)>,
+}
+
+impl<T: Copy> Latest<T> {
+    fn set(&mut self, value: T) {
+        self.value = Some((value, Instant::now()));
+    }
+
+    /// The value if it isn't older than `timeout` (s).
+    fn fresh(&self, timeout: f64) -> Option<T> {
+        match self.value {
+            Some((value, received)) if received.elapsed().as_secs_f64() < timeout => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl<T> Default for Latest<T> {
+    fn default() -> Self {
+        Latest { value: None }
+    }
+}
+
+/// Lights and power relays, port and starboard.
+#[derive(Clone, Copy)]
+struct Outputs {
+    lights: (bool, bool),
+    power: (bool, bool),
+}
+
+struct ControllerNode {
+    vehicle_id: String,
+    settings: Settings,
+    controller: Controller,
+    setpoint: Latest<(f64, f64)>,
+    speed: Latest<f64>,
+    yaw_rate: Latest<f64>,
+    /// Heading estimate, set from the GPS course and carried on with the yaw rate
+    heading: Option<f64>,
+    /// When the GPS last gave a usable course
+    last_course: Option<Instant>,
+    /// Someone else is driving or the RC transmitter has taken over
+    locked_out: Option<String>,
+    /// The RC override switch is on, so our throttles would be ignored
+    rc_override: bool,
+    /// Lights and power relays as the microcontroller last reported them
+    outputs: Option<Outputs>,
+    /// Whether our commands are driving the thrusters
+    engaged: bool,
+    last_update: Instant,
+    last_thrust: Thrust,
+    last_status: Option<(bool, String, Instant)>,
+    control_writer: DataWriterAsync<MicroControl>,
+    status_writer: DataWriterAsync<ControllerStatus>,
+}
+
+impl ControllerNode {
+    async fn handle_setpoints(&mut self, reader: &DataReaderAsync<NavigationSetpoint>) {
+        for setpoint in take::<NavigationSetpointTopic>(reader).await {
+            if setpoint.id != self.vehicle_id {
+                continue;
+            }
+            let usable = setpoint.active && setpoint.heading.is_finite() && setpoint.speed.is_finite();
+            if usable {
+                self.setpoint.set((setpoint.heading as f64, setpoint.speed.max(0.0) as f64));
+            } else {
+                self.setpoint = Latest::default();
+            }
+        }
+    }
+
+    async fn handle_gps(&mut self, reader: &DataReaderAsync<GpsData>) {
+        for fix in take::<GpsTopic>(reader).await {
+            if fix.id != self.vehicle_id || matches!(fix.fix, GpsFix::None) || !fix.velocity.is_finite() {
+                continue;
+            }
+            let speed = fix.velocity as f64;
+            self.speed.set(speed);
+            if speed >= self.settings.min_course_speed && fix.direction.is_finite() {
+                self.heading = Some((fix.direction as f64).rem_euclid(360.0));
+                self.last_course = Some(Instant::now());
+            }
+        }
+    }
+
+    async fn handle_imu(&mut self, reader: &DataReaderAsync<ImuData>) {
+        for sample in take::<ImuTopic>(reader).await {
+            if sample.id != self.vehicle_id {
+                continue;
+            }
+            match sample.gyroscope.get(self.settings.gyro_axis) {
+                Some(rate) if rate.is_finite() => self.yaw_rate.set(*rate as f64 * self.settings.gyro_scale),
+                _ => (),
+            }
+        }
+    }
+
+    async fn handle_micro_status(&mut self, reader: &DataReaderAsync<MicroStatus>) {
+        for status in take::<MicroStatusTopic>(reader).await {
+            if status.id != self.vehicle_id {
+                continue;
+            }
+            self.rc_override = status.rc_override;
+            self.outputs = Some(Outputs {
+                lights: (status.port_light, status.starboard_light),
+                power: (status.port_power, status.starboard_power),
+            });
+            self.locked_out = lockout(&status);
+        }
+    }
+
+    async fn handle_tuning(&mut self, reader: &DataReaderAsync<ControllerTuning>) {
+        for tuning in take::<ControllerTuningTopic>(reader).await {
+            if tuning.id != self.vehicle_id {
+                continue;
+            }
+            match check_gains(&tuning.gains) {
+                Ok(()) => {
+                    log::info!("{} set the gains to {:?}.", tuning.source, tuning.gains);
+                    self.controller.set_gains(tuning.gains);
+                    // Publish the new gains straight away, so the tuner sees they were taken.
+                    self.last_status = None;
+                }
+                Err(e) => log::warn!("Ignoring the gains from {}: {}", tuning.source, e),
+            }
+        }
+    }
+
+    /// Run the controller and send the throttles.
+    async fn update(&mut self) {
+        // A stalled loop shouldn't wind the integral up in one go.
+        let dt = self.last_update.elapsed().as_secs_f64().min(CONTROL_DEADLINE);
+        self.last_update = Instant::now();
+
+        let yaw_rate = self.yaw_rate.fresh(self.settings.imu_timeout);
+        let course_fresh = self.last_course
+            .is_some_and(|time| time.elapsed().as_secs_f64() < self.settings.course_timeout);
+        if !course_fresh {
+            self.heading = None;
+        }
+        if let (Some(heading), Some(rate)) = (self.heading, yaw_rate) {
+            self.heading = Some((heading + rate * dt).rem_euclid(360.0));
+        }
+        let measurement = Measurement {
+            heading: self.heading,
+            yaw_rate,
+            speed: self.speed.fresh(self.settings.gps_timeout),
+        };
+
+        let setpoint = self.setpoint.fresh(CONTROL_DEADLINE);
+        match (setpoint, &self.locked_out) {
+            (Some((heading, speed)), None) => {
+                if !self.engaged {
+                    log::info!("Engaging, heading for {:.0}° at {:.1} m/s.", heading, speed);
+                    self.engaged = true;
+                }
+                self.last_thrust = self.controller.update(heading, speed, &measurement, dt);
+                self.send(true).await;
+            }
+            _ => {
+                if self.engaged {
+                    log::info!("Letting go of the thrusters.");
+                    self.engaged = false;
+                    self.last_thrust = Thrust::default();
+                    self.send(false).await;
+                }
+                self.controller.reset();
+            }
+        }
+        self.publish_status(setpoint, &measurement).await;
+    }
+
+    /// Send the last throttles. Disarming releases the thrusters for other drivers and leaves the lights and relays
+    /// as they are, so the RC transmitter or the next driver still has thruster power.
+    async fn send(&self, armed: bool) {
+        // What we drive with, which is also what the relays were last commanded to.
+        let driving = Outputs {
+            lights: (self.settings.lights, self.settings.lights),
+            power: (true, true),
+        };
+        let mut outputs = if armed { driving } else { self.outputs.unwrap_or(driving) };
+        // The report may be behind, never cut the power out from under the transmitter.
+        if self.rc_override {
+            outputs.power = (true, true);
+        }
+        let command = MicroControl {
+            id: self.vehicle_id.clone(),
+            source: SOURCE.to_string(),
+            armed,
+            port_light: outputs.lights.0,
+            starboard_light: outputs.lights.1,
+            port_power: outputs.power.0,
+            starboard_power: outputs.power.1,
+            port_throttle: self.last_thrust.port as f32,
+            starboard_throttle: self.last_thrust.starboard as f32,
+            time: now(),
+        };
+        if let Err(e) = MicroControlTopic::write(&self.control_writer, &command).await {
+            log::error!("Failed to send the throttles: {:?}", e);
+        }
+    }
+
+    async fn publish_status(&mut self, setpoint: Option<(f64, f64)>, measurement: &Measurement) {
+        let message = match (&setpoint, &self.locked_out) {
+            (Some(_), Some(reason)) => reason.clone(),
+            (Some(_), None) if measurement.heading.is_none() => "No GPS course, going straight".to_string(),
+            _ => String::new(),
+        };
+        if let Some((engaged, last_message, time)) = &self.last_status {
+            if *engaged == self.engaged && *last_message == message && time.elapsed() < STATUS_PERIOD {
+                return;
+            }
+        }
+        let unknown = |value: Option<f64>| value.map(|v| v as f32).unwrap_or(f32::NAN);
+        let status = ControllerStatus {
+            id: self.vehicle_id.clone(),
+            engaged: self.engaged,
+            message: message.clone(),
+            heading_setpoint: unknown(setpoint.map(|s| s.0)),
+            heading: unknown(measurement.heading),
+            yaw_rate: unknown(measurement.yaw_rate),
+            speed_setpoint: setpoint.map(|s| s.1 as f32).unwrap_or(0.0),
+            speed: unknown(measurement.speed),
+            integral: self.controller.integral() as f32,
+            port_throttle: self.last_thrust.port as f32,
+            starboard_throttle: self.last_thrust.starboard as f32,
+            gains: self.controller.gains().clone(),
+            time: now(),
+        };
+        match ControllerStatusTopic::write(&self.status_writer, &status).await {
+            Ok(_) => self.last_status = Some((self.engaged, message, Instant::now())),
+            Err(e) => log::error!("Failed to send the controller status: {:?}", e),
+        }
+    }
+}
+
+/// Why our commands wouldn't drive the thrusters, None if they would. The microcontroller ignores the throttles
+/// from DDS while the RC override switch is on, and only takes commands from one driver at a time.
+fn lockout(status: &MicroStatus) -> Option<String> {
+    if status.rc_override {
+        Some("The RC transmitter has taken over".to_string())
+    } else if !status.driver.is_empty() && status.driver != SOURCE {
+        Some(format!("{} is driving", status.driver))
+    } else {
+        None
+    }
+}
+
+/// Samples waiting on a reader, empty if there are none.
+async fn take<T: Topic>(reader: &DataReaderAsync<T::Data>) -> Vec<T::Data> {
+    match T::take(reader, MAX_SAMPLES).await {
+        Ok(samples) => samples.into_iter().map(|s| s.data).collect(),
+        Err(DdsError::NoData) => Vec::new(),
+        Err(e) => {
+            log::error!("Failed to take {} samples: {:?}", T::NAME, e);
+            Vec::new()
+        }
+    }
+}
+
+fn now() -> f64 {
+    match std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH) {
+        Ok(val) => val.as_secs_f64(),
+        Err(_) => 0.0
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = CommandLineParameters::parse();
+    let node = Node::init("controller", env!("CARGO_PKG_VERSION"), &cli.node, "./controller.toml");
+
+    let settings: Settings = match node.settings().clone().try_deserialize() {
+        Ok(val) => val,
+        Err(e) => {
+            log::error!("Invalid settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = settings.check() {
+        log::error!("Invalid settings: {}", e);
+        std::process::exit(1);
+    }
+
+    let participant = node.participant_async().await;
+    let publisher = participant
+        .create_publisher(QosKind::Default, None, NO_STATUS)
+        .await
+        .unwrap();
+    let subscriber = participant
+        .create_subscriber(QosKind::Default, None, NO_STATUS)
+        .await
+        .unwrap();
+    let setpoint_reader = subscribe::<NavigationSetpointTopic>(&participant, &subscriber).await.unwrap();
+    let gps_reader = subscribe::<GpsTopic>(&participant, &subscriber).await.unwrap();
+    let imu_reader = subscribe::<ImuTopic>(&participant, &subscriber).await.unwrap();
+    let micro_status_reader = subscribe::<MicroStatusTopic>(&participant, &subscriber).await.unwrap();
+    let tuning_reader = subscribe::<ControllerTuningTopic>(&participant, &subscriber).await.unwrap();
+
+    let mut controller_node = ControllerNode {
+        vehicle_id: node.vehicle_id().to_string(),
+        controller: Controller::new(ControllerGains::from(&settings.gains)),
+        settings,
+        setpoint: Latest::default(),
+        speed: Latest::default(),
+        yaw_rate: Latest::default(),
+        heading: None,
+        last_course: None,
+        locked_out: None,
+        rc_override: false,
+        outputs: None,
+        engaged: false,
+        last_update: Instant::now(),
+        last_thrust: Thrust::default(),
+        last_status: None,
+        control_writer: publish::<MicroControlTopic>(&participant, &publisher).await.unwrap(),
+        status_writer: publish::<ControllerStatusTopic>(&participant, &publisher).await.unwrap(),
+    };
+
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / controller_node.settings.update_rate));
+    node.ready();
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                controller_node.handle_tuning(&tuning_reader).await;
+                controller_node.handle_micro_status(&micro_status_reader).await;
+                controller_node.handle_setpoints(&setpoint_reader).await;
+                controller_node.handle_gps(&gps_reader).await;
+                controller_node.handle_imu(&imu_reader).await;
+                controller_node.update().await;
+                node.progress();
+            }
+            _ = node.wait_for_shutdown() => break,
+        }
+    }
+
+    if controller_node.engaged {
+        controller_node.last_thrust = Thrust::default();
+        controller_node.send(false).await;
+    }
+    node.close_async(participant).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(rc_override: bool, driver: &str) -> MicroStatus {
+        MicroStatus {
+            id: "kingfisher".to_string(),
+            port_light: true,
+            starboard_light: true,
+            port_power: true,
+            starboard_power: true,
+            port_throttle: 0.0,
+            starboard_throttle: 0.0,
+            rc_override,
+            rc_throttle: 32768,
+            rc_turn: 32768,
+            rc_switch: if rc_override { 9000 } else { 3000 },
+            driver: driver.to_string(),
+            time: 0.0,
+        }
+    }
+
+    #[test]
+    fn drives_while_the_override_switch_is_off() {
+        assert_eq!(lockout(&status(false, "")), None);
+        assert_eq!(lockout(&status(false, SOURCE)), None);
+    }
+
+    #[test]
+    fn locked_out_by_the_transmitter_and_other_drivers() {
+        assert!(lockout(&status(true, "")).is_some());
+        assert!(lockout(&status(true, SOURCE)).is_some());
+        assert_eq!(lockout(&status(false, "dashboard")), Some("dashboard is driving".to_string()));
+    }
+}
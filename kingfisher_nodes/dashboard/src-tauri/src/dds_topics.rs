@@ -1,18 +1,92 @@
+use crate::navigation::{self, Navigation};
+use crate::system_status::{self, Telemetry};
+use crate::teleop::{self, Teleop};
 use dust_dds::{
-    dds_async::{domain_participant_factory::DomainParticipantFactoryAsync, data_reader::DataReaderAsync}, 
-    infrastructure::{error::DdsError, qos::QosKind, status::NO_STATUS}, 
-    subscription::sample_info::{ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE}, 
+    dds_async::data_reader::DataReaderAsync, 
+    infrastructure::{qos::QosKind, status::NO_STATUS}, 
 };
 use kingfisher_data_types::dds_topics::{
-    GPS_TOPIC, SYSTEM_STATUS_CPU_TOPIC, SYSTEM_STATUS_DISK_TOPIC, 
-    SYSTEM_STATUS_MEMORY_TOPIC, SYSTEM_STATUS_NETWORK_TOPIC, IMU_TOPIC,
     SystemStatusMemory, SystemStatusCpu, SystemStatusNetwork, 
-    SystemStatusDisk, GpsData, ImuData
+    SystemStatusDisk, SystemStatusThermal, SystemStatusProcesses, GpsData, GpsSatellites, ImuData, Alarm,
+    AlarmSeverity, MicroStatus, Mission, Geofence, GeoPoint
+};
+use kingfisher_data_types::topic_registry::{
+    publish, subscribe, take_samples, AlarmTopic, GeofenceTopic, GoToTopic, GpsSatellitesTopic, GpsTopic, ImuTopic,
+    MicroControlTopic, MicroStatusTopic, MissionTopic, SystemStatusCpuTopic, SystemStatusDiskTopic, SystemStatusMemoryTopic, SystemStatusNetworkTopic,
+    SystemStatusProcessesTopic, SystemStatusThermalTopic
 };
 
+use kingfisher_node::{Node, VehicleFilter};
 use rerun;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use tauri::{Emitter, Manager};
+
+/// The heading arrow reaches where the vehicle will be in this long (s)
+const HEADING_ARROW_TIME: f64 = 10.0;
+/// Shortest heading arrow (m)
+const HEADING_ARROW_MIN_LENGTH: f64 = 5.0;
+/// Below this speed the GPS direction is noise, so no arrow is drawn (m/s)
+const HEADING_MIN_SPEED: f32 = 0.3;
+
+/// A vehicle seen on the network.
+#[derive(Serialize, Clone)]
+pub struct VehicleInfo {
+    pub id: String,
+    /// Time of its last sample (s since the unix epoch)
+    pub last_seen: f64,
+}
+
+/// Vehicles discovered from the keys of the samples, and which of them are displayed.
+/// Each vehicle gets its own branch of entity paths in rerun.
+pub struct Vehicles {
+    filter: VehicleFilter,
+    last_seen: Mutex<BTreeMap<String, f64>>,
+}
 
-pub fn setup_dds_topics() {
+impl Vehicles {
+    pub fn new(filter: VehicleFilter) -> Self {
+        Vehicles {
+            filter,
+            last_seen: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Note a sample from a vehicle, returning false if the vehicle isn't displayed.
+    fn seen(&self, vehicle_id: &str, rrd: &rerun::RecordingStream) -> bool {
+        if !self.filter.accepts(vehicle_id) {
+            return false;
+        }
+        let now = match std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH) {
+            Ok(val) => val.as_secs_f64(),
+            Err(_) => 0.0
+        };
+
+        let mut last_seen = self.last_seen.lock().unwrap();
+        if last_seen.insert(vehicle_id.to_string(), now).is_none() {
+            log::info!("Discovered vehicle {}", vehicle_id);
+            let list: Vec<&str> = last_seen.keys().map(|k| k.as_str()).collect();
+            rrd.log("vehicles", &rerun::TextDocument::new(list.join("\n"))).unwrap();
+        }
+        true
+    }
+
+    pub fn list(&self) -> Vec<VehicleInfo> {
+        self.last_seen.lock().unwrap().iter().map(|(id, last_seen)| VehicleInfo {
+            id: id.clone(),
+            last_seen: *last_seen,
+        }).collect()
+    }
+}
+
+/// Vehicles seen so far, for the vehicle selector.
+#[tauri::command]
+pub fn list_vehicles(vehicles: tauri::State<Arc<Vehicles>>) -> Vec<VehicleInfo> {
+    vehicles.list()
+}
+
+pub fn setup_dds_topics(node: Node, app: tauri::AppHandle) {
     
 
     let rrd = rerun::RecordingStreamBuilder::new("kingfisher")
@@ -21,103 +95,85 @@ pub fn setup_dds_topics() {
     rerun::MemoryLimit::from_fraction_of_total(0.25),
     false).unwrap();
     
+    let vehicles = Arc::new(Vehicles::new(node.vehicle_filter()));
+    app.manage(vehicles.clone());
+    let teleop = Arc::new(Teleop::default());
+    app.manage(teleop.clone());
+    let telemetry = Arc::new(Telemetry::default());
+    app.manage(telemetry.clone());
+    let vehicle_filter = node.vehicle_filter();
+    let (navigation, go_to_requests) = Navigation::new();
+    app.manage(navigation);
+
     tauri::async_runtime::spawn(async move {
         //Setting up DDS
-        let domain_id = kingfisher_data_types::DEFAULT_DOMAIN;
-        let participant_factory = DomainParticipantFactoryAsync::new();
+        let participant = node.participant_async().await;
         
-        let participant = participant_factory
-        .create_participant(domain_id, QosKind::Default, None, NO_STATUS)
-        .await
-        .unwrap();
-        
-        //Topics
-        //System Status topics
-        let topic_memory = participant
-        .create_topic::<SystemStatusMemory>(SYSTEM_STATUS_MEMORY_TOPIC, "SystemStatusMemory", QosKind::Default, None, NO_STATUS)
-        .await.unwrap();
-        let topic_cpu = participant
-        .create_topic::<SystemStatusCpu>(SYSTEM_STATUS_CPU_TOPIC, "SystemStatusCpu", QosKind::Default, None, NO_STATUS)
-        .await.unwrap();
-        let topic_network = participant
-        .create_topic::<SystemStatusCpu>(SYSTEM_STATUS_NETWORK_TOPIC, "SystemStatusNetwork", QosKind::Default, None, NO_STATUS)
-        .await.unwrap();
-        let topic_disk = participant
-        .create_topic::<SystemStatusDisk>(SYSTEM_STATUS_DISK_TOPIC, "SystemStatusDisk", QosKind::Default, None, NO_STATUS)
-        .await.unwrap();
-        let topic_gps = participant
-        .create_topic::<GpsData>(GPS_TOPIC, "GpsData", QosKind::Default, None, NO_STATUS)
-        .await.unwrap();
-        let topic_imu = participant
-        .create_topic::<GpsData>(IMU_TOPIC, "ImuData", QosKind::Default, None, NO_STATUS)
-        .await.unwrap();
-
         let subscriber = participant
         .create_subscriber(QosKind::Default, None, NO_STATUS)
         .await
         .unwrap();
         
-        let reader_memory = subscriber.create_datareader::<SystemStatusMemory>(&topic_memory, QosKind::Default, None, NO_STATUS).await.unwrap();
-        let reader_cpu = subscriber.create_datareader::<SystemStatusCpu>(&topic_cpu, QosKind::Default, None, NO_STATUS).await.unwrap();
-        let reader_network = subscriber.create_datareader::<SystemStatusNetwork>(&topic_network, QosKind::Default, None, NO_STATUS).await.unwrap();
-        let reader_disk = subscriber.create_datareader::<SystemStatusDisk>(&topic_disk, QosKind::Default, None, NO_STATUS).await.unwrap();
-        let reader_gps = subscriber.create_datareader::<GpsData>(&topic_gps, QosKind::Default, None, NO_STATUS).await.unwrap();
-        let reader_imu = subscriber.create_datareader::<ImuData>(&topic_imu, QosKind::Default, None, NO_STATUS).await.unwrap();
+        let reader_memory = subscribe::<SystemStatusMemoryTopic>(&participant, &subscriber).await.unwrap();
+        let reader_cpu = subscribe::<SystemStatusCpuTopic>(&participant, &subscriber).await.unwrap();
+        let reader_network = subscribe::<SystemStatusNetworkTopic>(&participant, &subscriber).await.unwrap();
+        let reader_disk = subscribe::<SystemStatusDiskTopic>(&participant, &subscriber).await.unwrap();
+        let reader_thermal = subscribe::<SystemStatusThermalTopic>(&participant, &subscriber).await.unwrap();
+        let reader_processes = subscribe::<SystemStatusProcessesTopic>(&participant, &subscriber).await.unwrap();
+        let reader_gps = subscribe::<GpsTopic>(&participant, &subscriber).await.unwrap();
+        let reader_gps_satellites = subscribe::<GpsSatellitesTopic>(&participant, &subscriber).await.unwrap();
+        let reader_imu = subscribe::<ImuTopic>(&participant, &subscriber).await.unwrap();
+        let reader_alarm = subscribe::<AlarmTopic>(&participant, &subscriber).await.unwrap();
+        let reader_micro_status = subscribe::<MicroStatusTopic>(&participant, &subscriber).await.unwrap();
+        let reader_mission = subscribe::<MissionTopic>(&participant, &subscriber).await.unwrap();
+        let reader_geofence = subscribe::<GeofenceTopic>(&participant, &subscriber).await.unwrap();
         
-        let rrd_mem = rrd.clone();
-        tokio::spawn (async move {
-            handle_memory_topic(reader_memory, rrd_mem).await;
-        });
-        let rrd_cpu = rrd.clone();
-        tokio::spawn (async move {
-            handle_cpu_topic(reader_cpu, rrd_cpu).await;
-        });
-        let rrd_network = rrd.clone();
-        tokio::spawn (async move {
-            handle_network_topic(reader_network, rrd_network).await;
-        });
-        let rrd_disk = rrd.clone();
-        tokio::spawn (async move {
-             handle_disk_topic(reader_disk, rrd_disk).await;
-        });
-        let rrd_gps = rrd.clone();
-        tokio::spawn (async move {
-            handle_gps_topic(reader_gps, rrd_gps).await;
-        });
-        tokio::spawn (async move {
-            handle_imu_topic(reader_imu, rrd.clone()).await;
-        });
+        tokio::spawn(handle_memory_topic(reader_memory, rrd.clone(), vehicles.clone()));
+        tokio::spawn(handle_cpu_topic(reader_cpu, rrd.clone(), vehicles.clone()));
+        tokio::spawn(handle_network_topic(reader_network, rrd.clone(), vehicles.clone()));
+        tokio::spawn(handle_disk_topic(reader_disk, rrd.clone(), vehicles.clone()));
+        tokio::spawn(handle_thermal_topic(reader_thermal, rrd.clone(), vehicles.clone()));
+        tokio::spawn(handle_processes_topic(reader_processes, rrd.clone(), vehicles.clone()));
+        tokio::spawn(handle_gps_topic(reader_gps, rrd.clone(), vehicles.clone(), telemetry.clone()));
+        tokio::spawn(handle_gps_satellites_topic(reader_gps_satellites, rrd.clone(), vehicles.clone()));
+        tokio::spawn(handle_imu_topic(reader_imu, rrd.clone(), vehicles.clone()));
+        tokio::spawn(handle_alarm_topic(reader_alarm, rrd.clone(), vehicles.clone()));
+        tokio::spawn(handle_micro_status_topic(reader_micro_status, rrd.clone(), vehicles.clone(), app.clone()));
+        tokio::spawn(handle_mission_topic(reader_mission, rrd.clone(), vehicles.clone()));
+        tokio::spawn(handle_geofence_topic(reader_geofence, rrd.clone(), vehicles.clone()));
+
+        // The native widgets get their own readers, rate limited.
+        if let Err(e) = system_status::setup_telemetry(&participant, &subscriber, telemetry, vehicle_filter).await {
+            log::error!("Failed to subscribe to the telemetry, the status widgets won't update: {:?}", e);
+        }
+
+        let publisher = participant
+        .create_publisher(QosKind::Default, None, NO_STATUS)
+        .await
+        .unwrap();
+        let writer_control = publish::<MicroControlTopic>(&participant, &publisher).await.unwrap();
+        tokio::spawn(teleop::run(teleop, writer_control));
+        let writer_go_to = publish::<GoToTopic>(&participant, &publisher).await.unwrap();
+        tokio::spawn(navigation::run(go_to_requests, writer_go_to));
+
+        node.ready();
+        // SIGINT/SIGTERM close the window too.
+        node.wait_for_shutdown().await;
+        node.close_async(participant).await;
+        app.exit(0);
     });
 }
 
 // Topic handlers
 // Function to handle reading topics from dds and sending them along via rerun
-async fn handle_memory_topic (reader: DataReaderAsync<SystemStatusMemory>, rrd: rerun::RecordingStream) {
+async fn handle_memory_topic (reader: DataReaderAsync<SystemStatusMemory>, rrd: rerun::RecordingStream, vehicles: Arc<Vehicles>) {
     loop {
-        let data = match reader.take(10, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await {
-            Ok(val) => val,
-            Err(e) => match e {
-                DdsError::NoData => {
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await; 
-                    Vec::new()
-                },
-                _ => {
-                    log::error!("Unexpected error: {:?}", e);
-                    Vec::new()
-                }
-                
+        for sample in take_samples::<SystemStatusMemoryTopic>(&reader, 10).await {
+            let sample_data = sample.data;
+            if !vehicles.seen(&sample_data.id, &rrd) {
+                continue;
             }
-        };
-        
-        for sample in data {
-            //log::info!("{:?}", sample);
-            let sample_data =  match sample.data() {
-                Ok(val) => val,
-                Err(e) => {
-                    log::error!("Failed unpack DDS system memory sample: {:?}", e);
-                    continue;
-                }
-            };
+            let vehicle = sample_data.id.clone();
             let now = match std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH) {
                 Ok(val)=> val.as_secs_f64(),
                 Err(e) => {
@@ -128,41 +184,23 @@ async fn handle_memory_topic (reader: DataReaderAsync<SystemStatusMemory>, rrd:
             };
             rrd.set_time_seconds("system_time", now);
             //Send to existing rerun instance.
-            rrd.log("system/memory/percent", &rerun::Scalar::new(sample_data.used_memory as f64 / sample_data.total_memory as f64 * 100.0)).unwrap();  
-            rrd.log("system/memory/swap_percent", &rerun::Scalar::new(sample_data.used_swap as f64 / sample_data.total_swap as f64 * 100.0)).unwrap();  
-            rrd.log("system/memory", &rerun::TextDocument::new(format!("Memory Usage: {}/{} MB\nSwap Usage: {}/{} MB", 
+            rrd.log(format!("{}/system/memory/percent", vehicle), &rerun::Scalar::new(sample_data.used_memory as f64 / sample_data.total_memory as f64 * 100.0)).unwrap();  
+            rrd.log(format!("{}/system/memory/swap_percent", vehicle), &rerun::Scalar::new(sample_data.used_swap as f64 / sample_data.total_swap as f64 * 100.0)).unwrap();  
+            rrd.log(format!("{}/system/memory", vehicle), &rerun::TextDocument::new(format!("Memory Usage: {}/{} MB\nSwap Usage: {}/{} MB", 
                         sample_data.used_memory/1024/1024, sample_data.total_memory/1024/1024, sample_data.used_swap/1024/1024, sample_data.total_swap/1024/1024))).unwrap();
         }
     }
 }
 
 // Function to handle reading topics from dds and sending them along via mpsc channels
-async fn handle_cpu_topic (reader: DataReaderAsync<SystemStatusCpu>, rrd: rerun::RecordingStream) {
+async fn handle_cpu_topic (reader: DataReaderAsync<SystemStatusCpu>, rrd: rerun::RecordingStream, vehicles: Arc<Vehicles>) {
     loop {
-        let data = match reader.take(10, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await {
-            Ok(val) => val,
-            Err(e) => match e {
-                DdsError::NoData => {
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await; 
-                    Vec::new()
-                },
-                _ => {
-                    log::error!("Unexpected error: {:?}", e);
-                    Vec::new()
-                }
-                
+        for sample in take_samples::<SystemStatusCpuTopic>(&reader, 10).await {
+            let sample_data = sample.data;
+            if !vehicles.seen(&sample_data.id, &rrd) {
+                continue;
             }
-        };
-        
-        for sample in data {
-            //log::info!("{:?}", sample);
-            let sample_data =  match sample.data() {
-                Ok(val) => val,
-                Err(e) => {
-                    log::error!("Failed unpack DDS system cpu sample: {:?}", e);
-                    continue;
-                }
-            };
+            let vehicle = sample_data.id.clone();
             let now = match std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH) {
                 Ok(val)=> val.as_secs_f64(),
                 Err(e) => {
@@ -177,38 +215,84 @@ async fn handle_cpu_topic (reader: DataReaderAsync<SystemStatusCpu>, rrd: rerun:
                 cpu_usage.push(cpu_info.usage);
             }
 
-            rrd.log("system/cpu", &rerun::BarChart::new(cpu_usage)).unwrap();
+            rrd.log(format!("{}/system/cpu", vehicle), &rerun::BarChart::new(cpu_usage)).unwrap();
+            rrd.log(format!("{}/system/load", vehicle), &rerun::Scalar::new(sample_data.load_one)).unwrap();
         }
     }
 }
 
 // Function to handle reading topics from dds and sending them along via mpsc channels
-async fn handle_disk_topic (reader: DataReaderAsync<SystemStatusDisk>, rrd: rerun::RecordingStream) {
+async fn handle_thermal_topic (reader: DataReaderAsync<SystemStatusThermal>, rrd: rerun::RecordingStream, vehicles: Arc<Vehicles>) {
     loop {
-        let data = match reader.take(10, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await {
-            Ok(val) => val,
-            Err(e) => match e {
-                DdsError::NoData => {
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await; 
-                    Vec::new()
-                },
-                _ => {
-                    log::error!("Unexpected error: {:?}", e);
-                    Vec::new()
-                }
-                
+        for sample in take_samples::<SystemStatusThermalTopic>(&reader, 10).await {
+            let sample_data = sample.data;
+            if !vehicles.seen(&sample_data.id, &rrd) {
+                continue;
             }
-        };
-        
-        for sample in data {
-            //log::info!("{:?}", sample);
-            let sample_data =  match sample.data() {
-                Ok(val) => val,
+            let vehicle = sample_data.id.clone();
+            let now = match std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH) {
+                Ok(val)=> val.as_secs_f64(),
                 Err(e) => {
-                    log::error!("Failed unpack DDS system disk sample: {:?}", e);
+                    log::error!("Failed to get system time for thermal update: {:?}", e);
+                    0.0
+                }
+            };
+            rrd.set_time_seconds("system_time", now);
+
+            for sensor in sample_data.sensors {
+                if sensor.temperature.is_nan() {
                     continue;
                 }
+                // Sensor labels can contain spaces and slashes, which rerun would treat as path separators.
+                let name = sensor.name.replace(['/', ' '], "_");
+                rrd.log(format!("{}/system/thermal/{}", vehicle, name), &rerun::Scalar::new(sensor.temperature as f64)).unwrap();
+            }
+        }
+    }
+}
+
+// Function to handle reading topics from dds and sending them along via mpsc channels
+async fn handle_processes_topic (reader: DataReaderAsync<SystemStatusProcesses>, rrd: rerun::RecordingStream, vehicles: Arc<Vehicles>) {
+    loop {
+        for sample in take_samples::<SystemStatusProcessesTopic>(&reader, 10).await {
+            let sample_data = sample.data;
+            if !vehicles.seen(&sample_data.id, &rrd) {
+                continue;
+            }
+            let vehicle = sample_data.id.clone();
+            let now = match std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH) {
+                Ok(val)=> val.as_secs_f64(),
+                Err(e) => {
+                    log::error!("Failed to get system time for processes update: {:?}", e);
+                    0.0
+                }
             };
+            rrd.set_time_seconds("system_time", now);
+
+            let mut processes = String::new();
+            for process in sample_data.processes {
+                if process.running {
+                    processes += format!("{} ({}): {:.1}% CPU, {} MB, up {} s, {} restarts\n",
+                        process.name, process.pid, process.cpu_usage, process.memory/1024/1024, process.run_time, process.restarts).as_str();
+                } else {
+                    processes += format!("{}: not running, {} restarts\n", process.name, process.restarts).as_str();
+                }
+            }
+
+            rrd.log(format!("{}/system/processes", vehicle), &rerun::TextDocument::new(processes)).unwrap();
+        }
+    }
+}
+
+// Function to handle reading topics from dds and sending them along via mpsc channels
+async fn handle_disk_topic (reader: DataReaderAsync<SystemStatusDisk>, rrd: rerun::RecordingStream, vehicles: Arc<Vehicles>) {
+    loop {
+        for sample in take_samples::<SystemStatusDiskTopic>(&reader, 10).await {
+            let sample_data = sample.data;
+            if !vehicles.seen(&sample_data.id, &rrd) {
+                continue;
+            }
+            let vehicle = sample_data.id.clone();
             let now = match std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH) {
                 Ok(val)=> val.as_secs_f64(),
                 Err(e) => {
@@ -223,38 +307,20 @@ async fn handle_disk_topic (reader: DataReaderAsync<SystemStatusDisk>, rrd: reru
                 disk_usage += format!("{}: {}/{} GB ({}%)\n", disk_info.name, disk_info.bytes_used/1024/1024/1024, (disk_info.bytes_available+disk_info.bytes_used)/1024/1024/1024, disk_info.bytes_used as f32/(disk_info.bytes_used+disk_info.bytes_available) as f32*100.0 as f32).as_str();
             }
 
-            rrd.log("system/disks", &rerun::TextDocument::new(disk_usage)).unwrap();
+            rrd.log(format!("{}/system/disks", vehicle), &rerun::TextDocument::new(disk_usage)).unwrap();
         }
     }
 }
 
 // Function to handle reading topics from dds and sending them along via mpsc channels
-async fn handle_network_topic (reader: DataReaderAsync<SystemStatusNetwork>, rrd: rerun::RecordingStream) {
+async fn handle_network_topic (reader: DataReaderAsync<SystemStatusNetwork>, rrd: rerun::RecordingStream, vehicles: Arc<Vehicles>) {
     loop {
-        let data = match reader.take(10, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await {
-            Ok(val) => val,
-            Err(e) => match e {
-                DdsError::NoData => {
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await; 
-                    Vec::new()
-                },
-                _ => {
-                    log::error!("Unexpected error: {:?}", e);
-                    Vec::new()
-                }
-                
+        for sample in take_samples::<SystemStatusNetworkTopic>(&reader, 10).await {
+            let sample_data = sample.data;
+            if !vehicles.seen(&sample_data.id, &rrd) {
+                continue;
             }
-        };
-        
-        for sample in data {
-            //log::info!("{:?}", sample);
-            let sample_data =  match sample.data() {
-                Ok(val) => val,
-                Err(e) => {
-                    log::error!("Failed unpack DDS system network sample: {:?}", e);
-                    continue;
-                }
-            };
+            let vehicle = sample_data.id.clone();
             let now = match std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH) {
                 Ok(val)=> val.as_secs_f64(),
                 Err(e) => {
@@ -267,42 +333,39 @@ async fn handle_network_topic (reader: DataReaderAsync<SystemStatusNetwork>, rrd
             let mut network_usage = String::new();
             for network_info in sample_data.network_info {
                 network_usage += 
-                    format!("{}: \n\tIP Address: {:?} \n\t {} MB Sent, {} MB Received\n\tErrors: {} TX, {} RX \n ", 
-                    network_info.name, network_info.ip_address, network_info.bytes_sent/1024/1024, network_info.bytes_received/1024/1024, network_info.transmit_errors, network_info.receive_errors).as_str();
+                    format!("{}: \n\tIP Address: {:?} \n\t {} MB Sent, {} MB Received\n\t {:.1} kB/s Sent, {:.1} kB/s Received\n\tErrors: {} TX, {} RX \n ", 
+                    network_info.name, network_info.ip_address, network_info.bytes_sent/1024/1024, network_info.bytes_received/1024/1024,
+                    network_info.send_rate/1024.0, network_info.receive_rate/1024.0, network_info.transmit_errors, network_info.receive_errors).as_str();
+                if !network_info.signal.is_nan() {
+                    network_usage += format!("\tSignal: {} dBm, Noise: {} dBm, Bitrate: {} Mbit/s\n ",
+                        network_info.signal, network_info.noise, network_info.bitrate).as_str();
+                    rrd.log(format!("{}/system/network/{}/signal", vehicle, network_info.name), &rerun::Scalar::new(network_info.signal as f64)).unwrap();
+                }
+                rrd.log(format!("{}/system/network/{}/receive_rate", vehicle, network_info.name), &rerun::Scalar::new(network_info.receive_rate)).unwrap();
+                rrd.log(format!("{}/system/network/{}/send_rate", vehicle, network_info.name), &rerun::Scalar::new(network_info.send_rate)).unwrap();
+            }
+            for ping in sample_data.pings {
+                network_usage += format!("Ping {}: {:.1} ms, {:.0}% lost\n", ping.host, ping.rtt, ping.loss).as_str();
+                if !ping.rtt.is_nan() {
+                    rrd.log(format!("{}/system/ping/{}", vehicle, ping.host.replace(['/', '.'], "_")), &rerun::Scalar::new(ping.rtt as f64)).unwrap();
+                }
             }
 
-            rrd.log("system/network", &rerun::TextDocument::new(network_usage)).unwrap();
+            rrd.log(format!("{}/system/network", vehicle), &rerun::TextDocument::new(network_usage)).unwrap();
         }
     }
 }
 
 // Function to handle reading topics from dds and sending them along via mpsc channels
-async fn handle_gps_topic (reader: DataReaderAsync<GpsData>, rrd: rerun::RecordingStream) {
+async fn handle_gps_topic (reader: DataReaderAsync<GpsData>, rrd: rerun::RecordingStream, vehicles: Arc<Vehicles>,
+                           telemetry: Arc<Telemetry>) {
     loop {
-        let data = match reader.take(10, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await {
-            Ok(val) => val,
-            Err(e) => match e {
-                DdsError::NoData => {
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await; 
-                    Vec::new()
-                },
-                _ => {
-                    log::error!("Unexpected error: {:?}", e);
-                    Vec::new()
-                }
-                
+        for sample in take_samples::<GpsTopic>(&reader, 10).await {
+            let sample_data = sample.data;
+            if !vehicles.seen(&sample_data.id, &rrd) {
+                continue;
             }
-        };
-        
-        for sample in data {
-            //log::info!("{:?}", sample);
-            let sample_data =  match sample.data() {
-                Ok(val) => val,
-                Err(e) => {
-                    log::error!("Failed unpack DDS system gps sample: {:?}", e);
-                    continue;
-                }
-            };
+            let vehicle = sample_data.id.clone();
             let now = match std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH) {
                 Ok(val)=> val.as_secs_f64(),
                 Err(e) => {
@@ -312,55 +375,235 @@ async fn handle_gps_topic (reader: DataReaderAsync<GpsData>, rrd: rerun::Recordi
             };
             rrd.set_time_seconds("system_time", now);
 
-            rrd.log("gps/status", &rerun::TextDocument::new(format!("({}°, {}°, {}m)\n{} m/s, {}°\n Fix: {:?} - {} satellites",
+            rrd.log(format!("{}/gps/status", vehicle), &rerun::TextDocument::new(format!("({}°, {}°, {}m)\n{} m/s, {}°\n Fix: {:?} - {}/{} satellites\n HDOP: {:.2}, VDOP: {:.2}\n Error: ±{:.1}m horizontal, ±{:.1}m vertical\n GPS Time: {:.3}",
                                     sample_data.latitude, sample_data.longitude, sample_data.altitude, sample_data.velocity, 
-                                    sample_data.direction, sample_data.fix, sample_data.good_satellites))).unwrap();
-            rrd.log("gps/position", &rerun::GeoPoints::from_lat_lon([(sample_data.latitude, sample_data.longitude)])).unwrap();
+                                    sample_data.direction, sample_data.fix, sample_data.good_satellites, sample_data.visible_satellites,
+                                    sample_data.hdop, sample_data.vdop, sample_data.horizontal_error, sample_data.vertical_error, sample_data.time))).unwrap();
+            rrd.log(format!("{}/gps/hdop", vehicle), &rerun::Scalar::new(sample_data.hdop as f64)).unwrap();
+            rrd.log(format!("{}/gps/horizontal_error", vehicle), &rerun::Scalar::new(sample_data.horizontal_error as f64)).unwrap();
+            rrd.log(format!("{}/gps/vertical_error", vehicle), &rerun::Scalar::new(sample_data.vertical_error as f64)).unwrap();
+            rrd.log(format!("{}/gps/position", vehicle), &rerun::GeoPoints::from_lat_lon([(sample_data.latitude, sample_data.longitude)])).unwrap();
+
+            if sample_data.latitude.is_nan() || sample_data.longitude.is_nan() {
+                continue;
+            }
+            let position = GeoPoint::new(sample_data.latitude, sample_data.longitude);
+            // The same track goes to the map of the UI.
+            if let Some(points) = telemetry.add_to_track(&vehicle, &position) {
+                rrd.log(format!("{}/gps/track", vehicle), &rerun::GeoLineStrings::from_lat_lon([points])
+                    .with_colors([rerun::Color::from_rgb(57, 108, 216)])).unwrap();
+            }
+            if sample_data.velocity >= HEADING_MIN_SPEED && !sample_data.direction.is_nan() {
+                let length = (sample_data.velocity as f64 * HEADING_ARROW_TIME).max(HEADING_ARROW_MIN_LENGTH);
+                let ahead = position.offset(length, sample_data.direction as f64);
+                rrd.log(format!("{}/gps/heading", vehicle), &rerun::GeoLineStrings::from_lat_lon([[
+                    [position.latitude, position.longitude], [ahead.latitude, ahead.longitude]
+                ]]).with_colors([rerun::Color::from_rgb(216, 57, 57)])).unwrap();
+            } else {
+                rrd.log(format!("{}/gps/heading", vehicle), &rerun::Clear::flat()).unwrap();
+            }
         }
     }
 }
 
 // Function to handle reading topics from dds and sending them along via mpsc channels
-async fn handle_imu_topic (reader: DataReaderAsync<ImuData>, rrd: rerun::RecordingStream) {
+async fn handle_gps_satellites_topic (reader: DataReaderAsync<GpsSatellites>, rrd: rerun::RecordingStream, vehicles: Arc<Vehicles>) {
     loop {
-        let data = match reader.take(25, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await {
-            Ok(val) => val,
-            Err(e) => match e {
-                DdsError::NoData => {
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await; 
-                    Vec::new()
-                },
-                _ => {
-                    log::error!("Unexpected error: {:?}", e);
-                    Vec::new()
-                }
-                
+        for sample in take_samples::<GpsSatellitesTopic>(&reader, 10).await {
+            let sample_data = sample.data;
+            if !vehicles.seen(&sample_data.id, &rrd) {
+                continue;
             }
-        };
-        
-        for sample in data {
-            //log::info!("{:?}", sample);
-            let sample_data =  match sample.data() {
-                Ok(val) => val,
+            let vehicle = sample_data.id.clone();
+            let now = match std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH) {
+                Ok(val)=> val.as_secs_f64(),
                 Err(e) => {
-                    log::error!("Failed unpack DDS system imu sample: {:?}", e);
-                    continue;
+                    log::error!("Failed to get system time for gps satellites update: {:?}", e);
+                    0.0
                 }
             };
+            rrd.set_time_seconds("system_time", now);
+
+            let mut snr = Vec::new();
+            let mut sky_view = String::new();
+            for satellite in sample_data.satellites {
+                snr.push(satellite.snr);
+                sky_view += format!("PRN {}: {:.0} dBHz, el {:.0}°, az {:.0}°{}\n", 
+                    satellite.prn, satellite.snr, satellite.elevation, satellite.azimuth, if satellite.used {" (used)"} else {""}).as_str();
+            }
+
+            rrd.log(format!("{}/gps/satellites/snr", vehicle), &rerun::BarChart::new(snr)).unwrap();
+            rrd.log(format!("{}/gps/satellites", vehicle), &rerun::TextDocument::new(sky_view)).unwrap();
+        }
+    }
+}
+
+// Function to handle reading topics from dds and sending them along via mpsc channels
+async fn handle_imu_topic (reader: DataReaderAsync<ImuData>, rrd: rerun::RecordingStream, vehicles: Arc<Vehicles>) {
+    loop {
+        for sample in take_samples::<ImuTopic>(&reader, 25).await {
+            let sample_data = sample.data;
+            if !vehicles.seen(&sample_data.id, &rrd) {
+                continue;
+            }
+            let vehicle = sample_data.id.clone();
             
             rrd.set_time_seconds("system_time", sample_data.time);
 
-            rrd.log("imu_accelerometer/x", &rerun::Scalar::new(sample_data.accelerometer[0] as f64)).unwrap();
-            rrd.log("imu_accelerometer/y", &rerun::Scalar::new(sample_data.accelerometer[1] as f64)).unwrap();
-            rrd.log("imu_accelerometer/z", &rerun::Scalar::new(sample_data.accelerometer[2] as f64)).unwrap();
+            rrd.log(format!("{}/imu_accelerometer/x", vehicle), &rerun::Scalar::new(sample_data.accelerometer[0] as f64)).unwrap();
+            rrd.log(format!("{}/imu_accelerometer/y", vehicle), &rerun::Scalar::new(sample_data.accelerometer[1] as f64)).unwrap();
+            rrd.log(format!("{}/imu_accelerometer/z", vehicle), &rerun::Scalar::new(sample_data.accelerometer[2] as f64)).unwrap();
 
-            rrd.log("imu_gyroscope/x", &rerun::Scalar::new(sample_data.gyroscope[0] as f64)).unwrap();
-            rrd.log("imu_gyroscope/y", &rerun::Scalar::new(sample_data.gyroscope[1] as f64)).unwrap();
-            rrd.log("imu_gyroscope/z", &rerun::Scalar::new(sample_data.gyroscope[2] as f64)).unwrap();
+            rrd.log(format!("{}/imu_gyroscope/x", vehicle), &rerun::Scalar::new(sample_data.gyroscope[0] as f64)).unwrap();
+            rrd.log(format!("{}/imu_gyroscope/y", vehicle), &rerun::Scalar::new(sample_data.gyroscope[1] as f64)).unwrap();
+            rrd.log(format!("{}/imu_gyroscope/z", vehicle), &rerun::Scalar::new(sample_data.gyroscope[2] as f64)).unwrap();
             
-            rrd.log("imu_magnetometer/x", &rerun::Scalar::new(sample_data.magnetometer[0] as f64)).unwrap();
-            rrd.log("imu_magnetometer/y", &rerun::Scalar::new(sample_data.magnetometer[1] as f64)).unwrap();
-            rrd.log("imu_magnetometer/z", &rerun::Scalar::new(sample_data.magnetometer[2] as f64)).unwrap();
+            rrd.log(format!("{}/imu_magnetometer/x", vehicle), &rerun::Scalar::new(sample_data.magnetometer[0] as f64)).unwrap();
+            rrd.log(format!("{}/imu_magnetometer/y", vehicle), &rerun::Scalar::new(sample_data.magnetometer[1] as f64)).unwrap();
+            rrd.log(format!("{}/imu_magnetometer/z", vehicle), &rerun::Scalar::new(sample_data.magnetometer[2] as f64)).unwrap();
         }
     }
-}
\ No newline at end of file
+}
+
+// Alarms go into a text log per vehicle, raised ones at their severity and cleared ones as info.
+async fn handle_alarm_topic (reader: DataReaderAsync<Alarm>, rrd: rerun::RecordingStream, vehicles: Arc<Vehicles>) {
+    loop {
+        for sample in take_samples::<AlarmTopic>(&reader, 10).await {
+            let sample_data = sample.data;
+            if !vehicles.seen(&sample_data.id, &rrd) {
+                continue;
+            }
+            let vehicle = sample_data.id.clone();
+            rrd.set_time_seconds("system_time", sample_data.time);
+
+            let level = match (sample_data.raised, sample_data.severity) {
+                (false, _) | (true, AlarmSeverity::Info) => rerun::TextLogLevel::INFO,
+                (true, AlarmSeverity::Warning) => rerun::TextLogLevel::WARN,
+                (true, AlarmSeverity::Critical) => rerun::TextLogLevel::ERROR,
+            };
+            let text = if sample_data.raised {
+                format!("Raised {}", sample_data.message)
+            } else {
+                format!("Cleared {}", sample_data.message)
+            };
+            rrd.log(format!("{}/alarms", vehicle), &rerun::TextLog::new(text).with_level(level)).unwrap();
+        }
+    }
+}
+
+/// Sent to the UI when the RC override of a vehicle is switched on or off.
+#[derive(Serialize, Clone)]
+struct RcOverride {
+    vehicle: String,
+    active: bool,
+}
+
+// Function to handle reading topics from dds and sending them along via rerun
+async fn handle_micro_status_topic (reader: DataReaderAsync<MicroStatus>, rrd: rerun::RecordingStream, vehicles: Arc<Vehicles>,
+                                    app: tauri::AppHandle) {
+    let mut overridden: HashMap<String, bool> = HashMap::new();
+    loop {
+        for sample in take_samples::<MicroStatusTopic>(&reader, 10).await {
+            let sample_data = sample.data;
+            if !vehicles.seen(&sample_data.id, &rrd) {
+                continue;
+            }
+            let vehicle = sample_data.id.clone();
+            rrd.set_time_seconds("system_time", sample_data.time);
+
+            let on_off = |val: bool| if val { "on" } else { "off" };
+            let scalar = |val: bool| rerun::Scalar::new(if val { 1.0 } else { 0.0 });
+            rrd.log(format!("{}/microcontroller/throttle/port", vehicle), &rerun::Scalar::new(sample_data.port_throttle as f64)).unwrap();
+            rrd.log(format!("{}/microcontroller/throttle/starboard", vehicle), &rerun::Scalar::new(sample_data.starboard_throttle as f64)).unwrap();
+            rrd.log(format!("{}/microcontroller/lights/port", vehicle), &scalar(sample_data.port_light)).unwrap();
+            rrd.log(format!("{}/microcontroller/lights/starboard", vehicle), &scalar(sample_data.starboard_light)).unwrap();
+            rrd.log(format!("{}/microcontroller/power/port", vehicle), &scalar(sample_data.port_power)).unwrap();
+            rrd.log(format!("{}/microcontroller/power/starboard", vehicle), &scalar(sample_data.starboard_power)).unwrap();
+            rrd.log(format!("{}/microcontroller/rc/throttle", vehicle), &rerun::Scalar::new(sample_data.rc_throttle as f64)).unwrap();
+            rrd.log(format!("{}/microcontroller/rc/turn", vehicle), &rerun::Scalar::new(sample_data.rc_turn as f64)).unwrap();
+            rrd.log(format!("{}/microcontroller/rc/switch", vehicle), &rerun::Scalar::new(sample_data.rc_switch as f64)).unwrap();
+            rrd.log(format!("{}/microcontroller/rc/overridden", vehicle), &scalar(sample_data.rc_override)).unwrap();
+
+            let mut status = String::new();
+            if sample_data.rc_override {
+                status += "# RC OVERRIDE ACTIVE\n\nThe microcontroller ignores the throttles sent from here until the switch is off.\n\n";
+            }
+            status += format!("Throttle: port {:.0}%, starboard {:.0}%\n\nNav lights: port {}, starboard {}\n\nPower: port {}, starboard {}\n\nRC: throttle {}, turn {}, switch {}\n\nDriver: {}\n",
+                sample_data.port_throttle * 100.0, sample_data.starboard_throttle * 100.0, on_off(sample_data.port_light),
+                on_off(sample_data.starboard_light), on_off(sample_data.port_power), on_off(sample_data.starboard_power),
+                sample_data.rc_throttle, sample_data.rc_turn, sample_data.rc_switch,
+                if sample_data.driver.is_empty() { "none" } else { sample_data.driver.as_str() }).as_str();
+            rrd.log(format!("{}/microcontroller", vehicle), &rerun::TextDocument::from_markdown(status)).unwrap();
+
+            // Only changes are sent to the UI, it keeps the banner up until the override is switched off.
+            let was_overridden = overridden.insert(vehicle.clone(), sample_data.rc_override).unwrap_or(false);
+            if was_overridden != sample_data.rc_override {
+                if sample_data.rc_override {
+                    log::warn!("RC override active on {}", vehicle);
+                } else {
+                    log::info!("RC override released on {}", vehicle);
+                }
+                if let Err(e) = app.emit("rc_override", RcOverride { vehicle, active: sample_data.rc_override }) {
+                    log::error!("Failed to notify the UI of the RC override: {:?}", e);
+                }
+            }
+        }
+    }
+}
+
+// Function to handle reading topics from dds and sending them along via rerun
+async fn handle_mission_topic (reader: DataReaderAsync<Mission>, rrd: rerun::RecordingStream, vehicles: Arc<Vehicles>) {
+    loop {
+        for sample in take_samples::<MissionTopic>(&reader, 10).await {
+            let sample_data = sample.data;
+            if !vehicles.seen(&sample_data.id, &rrd) {
+                continue;
+            }
+            let vehicle = sample_data.id.clone();
+            log::info!("Mission {} for {}: {} waypoints", sample_data.name, vehicle, sample_data.waypoints.len());
+
+            if sample_data.waypoints.is_empty() {
+                rrd.log(format!("{}/mission", vehicle), &rerun::Clear::recursive()).unwrap();
+                continue;
+            }
+            let points: Vec<[f64; 2]> = sample_data.waypoints.iter().map(|w| [w.latitude, w.longitude]).collect();
+            rrd.log(format!("{}/mission/waypoints", vehicle), &rerun::GeoPoints::from_lat_lon(points.iter().map(|p| (p[0], p[1])))
+                .with_radii([rerun::Radius::new_ui_points(6.0)])
+                .with_colors([rerun::Color::from_rgb(216, 160, 57)])).unwrap();
+            rrd.log(format!("{}/mission/route", vehicle), &rerun::GeoLineStrings::from_lat_lon([points])
+                .with_colors([rerun::Color::from_rgb(216, 160, 57)])).unwrap();
+            let mut text = format!("Mission {}\n", sample_data.name);
+            for (i, waypoint) in sample_data.waypoints.iter().enumerate() {
+                text += format!("{}: ({:.6}°, {:.6}°) ±{} m at {} m/s, loiter {} s\n", i + 1, waypoint.latitude, waypoint.longitude,
+                    waypoint.acceptance_radius, waypoint.speed, waypoint.loiter_time).as_str();
+            }
+            rrd.log(format!("{}/mission", vehicle), &rerun::TextDocument::new(text)).unwrap();
+        }
+    }
+}
+
+// Function to handle reading topics from dds and sending them along via rerun
+async fn handle_geofence_topic (reader: DataReaderAsync<Geofence>, rrd: rerun::RecordingStream, vehicles: Arc<Vehicles>) {
+    loop {
+        for sample in take_samples::<GeofenceTopic>(&reader, 10).await {
+            let sample_data = sample.data;
+            if !vehicles.seen(&sample_data.id, &rrd) {
+                continue;
+            }
+            let vehicle = sample_data.id.clone();
+
+            rrd.log(format!("{}/geofence", vehicle), &rerun::Clear::recursive()).unwrap();
+            for zone in sample_data.zones {
+                // Closed outline, keep in zones green and keep out zones red.
+                let mut outline: Vec<[f64; 2]> = zone.points.iter().map(|p| [p.latitude, p.longitude]).collect();
+                if let Some(&first) = outline.first() {
+                    outline.push(first);
+                }
+                let color = if zone.keep_in { rerun::Color::from_rgb(57, 216, 108) } else { rerun::Color::from_rgb(216, 57, 57) };
+                let name = zone.name.replace(['/', ' '], "_");
+                rrd.log(format!("{}/geofence/{}", vehicle, name), &rerun::GeoLineStrings::from_lat_lon([outline])
+                    .with_colors([color])).unwrap();
+            }
+        }
+    }
+}
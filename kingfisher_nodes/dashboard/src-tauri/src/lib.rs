@@ -1,20 +1,28 @@
 //! Main Tauri application stratup.
+use kingfisher_node::Node;
 use tauri::Builder;
 
-//mod system_status;
 mod dds_topics;
+mod navigation;
+mod system_status;
+mod teleop;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
+pub fn run(node: Node) {
 
     Builder::default()
         .plugin(tauri_plugin_opener::init())
-        .setup( |_app| {
-            dds_topics::setup_dds_topics();
-            //app.manage(system_status::setup_app_state());
+        .setup( move |app| {
+            dds_topics::setup_dds_topics(node, app.handle().clone());
             Ok(())
         })
-        //.invoke_handler(tauri::generate_handler![system_status::connect_dds_topics])
+        .invoke_handler(tauri::generate_handler![
+            dds_topics::list_vehicles,
+            navigation::go_here,
+            system_status::connect_dds_topics,
+            teleop::teleop_input,
+            teleop::teleop_stop
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -1,8 +1,19 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use clap::Parser;
+use kingfisher_node::{Node, NodeArgs};
+
+/// Parser for command line parameters
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct CommandLineParameters {
+    #[command(flatten)]
+    node: NodeArgs,
+}
+
 fn main() {
-    env_logger::init();
-    log::info!("Starting Kingfisher Dashboard");
-    dashboard_lib::run()
+    let cli = CommandLineParameters::parse();
+    let node = Node::init("dashboard", env!("CARGO_PKG_VERSION"), &cli.node, "./dashboard.toml");
+    dashboard_lib::run(node)
 }
@@ -0,0 +1,49 @@
+//! Navigation requests from the map, sent to the mission node of a vehicle.
+use crate::teleop;
+use dust_dds::dds_async::data_writer::DataWriterAsync;
+use kingfisher_data_types::dds_topics::GoTo;
+use kingfisher_data_types::topic_registry::{GoToTopic, Topic};
+use tokio::sync::mpsc;
+
+pub struct Navigation {
+    /// Identifies this dashboard to the mission node
+    source: String,
+    requests: mpsc::Sender<GoTo>,
+}
+
+impl Navigation {
+    /// The state for the commands, and the receiver of the requests to pass to `run`.
+    pub fn new() -> (Self, mpsc::Receiver<GoTo>) {
+        let (requests, receiver) = mpsc::channel(8);
+        (Navigation { source: teleop::source_name(), requests }, receiver)
+    }
+}
+
+/// Send a vehicle straight to a position, replacing its mission.
+#[tauri::command]
+pub fn go_here(vehicle: String, latitude: f64, longitude: f64, navigation: tauri::State<Navigation>) -> Result<(), String> {
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        return Err(format!("Invalid position ({}, {})", latitude, longitude));
+    }
+    let request = GoTo {
+        id: vehicle,
+        source: navigation.source.clone(),
+        latitude,
+        longitude,
+        time: match std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH) {
+            Ok(val) => val.as_secs_f64(),
+            Err(_) => 0.0
+        },
+    };
+    log::info!("Sending {} to ({:.6}, {:.6})", request.id, latitude, longitude);
+    navigation.requests.try_send(request).map_err(|e| format!("Failed to queue the request: {}", e))
+}
+
+/// Publish the requests.
+pub async fn run(mut requests: mpsc::Receiver<GoTo>, writer: DataWriterAsync<GoTo>) {
+    while let Some(request) = requests.recv().await {
+        if let Err(e) = GoToTopic::write(&writer, &request).await {
+            log::error!("Failed to send the go to request: {:?}", e);
+        }
+    }
+}
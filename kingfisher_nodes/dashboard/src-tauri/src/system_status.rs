@@ -1,93 +1,52 @@
-use tauri::{AppHandle, ipc::Channel};
-use serde::Serialize;
-use tokio::sync::mpsc;
+//! Telemetry for the native status widgets, sent to the UI over a Tauri channel.
+//!
+//! The rerun view gets every sample, while the widgets only need the latest values a few times a second. Updates are
+//! rate limited per topic and vehicle, holding the newest sample back until its turn comes. Alarms are never held
+//! back, nor are missions and geofences. A UI that (re)connects first gets the last update of everything.
+//!
+//! The decimated GPS track of each vehicle is kept here, for both the rerun view and the map of the UI.
 use dust_dds::{
-    dds_async::{domain_participant_factory::DomainParticipantFactoryAsync, data_reader::DataReaderAsync}, 
-    infrastructure::{error::DdsError, qos::QosKind, status::NO_STATUS}, 
-    subscription::sample_info::{ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE}, 
+    dds_async::{data_reader::DataReaderAsync, domain_participant::DomainParticipantAsync, subscriber::SubscriberAsync},
+    infrastructure::error::DdsResult,
 };
 use kingfisher_data_types::dds_topics::{
-    GPS_TOPIC, SYSTEM_STATUS_CPU_TOPIC, SYSTEM_STATUS_DISK_TOPIC, 
-    SYSTEM_STATUS_MEMORY_TOPIC, SYSTEM_STATUS_NETWORK_TOPIC,
-    SystemStatusMemory, SystemStatusCpu, SystemStatusNetwork, 
-    SystemStatusDisk, GpsData
+    Alarm, GeoPoint, Geofence, GpsData, ImuData, MicroStatus, Mission, SystemStatusCpu, SystemStatusDisk,
+    SystemStatusMemory, SystemStatusNetwork, SystemStatusProcesses, SystemStatusThermal
 };
-use tauri::Manager;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-
-use rerun;
-
+use kingfisher_data_types::topic_registry::{
+    subscribe, take_samples, AlarmTopic, GeofenceTopic, GpsTopic, ImuTopic, MicroStatusTopic, MissionTopic,
+    SystemStatusCpuTopic, SystemStatusDiskTopic, SystemStatusMemoryTopic, SystemStatusNetworkTopic,
+    SystemStatusProcessesTopic, SystemStatusThermalTopic, Topic
+};
+use kingfisher_node::VehicleFilter;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::ipc::Channel;
 
-// Structure to store all the dds topic receivers, to be used as part of the application state.
-pub struct DdsTopicReceivers {
-    
-    rx_sys_mem: Arc<Mutex<mpsc::Receiver<SystemStatusMemory>>>,
-    rx_sys_cpu: Arc<Mutex<mpsc::Receiver<SystemStatusCpu>>>,
-    rx_sys_disk: Arc<Mutex<mpsc::Receiver<SystemStatusDisk>>>,
-    rx_sys_network: Arc<Mutex<mpsc::Receiver<SystemStatusNetwork>>>,
-    rx_gps: Arc<Mutex<mpsc::Receiver<GpsData>>>
-}
+/// Least time between two updates of a topic for one vehicle
+const MIN_UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+/// How often the held back updates are checked
+const FLUSH_PERIOD: Duration = Duration::from_millis(100);
+/// Most samples taken from a reader at once
+const MAX_SAMPLES: i32 = 25;
+/// Most points kept in the track of a vehicle, the track is thinned out past it
+const MAX_TRACK_POINTS: usize = 2000;
+/// A fix closer than this to the last point of the track isn't added to it (m)
+const TRACK_MIN_DISTANCE: f64 = 2.0;
 
-//Sets up the App State
-pub fn setup_app_state() -> DdsTopicReceivers {
-
-    let rrd = rerun::RecordingStreamBuilder::new("kingfisher")
-                .serve_web("0.0.0.0", Default::default(),
-                Default::default(),
-                rerun::MemoryLimit::from_fraction_of_total(0.25),
-                false).unwrap();
-
-    
-
-    let (tx_sys_mem, rx_sys_mem) = mpsc::channel::<SystemStatusMemory>(1);
-    let (tx_sys_cpu, rx_sys_cpu) = mpsc::channel::<SystemStatusCpu>(1);
-    let (tx_sys_disk, rx_sys_disk) = mpsc::channel::<SystemStatusDisk>(1);
-    let (tx_sys_network, rx_sys_network) = mpsc::channel::<SystemStatusNetwork>(1);
-    let (tx_gps, rx_gps) = mpsc::channel::<GpsData>(1);
-    
-    tauri::async_runtime::spawn(async move {
-        //Setting up DDS
-        let domain_id = kingfisher_data_types::DEFAULT_DOMAIN;
-        let participant_factory = DomainParticipantFactoryAsync::new();
-        
-        let participant = participant_factory
-        .create_participant(domain_id, QosKind::Default, None, NO_STATUS)
-        .await
-        .unwrap();
-        
-        //Topics
-        //System Status topics
-        let topic_memory = participant
-        .create_topic::<SystemStatusMemory>(SYSTEM_STATUS_MEMORY_TOPIC, "SystemStatusMemory", QosKind::Default, None, NO_STATUS)
-        .await
-        .unwrap();
-        
-        let subscriber = participant
-        .create_subscriber(QosKind::Default, None, NO_STATUS)
-        .await
-        .unwrap();
-        
-        let reader_memory = subscriber.create_datareader::<SystemStatusMemory>(&topic_memory, QosKind::Default, None, NO_STATUS).await.unwrap();
-        
-        tokio::spawn (async move {
-            handle_memory_topic(reader_memory, tx_sys_mem).await;
-        });
-    });
-    
-    DdsTopicReceivers {
-        rx_sys_mem: Arc::new(Mutex::new(rx_sys_mem)),
-        rx_sys_cpu: Arc::new(Mutex::new(rx_sys_cpu)),
-        rx_sys_disk: Arc::new(Mutex::new(rx_sys_disk)),
-        rx_sys_network: Arc::new(Mutex::new(rx_sys_network)),
-        rx_gps: Arc::new(Mutex::new(rx_gps))
-    }
+/// The track of a vehicle, for the map.
+#[derive(Clone, Serialize)]
+pub struct VehicleTrack {
+    pub id: String,
+    /// Latitude and longitude of each point, oldest first
+    pub points: Vec<[f64; 2]>,
 }
 
 #[derive(Clone, Serialize)]
 #[serde(rename_all = "camelCase", tag = "event", content = "data")]
 pub enum DataUpdate {
-    #[serde(rename_all = "camelCase")]
     DataMemory {
         data: SystemStatusMemory,
     },
@@ -100,70 +59,261 @@ pub enum DataUpdate {
     DataNetwork {
         data: SystemStatusNetwork,
     },
+    DataThermal {
+        data: SystemStatusThermal,
+    },
+    DataProcesses {
+        data: SystemStatusProcesses,
+    },
     DataGps {
         data: GpsData,
+    },
+    DataImu {
+        data: ImuData,
+    },
+    DataMicroStatus {
+        data: MicroStatus,
+    },
+    DataAlarm {
+        data: Alarm,
+    },
+    DataMission {
+        data: Mission,
+    },
+    DataGeofence {
+        data: Geofence,
+    },
+    DataTrack {
+        data: VehicleTrack,
+    },
+}
+
+impl DataUpdate {
+    /// Instance the update is for within its topic, the vehicle except for alarms.
+    fn instance(&self) -> String {
+        match self {
+            DataUpdate::DataMemory { data } => data.id.clone(),
+            DataUpdate::DataDisk { data } => data.id.clone(),
+            DataUpdate::DataCpu { data } => data.id.clone(),
+            DataUpdate::DataNetwork { data } => data.id.clone(),
+            DataUpdate::DataThermal { data } => data.id.clone(),
+            DataUpdate::DataProcesses { data } => data.id.clone(),
+            DataUpdate::DataGps { data } => data.id.clone(),
+            DataUpdate::DataImu { data } => data.id.clone(),
+            DataUpdate::DataMicroStatus { data } => data.id.clone(),
+            DataUpdate::DataAlarm { data } => format!("{}/{}", data.id, data.name),
+            DataUpdate::DataMission { data } => data.id.clone(),
+            DataUpdate::DataGeofence { data } => data.id.clone(),
+            DataUpdate::DataTrack { data } => data.id.clone(),
+        }
+    }
+
+    /// Alarms, missions and geofences change rarely and every change matters.
+    fn rate_limited(&self) -> bool {
+        !matches!(self, DataUpdate::DataAlarm { .. } | DataUpdate::DataMission { .. } | DataUpdate::DataGeofence { .. })
+    }
+}
+
+/// Topic name and instance of an update.
+type Key = (&'static str, String);
+
+/// Decimated history of the positions of a vehicle.
+#[derive(Default)]
+struct Track {
+    /// Latitude and longitude of each point
+    points: Vec<[f64; 2]>,
+}
+
+impl Track {
+    /// Returns false if the position was too close to the last point to be added.
+    fn add(&mut self, position: &GeoPoint) -> bool {
+        if let Some(&[latitude, longitude]) = self.points.last() {
+            if GeoPoint::new(latitude, longitude).distance_to(position) < TRACK_MIN_DISTANCE {
+                return false;
+            }
+        }
+        self.points.push([position.latitude, position.longitude]);
+        if self.points.len() > MAX_TRACK_POINTS {
+            // Drop every other point but the newest, so the older parts of the track get coarser every time.
+            let newest = self.points.len() - 1;
+            let mut index = 0;
+            self.points.retain(|_| {
+                index += 1;
+                index % 2 == 1 || index - 1 == newest
+            });
+        }
+        true
+    }
+}
+
+#[derive(Default)]
+struct Limiter {
+    /// Last update sent of each instance and when, replayed to a UI that connects
+    sent: HashMap<Key, (Instant, DataUpdate)>,
+    /// Newest update of each instance held back by the rate limit
+    pending: HashMap<Key, DataUpdate>,
+}
+
+impl Limiter {
+    fn due(&self, key: &Key) -> bool {
+        match self.sent.get(key) {
+            Some((time, _)) => time.elapsed() >= MIN_UPDATE_INTERVAL,
+            None => true,
+        }
     }
 }
 
+/// Forwards the telemetry to the UI, if one is connected.
+#[derive(Default)]
+pub struct Telemetry {
+    channel: Mutex<Option<Channel<DataUpdate>>>,
+    limiter: Mutex<Limiter>,
+    tracks: Mutex<HashMap<String, Track>>,
+}
+
+impl Telemetry {
+    /// Start sending to a new channel, replacing the previous one, e.g. after the page was reloaded.
+    fn connect(&self, channel: Channel<DataUpdate>) {
+        // Holding the limiter, no update can slip in between the replay and the switch.
+        let limiter = self.limiter.lock().unwrap();
+        for (_, update) in limiter.sent.values() {
+            if let Err(e) = channel.send(update.clone()) {
+                log::error!("Failed to send telemetry to the UI: {:?}", e);
+                return;
+            }
+        }
+        *self.channel.lock().unwrap() = Some(channel);
+        log::info!("UI connected to the telemetry.");
+    }
+
+    /// Send an update now, or hold it back if its instance was updated too recently.
+    fn offer(&self, topic: &'static str, update: DataUpdate) {
+        let key = (topic, update.instance());
+        {
+            let mut limiter = self.limiter.lock().unwrap();
+            if update.rate_limited() && !limiter.due(&key) {
+                limiter.pending.insert(key, update);
+                return;
+            }
+            limiter.pending.remove(&key);
+            limiter.sent.insert(key, (Instant::now(), update.clone()));
+        }
+        self.send(update);
+    }
+
+    /// Add a position to the track of a vehicle and send the track on. Returns the points of the track, None if the
+    /// position was too close to the last point to change it.
+    pub fn add_to_track(&self, vehicle: &str, position: &GeoPoint) -> Option<Vec<[f64; 2]>> {
+        let points = {
+            let mut tracks = self.tracks.lock().unwrap();
+            let track = tracks.entry(vehicle.to_string()).or_default();
+            if !track.add(position) {
+                return None;
+            }
+            track.points.clone()
+        };
+        self.offer("track", DataUpdate::DataTrack {
+            data: VehicleTrack { id: vehicle.to_string(), points: points.clone() },
+        });
+        Some(points)
+    }
+
+    /// Send the held back updates whose turn has come.
+    fn flush(&self) {
+        let due: Vec<DataUpdate> = {
+            let mut limiter = self.limiter.lock().unwrap();
+            let keys: Vec<Key> = limiter.pending.keys().filter(|key| limiter.due(key)).cloned().collect();
+            let mut due = Vec::new();
+            for key in keys {
+                if let Some(update) = limiter.pending.remove(&key) {
+                    limiter.sent.insert(key, (Instant::now(), update.clone()));
+                    due.push(update);
+                }
+            }
+            due
+        };
+        for update in due {
+            self.send(update);
+        }
+    }
+
+    fn send(&self, update: DataUpdate) {
+        let mut channel = self.channel.lock().unwrap();
+        if let Some(val) = channel.as_ref() {
+            if let Err(e) = val.send(update) {
+                // The page went away, it connects again when it's back.
+                log::warn!("The UI stopped listening to the telemetry: {:?}", e);
+                *channel = None;
+            }
+        }
+    }
+}
+
+/// Stream the telemetry to the UI. Calling it again replaces the previous channel.
 #[tauri::command]
-pub fn connect_dds_topics(app: AppHandle, on_event: Channel<DataUpdate>) {
-    
-    let data = app.state::<DdsTopicReceivers>();
-    
-    //clone the receiver.
-    let rx_sys_mem = data.rx_sys_mem.clone();
-
-    
-    tauri::async_runtime::spawn(async move {
+pub fn connect_dds_topics(on_event: Channel<DataUpdate>, telemetry: tauri::State<Arc<Telemetry>>) {
+    telemetry.connect(on_event);
+}
+
+/// Subscribe to the topics shown by the widgets and start forwarding them.
+pub async fn setup_telemetry(participant: &DomainParticipantAsync, subscriber: &SubscriberAsync,
+                             telemetry: Arc<Telemetry>, vehicles: VehicleFilter) -> DdsResult<()> {
+    let reader_memory = subscribe::<SystemStatusMemoryTopic>(participant, subscriber).await?;
+    let reader_disk = subscribe::<SystemStatusDiskTopic>(participant, subscriber).await?;
+    let reader_cpu = subscribe::<SystemStatusCpuTopic>(participant, subscriber).await?;
+    let reader_network = subscribe::<SystemStatusNetworkTopic>(participant, subscriber).await?;
+    let reader_thermal = subscribe::<SystemStatusThermalTopic>(participant, subscriber).await?;
+    let reader_processes = subscribe::<SystemStatusProcessesTopic>(participant, subscriber).await?;
+    let reader_gps = subscribe::<GpsTopic>(participant, subscriber).await?;
+    let reader_imu = subscribe::<ImuTopic>(participant, subscriber).await?;
+    let reader_micro_status = subscribe::<MicroStatusTopic>(participant, subscriber).await?;
+    let reader_alarm = subscribe::<AlarmTopic>(participant, subscriber).await?;
+    let reader_mission = subscribe::<MissionTopic>(participant, subscriber).await?;
+    let reader_geofence = subscribe::<GeofenceTopic>(participant, subscriber).await?;
+
+    tokio::spawn(forward::<SystemStatusMemoryTopic>(reader_memory, telemetry.clone(), vehicles.clone(),
+                                                    |data| DataUpdate::DataMemory { data }));
+    tokio::spawn(forward::<SystemStatusDiskTopic>(reader_disk, telemetry.clone(), vehicles.clone(),
+                                                  |data| DataUpdate::DataDisk { data }));
+    tokio::spawn(forward::<SystemStatusCpuTopic>(reader_cpu, telemetry.clone(), vehicles.clone(),
+                                                 |data| DataUpdate::DataCpu { data }));
+    tokio::spawn(forward::<SystemStatusNetworkTopic>(reader_network, telemetry.clone(), vehicles.clone(),
+                                                     |data| DataUpdate::DataNetwork { data }));
+    tokio::spawn(forward::<SystemStatusThermalTopic>(reader_thermal, telemetry.clone(), vehicles.clone(),
+                                                     |data| DataUpdate::DataThermal { data }));
+    tokio::spawn(forward::<SystemStatusProcessesTopic>(reader_processes, telemetry.clone(), vehicles.clone(),
+                                                       |data| DataUpdate::DataProcesses { data }));
+    tokio::spawn(forward::<GpsTopic>(reader_gps, telemetry.clone(), vehicles.clone(),
+                                     |data| DataUpdate::DataGps { data }));
+    tokio::spawn(forward::<ImuTopic>(reader_imu, telemetry.clone(), vehicles.clone(),
+                                     |data| DataUpdate::DataImu { data }));
+    tokio::spawn(forward::<MicroStatusTopic>(reader_micro_status, telemetry.clone(), vehicles.clone(),
+                                             |data| DataUpdate::DataMicroStatus { data }));
+    tokio::spawn(forward::<AlarmTopic>(reader_alarm, telemetry.clone(), vehicles.clone(),
+                                       |data| DataUpdate::DataAlarm { data }));
+    tokio::spawn(forward::<MissionTopic>(reader_mission, telemetry.clone(), vehicles.clone(),
+                                         |data| DataUpdate::DataMission { data }));
+    tokio::spawn(forward::<GeofenceTopic>(reader_geofence, telemetry.clone(), vehicles,
+                                          |data| DataUpdate::DataGeofence { data }));
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FLUSH_PERIOD);
         loop {
-            let mut sender = rx_sys_mem.lock().await;
-            match sender.recv().await {
-                Some(val) => {
-                    on_event.send(DataUpdate::DataMemory { data: val }).unwrap();
-                },
-                None => ()
-            };
+            interval.tick().await;
+            telemetry.flush();
         }
     });
+    Ok(())
 }
 
-// Topic handlers
-// Function to handle reading topics from dds and sending them along via mpsc channels
-async fn handle_memory_topic (reader: DataReaderAsync<SystemStatusMemory>, sender: mpsc::Sender<SystemStatusMemory>) {
+async fn forward<T: Topic>(reader: DataReaderAsync<T::Data>, telemetry: Arc<Telemetry>, vehicles: VehicleFilter,
+                           update: fn(T::Data) -> DataUpdate) {
     loop {
-        let data = match reader.take(10, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await {
-            Ok(val) => val,
-            Err(e) => match e {
-                DdsError::NoData => {
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await; 
-                    Vec::new()
-                },
-                _ => {
-                    log::error!("Unexpected error: {:?}", e);
-                    Vec::new()
-                }
-                
+        for sample in take_samples::<T>(&reader, MAX_SAMPLES).await {
+            if !vehicles.accepts(T::vehicle_id(&sample.data)) {
+                continue;
             }
-        };
-        
-        for sample in data {
-            //log::info!("{:?}", sample);
-            let sample_data =  match sample.data() {
-                Ok(val) => val,
-                Err(e) => {
-                    log::error!("Failed unpack DDS system memory sample: {:?}", e);
-                    continue;
-                }
-            };
-
-            //Send to existing rerun instance.
-            
-            match sender.send(sample_data).await {
-                Ok(_) => (),
-                Err(e) => {
-                    log::error!("Unexpected error sending system memory data: {:?}", e);
-                }
-            };   
+            telemetry.offer(T::NAME, update(sample.data));
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,114 @@
+//! Tele-operation of a vehicle from the dashboard.
+//!
+//! While armed the UI sends the driver's inputs many times a second. They are published on the control topic at a
+//! steady rate, with the throttles at zero unless the dead-man control is held. When the inputs stop coming, e.g. the
+//! window lost focus or the UI hung, the vehicle is disarmed. The microcontroller node stops the motors on its own
+//! when the commands stop, so a crashed dashboard can't leave them running either.
+use dust_dds::dds_async::data_writer::DataWriterAsync;
+use kingfisher_data_types::dds_topics::MicroControl;
+use kingfisher_data_types::topic_registry::{MicroControlTopic, Topic};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often commands are published while armed
+const COMMAND_PERIOD: Duration = Duration::from_millis(100);
+/// The vehicle is disarmed when the last input from the UI is older than this
+const INPUT_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Driver inputs from the tele-op panel.
+#[derive(Deserialize, Debug, Clone)]
+pub struct TeleopInput {
+    pub vehicle: String,
+    pub armed: bool,
+    /// Held by the driver, the throttles are zero without it
+    pub dead_man: bool,
+    pub nav_lights: bool,
+    pub port_power: bool,
+    pub starboard_power: bool,
+    /// From -1 (full reverse) to 1 (full ahead)
+    pub port_throttle: f32,
+    pub starboard_throttle: f32,
+}
+
+pub struct Teleop {
+    /// Identifies this dashboard to the microcontroller node
+    source: String,
+    /// Latest input and when it arrived
+    input: Mutex<Option<(TeleopInput, Instant)>>,
+}
+
+impl Default for Teleop {
+    fn default() -> Self {
+        Teleop {
+            source: source_name(),
+            input: Mutex::new(None),
+        }
+    }
+}
+
+/// Name of this dashboard in the commands it sends.
+pub fn source_name() -> String {
+    let host = std::fs::read_to_string("/etc/hostname").unwrap_or_default();
+    let host = if host.trim().is_empty() { "localhost" } else { host.trim() };
+    format!("dashboard@{}:{}", host, std::process::id())
+}
+
+/// Latest driver inputs. While armed the UI has to keep sending them, see `INPUT_TIMEOUT`.
+#[tauri::command]
+pub fn teleop_input(input: TeleopInput, teleop: tauri::State<Arc<Teleop>>) {
+    *teleop.input.lock().unwrap() = Some((input, Instant::now()));
+}
+
+/// Disarm straight away, e.g. when the window loses focus.
+#[tauri::command]
+pub fn teleop_stop(teleop: tauri::State<Arc<Teleop>>) {
+    if let Some((input, received)) = teleop.input.lock().unwrap().as_mut() {
+        input.armed = false;
+        *received = Instant::now();
+    }
+}
+
+/// Publish the inputs as control commands.
+pub async fn run(teleop: Arc<Teleop>, writer: DataWriterAsync<MicroControl>) {
+    let mut interval = tokio::time::interval(COMMAND_PERIOD);
+    loop {
+        interval.tick().await;
+        let command = {
+            let mut latest = teleop.input.lock().unwrap();
+            let (input, received) = match latest.as_ref() {
+                Some(val) => val,
+                None => continue,
+            };
+            let timed_out = received.elapsed() > INPUT_TIMEOUT;
+            if timed_out && input.armed {
+                log::warn!("Lost the tele-op inputs, disarming {}.", input.vehicle);
+            }
+            let armed = input.armed && !timed_out;
+            let driving = armed && input.dead_man;
+            let command = MicroControl {
+                id: input.vehicle.clone(),
+                source: teleop.source.clone(),
+                armed,
+                port_light: input.nav_lights,
+                starboard_light: input.nav_lights,
+                port_power: input.port_power,
+                starboard_power: input.starboard_power,
+                port_throttle: if driving { input.port_throttle } else { 0.0 },
+                starboard_throttle: if driving { input.starboard_throttle } else { 0.0 },
+                time: match std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH) {
+                    Ok(val) => val.as_secs_f64(),
+                    Err(_) => 0.0
+                },
+            };
+            // The topic is reliable, so one command is enough once disarmed.
+            if !armed {
+                *latest = None;
+            }
+            command
+        };
+        if let Err(e) = MicroControlTopic::write(&writer, &command).await {
+            log::error!("Failed to send the control command: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+// Telemetry shared by the widgets. The backend only keeps one channel, so it's connected once here.
+import { invoke, Channel } from '@tauri-apps/api/core';
+import type { DataUpdate, VehicleTelemetry } from './telemetry';
+
+// Latest telemetry by vehicle
+export const telemetry: Record<string, VehicleTelemetry> = $state({});
+// Vehicle shown by the widgets and the map
+export const selection = $state({ vehicle: "" });
+
+let connected: Promise<void> | undefined;
+
+// Start receiving the telemetry, the later calls share the first connection.
+export function connectTelemetry(): Promise<void> {
+  if (!connected) {
+    const onEvent = new Channel<DataUpdate>();
+    onEvent.onmessage = handle;
+    connected = invoke('connect_dds_topics', { onEvent });
+  }
+  return connected;
+}
+
+function handle(message: DataUpdate) {
+  const id = message.data.data.id;
+  if (!telemetry[id]) {
+    telemetry[id] = { alarms: {}, received: {}, track: [] };
+    if (!selection.vehicle) {
+      selection.vehicle = id;
+    }
+  }
+  const entry = telemetry[id];
+  switch (message.event) {
+    case "dataMemory": entry.memory = message.data.data; break;
+    case "dataDisk": entry.disk = message.data.data; break;
+    case "dataCpu": entry.cpu = message.data.data; break;
+    case "dataNetwork": entry.network = message.data.data; break;
+    case "dataThermal": entry.thermal = message.data.data; break;
+    case "dataProcesses": entry.processes = message.data.data; break;
+    case "dataImu": entry.imu = message.data.data; break;
+    case "dataMicroStatus": entry.micro = message.data.data; break;
+    case "dataMission": entry.mission = message.data.data; break;
+    case "dataGeofence": entry.geofence = message.data.data; break;
+    case "dataGps": entry.gps = message.data.data; break;
+    // The backend decimates the track, the same one the rerun view shows.
+    case "dataTrack": entry.track = message.data.data.points; break;
+    case "dataAlarm": {
+      const alarm = message.data.data;
+      if (alarm.raised) {
+        entry.alarms[alarm.name] = alarm;
+      } else {
+        delete entry.alarms[alarm.name];
+      }
+      break;
+    }
+  }
+  entry.received[message.event] = Date.now();
+}
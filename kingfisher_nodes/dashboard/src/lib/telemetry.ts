@@ -0,0 +1,88 @@
+// Types of the telemetry sent by the backend over the connect_dds_topics channel, see system_status.rs.
+// Readings the vehicle doesn't have (NaN in Rust) arrive as null.
+
+export type CpuInfo = { name: string, usage: number, frequency: number };
+export type SystemStatusCpu = {
+  id: string, cpus: CpuInfo[], load_one: number, load_five: number, load_fifteen: number, uptime: number
+};
+export type SystemStatusMemory = {
+  id: string, total_memory: number, used_memory: number, total_swap: number, used_swap: number
+};
+export type NetworkInfo = {
+  name: string, ip_address: string[], bytes_sent: number, bytes_received: number, transmit_errors: number,
+  receive_errors: number, send_rate: number, receive_rate: number, link_quality: number | null,
+  signal: number | null, noise: number | null, bitrate: number | null
+};
+export type PingInfo = { host: string, rtt: number | null, loss: number | null };
+export type SystemStatusNetwork = { id: string, network_info: NetworkInfo[], pings: PingInfo[] };
+export type DiskInfo = { name: string, bytes_used: number, bytes_available: number };
+export type SystemStatusDisk = { id: string, disk_info: DiskInfo[] };
+export type ThermalInfo = { name: string, temperature: number | null, max: number | null, critical: number | null };
+export type SystemStatusThermal = { id: string, sensors: ThermalInfo[] };
+export type ProcessInfo = {
+  name: string, running: boolean, pid: number, cpu_usage: number, memory: number, run_time: number, restarts: number
+};
+export type SystemStatusProcesses = { id: string, processes: ProcessInfo[] };
+export type GpsFix = "None" | "Fix2D" | "Fix3D" | "DGps" | "RtkFloat" | "RtkFixed" | "DeadReckoning";
+export type GpsData = {
+  id: string, time: number, latitude: number, longitude: number, altitude: number, velocity: number,
+  direction: number, fix: GpsFix, good_satellites: number, visible_satellites: number, hdop: number | null,
+  vdop: number | null, horizontal_error: number | null, vertical_error: number | null,
+  velocity_error: number | null, direction_error: number | null
+};
+export type ImuData = {
+  id: string, time: number, accelerometer: number[], gyroscope: number[], magnetometer: number[]
+};
+export type MicroStatus = {
+  id: string, port_light: boolean, starboard_light: boolean, port_power: boolean, starboard_power: boolean,
+  port_throttle: number, starboard_throttle: number, rc_override: boolean, rc_throttle: number, rc_turn: number,
+  rc_switch: number, driver: string, time: number
+};
+export type AlarmSeverity = "Info" | "Warning" | "Critical";
+export type Alarm = {
+  id: string, name: string, severity: AlarmSeverity, message: string, raised: boolean, time: number
+};
+
+export type GeoPoint = { latitude: number, longitude: number };
+export type Waypoint = {
+  latitude: number, longitude: number, acceptance_radius: number, speed: number, loiter_time: number
+};
+export type Mission = { id: string, name: string, waypoints: Waypoint[] };
+export type GeofenceZone = { name: string, keep_in: boolean, points: GeoPoint[] };
+export type Geofence = { id: string, zones: GeofenceZone[] };
+export type VehicleTrack = { id: string, points: [number, number][] };
+
+export type DataUpdate =
+  | { event: "dataMemory", data: { data: SystemStatusMemory } }
+  | { event: "dataDisk", data: { data: SystemStatusDisk } }
+  | { event: "dataCpu", data: { data: SystemStatusCpu } }
+  | { event: "dataNetwork", data: { data: SystemStatusNetwork } }
+  | { event: "dataThermal", data: { data: SystemStatusThermal } }
+  | { event: "dataProcesses", data: { data: SystemStatusProcesses } }
+  | { event: "dataGps", data: { data: GpsData } }
+  | { event: "dataImu", data: { data: ImuData } }
+  | { event: "dataMicroStatus", data: { data: MicroStatus } }
+  | { event: "dataAlarm", data: { data: Alarm } }
+  | { event: "dataMission", data: { data: Mission } }
+  | { event: "dataGeofence", data: { data: Geofence } }
+  | { event: "dataTrack", data: { data: VehicleTrack } };
+
+// Latest telemetry of one vehicle, with the time each part arrived (ms, Date.now()).
+export type VehicleTelemetry = {
+  memory?: SystemStatusMemory,
+  disk?: SystemStatusDisk,
+  cpu?: SystemStatusCpu,
+  network?: SystemStatusNetwork,
+  thermal?: SystemStatusThermal,
+  processes?: SystemStatusProcesses,
+  gps?: GpsData,
+  imu?: ImuData,
+  micro?: MicroStatus,
+  mission?: Mission,
+  geofence?: Geofence,
+  // GPS track decimated by the backend, [latitude, longitude]
+  track: [number, number][],
+  // Raised alarms by name
+  alarms: Record<string, Alarm>,
+  received: Record<string, number>,
+};
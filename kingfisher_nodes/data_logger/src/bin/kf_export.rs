@@ -0,0 +1,474 @@
+//! Exports data_logger recordings to per-topic CSV or Parquet tables and GPX/KML tracks.
+use clap::{Parser, ValueEnum};
+use data_logger::{json, log_reader};
+use kingfisher_data_types::dds_topics::{GpsData, GpsFix, GPS_TOPIC};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    Csv,
+    /// Needs the parquet feature
+    Parquet,
+    Gpx,
+    Kml,
+}
+
+/// Parser for command line parameters
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct CommandLineParameters {
+    /// Log file, session directory or save root to export
+    input: PathBuf,
+
+    /// Directory to write the exported files to
+    #[arg(short, long, default_value = "./export")]
+    output: PathBuf,
+
+    /// Output formats (comma separated)
+    #[arg(short, long, value_enum, value_delimiter = ',', default_values_t = [Format::Csv, Format::Gpx])]
+    format: Vec<Format>,
+
+    /// Skip this many seconds from the start of the recording
+    #[arg(long, default_value_t = 0.0)]
+    start: f64,
+
+    /// Stop this many seconds after the start of the recording
+    #[arg(long)]
+    end: Option<f64>,
+
+    /// Only keep every nth sample of each topic
+    #[arg(short, long, default_value_t = 1)]
+    decimate: usize,
+
+    /// Only export these topics (comma separated)
+    #[arg(short, long, value_delimiter = ',')]
+    topics: Vec<String>,
+
+    /// Only export the sessions recorded for this vehicle
+    #[arg(long = "only-vehicle")]
+    only_vehicle: Option<String>,
+}
+
+/// A flattened sample, column name and value in field order.
+type Row = Vec<(String, Value)>;
+
+/// All the rows of a topic and the union of their columns, in order of first appearance.
+#[derive(Default)]
+struct Table {
+    columns: Vec<String>,
+    rows: Vec<Row>,
+}
+
+impl Table {
+    fn push(&mut self, row: Row) {
+        for (column, _) in &row {
+            if !self.columns.contains(column) {
+                self.columns.push(column.clone());
+            }
+        }
+        self.rows.push(row);
+    }
+}
+
+fn column_name(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", prefix, name)
+    }
+}
+
+/// Flatten a json value into the columns of a row. Nested structs become dotted columns and lists become indexed
+/// columns (`satellites.0.snr`), so every sample is a single row however many lists it has.
+fn flatten(prefix: &str, value: &Value, row: &mut Row) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields {
+                flatten(&column_name(prefix, name), field, row);
+            }
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                flatten(&column_name(prefix, &index.to_string()), item, row);
+            }
+        }
+        _ => row.push((prefix.to_string(), value.clone())),
+    }
+}
+
+/// File name for a topic, without extension.
+fn table_name(topic: &str) -> String {
+    topic.replace(['/', ' '], "_")
+}
+
+fn csv_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn write_csv(path: &Path, table: &Table) -> Result<(), String> {
+    let mut writer = csv::Writer::from_path(path).map_err(|e| e.to_string())?;
+    writer.write_record(&table.columns).map_err(|e| e.to_string())?;
+    for row in &table.rows {
+        let values: HashMap<&str, &Value> = row.iter().map(|(c, v)| (c.as_str(), v)).collect();
+        let record: Vec<String> = table
+            .columns
+            .iter()
+            .map(|c| values.get(c.as_str()).map(|v| csv_value(v)).unwrap_or_default())
+            .collect();
+        writer.write_record(&record).map_err(|e| e.to_string())?;
+    }
+    writer.flush().map_err(|e| e.to_string())
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet(path: &Path, table: &Table) -> Result<(), String> {
+    use arrow_array::{ArrayRef, BooleanArray, Float64Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    let rows: Vec<HashMap<&str, &Value>> = table
+        .rows
+        .iter()
+        .map(|row| row.iter().map(|(c, v)| (c.as_str(), v)).collect())
+        .collect();
+
+    let mut fields = Vec::new();
+    let mut arrays: Vec<ArrayRef> = Vec::new();
+    for column in &table.columns {
+        let values: Vec<Option<&Value>> = rows.iter().map(|r| r.get(column.as_str()).copied()).collect();
+        let present = || values.iter().flatten().filter(|v| !v.is_null());
+
+        // Pick the narrowest type that holds every value of the column.
+        if present().all(|v| v.is_number()) {
+            fields.push(Field::new(column, DataType::Float64, true));
+            arrays.push(Arc::new(values.iter().map(|v| v.and_then(|v| v.as_f64())).collect::<Float64Array>()));
+        } else if present().all(|v| v.is_boolean()) {
+            fields.push(Field::new(column, DataType::Boolean, true));
+            arrays.push(Arc::new(values.iter().map(|v| v.and_then(|v| v.as_bool())).collect::<BooleanArray>()));
+        } else {
+            fields.push(Field::new(column, DataType::Utf8, true));
+            arrays.push(Arc::new(values.iter().map(|v| v.map(csv_value)).collect::<StringArray>()));
+        }
+    }
+
+    let batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays).map_err(|e| e.to_string())?;
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut writer = parquet::arrow::ArrowWriter::try_new(file, batch.schema(), None).map_err(|e| e.to_string())?;
+    writer.write(&batch).map_err(|e| e.to_string())?;
+    writer.close().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet(_path: &Path, _table: &Table) -> Result<(), String> {
+    Err(String::from("kf_export was built without the parquet feature"))
+}
+
+/// A usable point of the GPS track.
+struct TrackPoint {
+    time: f64,
+    latitude: f64,
+    longitude: f64,
+    altitude: f64,
+}
+
+/// The track point of a recorded GPS sample, None if it has no fix. Unknown values are recorded as null, which
+/// decode back to NaN.
+fn decode_track_point(value: &Value, log_time: f64) -> Result<Option<TrackPoint>, String> {
+    let gps: GpsData = json::from_value(value.clone()).map_err(|e| e.to_string())?;
+    Ok(track_point(&gps, log_time))
+}
+
+fn track_point(gps: &GpsData, log_time: f64) -> Option<TrackPoint> {
+    if matches!(gps.fix, GpsFix::None) || !gps.latitude.is_finite() || !gps.longitude.is_finite() {
+        return None;
+    }
+    Some(TrackPoint {
+        // Prefer the receiver's clock
+        time: if gps.time.is_finite() { gps.time } else { log_time },
+        latitude: gps.latitude,
+        longitude: gps.longitude,
+        altitude: if gps.altitude.is_finite() { gps.altitude } else { 0.0 },
+    })
+}
+
+fn rfc3339(time: f64) -> String {
+    chrono::DateTime::from_timestamp_micros((time * 1e6) as i64)
+        .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+        .unwrap_or_default()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Writes one track per vehicle, so the boats of a save root aren't joined into a single line.
+fn write_gpx(path: &Path, name: &str, tracks: &BTreeMap<String, Vec<TrackPoint>>) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(file, r#"<gpx version="1.1" creator="kf_export" xmlns="http://www.topografix.com/GPX/1/1">"#)?;
+    writeln!(file, "  <metadata>\n    <name>{}</name>\n  </metadata>", escape(name))?;
+    for (vehicle, track) in tracks {
+        writeln!(file, "  <trk>\n    <name>{}</name>\n    <trkseg>", escape(vehicle))?;
+        for point in track {
+            writeln!(
+                file,
+                r#"      <trkpt lat="{:.8}" lon="{:.8}"><ele>{:.2}</ele><time>{}</time></trkpt>"#,
+                point.latitude, point.longitude, point.altitude, rfc3339(point.time)
+            )?;
+        }
+        writeln!(file, "    </trkseg>\n  </trk>")?;
+    }
+    writeln!(file, "</gpx>")?;
+    file.flush()
+}
+
+/// Writes one placemark per vehicle, like `write_gpx`.
+fn write_kml(path: &Path, name: &str, tracks: &BTreeMap<String, Vec<TrackPoint>>) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(file, r#"<kml xmlns="http://www.opengis.net/kml/2.2">"#)?;
+    writeln!(file, "  <Document>\n    <name>{}</name>", escape(name))?;
+    for (vehicle, track) in tracks {
+        writeln!(file, "    <Placemark>\n      <name>{}</name>", escape(vehicle))?;
+        writeln!(file, "      <LineString>\n        <altitudeMode>absolute</altitudeMode>\n        <coordinates>")?;
+        for point in track {
+            writeln!(file, "          {:.8},{:.8},{:.2}", point.longitude, point.latitude, point.altitude)?;
+        }
+        writeln!(file, "        </coordinates>\n      </LineString>\n    </Placemark>")?;
+    }
+    writeln!(file, "  </Document>\n</kml>")?;
+    file.flush()
+}
+
+fn main() {
+    env_logger::init();
+    let cli = CommandLineParameters::parse();
+
+    let files = log_reader::filter_vehicle(log_reader::input_files(&cli.input), cli.only_vehicle.as_deref());
+    if files.is_empty() {
+        log::error!("No log files found in {:?}", cli.input);
+        std::process::exit(1);
+    }
+    if let Err(e) = std::fs::create_dir_all(&cli.output) {
+        log::error!("Failed to create {:?}: {:?}", cli.output, e);
+        std::process::exit(1);
+    }
+
+    let decimate = cli.decimate.max(1);
+    let mut recording_start = None;
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut tables: BTreeMap<String, Table> = BTreeMap::new();
+    let mut tracks: BTreeMap<String, Vec<TrackPoint>> = BTreeMap::new();
+    let name = cli.input.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or(String::from("kingfisher"));
+
+    let mut result = Ok(true);
+    for file in &files {
+        let vehicle = log_reader::file_vehicle(file).unwrap_or_else(|| name.clone());
+        result = log_reader::read_file(file, |message| {
+            let start = *recording_start.get_or_insert(message.log_time);
+            let relative = (message.log_time.saturating_sub(start)) as f64 / 1e9;
+            if relative < cli.start {
+                return true;
+            }
+            if cli.end.map(|end| relative > end).unwrap_or(false) {
+                return false;
+            }
+            if !cli.topics.is_empty() && !cli.topics.contains(&message.topic) {
+                return true;
+            }
+
+            let count = counts.entry(message.topic.clone()).or_insert(0);
+            *count += 1;
+            if (*count - 1) % decimate != 0 {
+                return true;
+            }
+
+            let value: Value = match serde_json::from_slice(&message.data) {
+                Ok(val) => val,
+                Err(e) => {
+                    log::error!("Failed to decode {} sample: {}", message.topic, e);
+                    return true;
+                }
+            };
+
+            let log_time = message.log_time as f64 / 1e9;
+            if message.topic == GPS_TOPIC {
+                match decode_track_point(&value, log_time) {
+                    Ok(point) => tracks.entry(vehicle.clone()).or_default().extend(point),
+                    Err(e) => log::error!("Failed to decode GPS sample: {}", e),
+                }
+            }
+
+            let mut row = vec![
+                (String::from("log_time"), Value::from(log_time)),
+                (String::from("publish_time"), Value::from(message.publish_time as f64 / 1e9)),
+            ];
+            flatten("", &value, &mut row);
+            tables.entry(message.topic.clone()).or_default().push(row);
+            true
+        });
+        if !matches!(result, Ok(true)) {
+            break;
+        }
+    }
+
+    if let Err(e) = result {
+        log::error!("Failed to read the recording: {}", e);
+        std::process::exit(1);
+    }
+
+    for (topic, table) in &tables {
+        for format in &cli.format {
+            let (extension, result) = match format {
+                Format::Csv => ("csv", write_csv as fn(&Path, &Table) -> Result<(), String>),
+                Format::Parquet => ("parquet", write_parquet as fn(&Path, &Table) -> Result<(), String>),
+                _ => continue,
+            };
+            let path = cli.output.join(format!("{}.{}", table_name(topic), extension));
+            match result(&path, table) {
+                Ok(_) => log::info!("Wrote {} rows of {} to {:?}", table.rows.len(), topic, path),
+                Err(e) => log::error!("Failed to write {:?}: {}", path, e),
+            }
+        }
+    }
+
+    let points: usize = tracks.values().map(Vec::len).sum();
+    for format in &cli.format {
+        let (path, result) = match format {
+            Format::Gpx => {
+                let path = cli.output.join("track.gpx");
+                let result = write_gpx(&path, &name, &tracks);
+                (path, result)
+            }
+            Format::Kml => {
+                let path = cli.output.join("track.kml");
+                let result = write_kml(&path, &name, &tracks);
+                (path, result)
+            }
+            _ => continue,
+        };
+        match result {
+            Ok(_) => log::info!("Wrote {} track points of {} vehicles to {:?}", points, tracks.len(), path),
+            Err(e) => log::error!("Failed to write {:?}: {:?}", path, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data_logger::log_writer::{ChannelDescription, LogRecord, LogWriter};
+
+    #[test]
+    fn fix_without_dop_makes_it_into_the_track() {
+        let fix = GpsData {
+            id: "kingfisher".to_string(),
+            time: 1_700_000_000.0,
+            latitude: 48.4284,
+            longitude: -123.3656,
+            altitude: f64::NAN,
+            velocity: 1.5,
+            direction: 90.0,
+            fix: GpsFix::Fix2D,
+            good_satellites: 5,
+            visible_satellites: 8,
+            hdop: f32::NAN,
+            vdop: f32::NAN,
+            horizontal_error: f32::NAN,
+            vertical_error: f32::NAN,
+            velocity_error: f32::NAN,
+            direction_error: f32::NAN,
+        };
+        let dir = std::env::temp_dir().join(format!("kf_export_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("kingfisher_0000.mcap");
+        let mut writer = LogWriter::create(&log, &[ChannelDescription::new::<GpsData>(GPS_TOPIC, "GpsData")]).unwrap();
+        writer.write(&LogRecord::new(GPS_TOPIC, "kingfisher", &fix, None).unwrap()).unwrap();
+        writer.finish().unwrap();
+
+        let mut track = Vec::new();
+        log_reader::read_file(&log, |message| {
+            let value: Value = serde_json::from_slice(&message.data).unwrap();
+            track.extend(decode_track_point(&value, message.log_time as f64 / 1e9).unwrap());
+            true
+        }).unwrap();
+        assert_eq!(track.len(), 1);
+        // The missing altitude falls back on 0 rather than NaN
+        assert_eq!(track[0].altitude, 0.0);
+
+        let gpx = dir.join("track.gpx");
+        write_gpx(&gpx, "test", &BTreeMap::from([(String::from("kingfisher"), track)])).unwrap();
+        let text = std::fs::read_to_string(&gpx).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(text.contains(r#"<trkpt lat="48.42840000" lon="-123.36560000"><ele>0.00</ele>"#));
+    }
+
+    #[test]
+    fn lists_of_structs_become_indexed_columns() {
+        let sample = serde_json::json!({
+            "id": "kingfisher",
+            "satellites": [{"prn": 1, "snr": 30.0}, {"prn": 7, "snr": 42.0}],
+            "disks": [{"name": "sda", "used": 0.5}],
+        });
+        let mut row = Row::new();
+        flatten("", &sample, &mut row);
+        let columns: BTreeMap<&str, &Value> = row.iter().map(|(column, value)| (column.as_str(), value)).collect();
+        assert_eq!(row.len(), 7);
+        assert_eq!(*columns["disks.0.name"], "sda");
+        assert_eq!(*columns["satellites.0.snr"], 30.0);
+        assert_eq!(*columns["satellites.1.prn"], 7);
+        assert_eq!(*columns["satellites.1.snr"], 42.0);
+    }
+
+    #[test]
+    fn each_vehicle_gets_its_own_track() {
+        let point = |latitude| TrackPoint { time: 1_700_000_000.0, latitude, longitude: -123.0, altitude: 0.0 };
+        let tracks = BTreeMap::from([
+            (String::from("kingfisher"), vec![point(48.0), point(48.1)]),
+            (String::from("heron"), vec![point(49.0)]),
+        ]);
+        let dir = std::env::temp_dir().join(format!("kf_export_vehicles_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let gpx = dir.join("track.gpx");
+        let kml = dir.join("track.kml");
+        write_gpx(&gpx, "test", &tracks).unwrap();
+        write_kml(&kml, "test", &tracks).unwrap();
+        let gpx_text = std::fs::read_to_string(&gpx).unwrap();
+        let kml_text = std::fs::read_to_string(&kml).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(gpx_text.matches("<trk>").count(), 2);
+        assert_eq!(kml_text.matches("<Placemark>").count(), 2);
+        let heron = gpx_text.find("<name>heron</name>").unwrap();
+        let kingfisher = gpx_text.find("<name>kingfisher</name>").unwrap();
+        // The heron's only point is in its own track, not between the kingfisher's
+        assert!(gpx_text[heron..kingfisher].contains(r#"lat="49.00000000""#));
+        assert!(!gpx_text[kingfisher..].contains(r#"lat="49.00000000""#));
+    }
+
+    #[test]
+    fn track_names_are_escaped() {
+        let dir = std::env::temp_dir().join(format!("kf_export_names_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let gpx = dir.join("track.gpx");
+        let kml = dir.join("track.kml");
+        let tracks = BTreeMap::from([(String::from("kingfisher"), Vec::new())]);
+        write_gpx(&gpx, "Survey <north> & back", &tracks).unwrap();
+        write_kml(&kml, "Survey <north> & back", &tracks).unwrap();
+        let gpx_text = std::fs::read_to_string(&gpx).unwrap();
+        let kml_text = std::fs::read_to_string(&kml).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        for text in [gpx_text, kml_text] {
+            assert!(text.contains("<name>Survey &lt;north&gt; &amp; back</name>"));
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! Replays data_logger recordings, republishing every sample on its original DDS topic.
+//!
+//! The samples go out under the recorded vehicle id with a replay- prefix, so the nodes of a live vehicle with that id
+//! don't steer on recorded fixes, reload recorded missions or report recorded nodes as their own. Commands and control
+//! messages, e.g. the throttles of a tele-operated drive, are left out unless asked for as well. Only with both
+//! --keep-vehicle-ids and --replay-commands could a replay drive the real vehicle.
+use clap::Parser;
+use data_logger::json;
+use data_logger::log_reader::{self, LogMessage};
+use dust_dds::{
+    domain::domain_participant::DomainParticipant,
+    infrastructure::{qos::QosKind, status::NO_STATUS},
+    publication::{data_writer::DataWriter, publisher::Publisher},
+};
+use kingfisher_data_types::for_each_topic;
+use kingfisher_data_types::qos_profiles::QosProfile;
+use kingfisher_data_types::topic_registry::{publish_blocking, Topic};
+use kingfisher_node::{Node, NodeArgs};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Parser for command line parameters
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct CommandLineParameters {
+    /// Log file, session directory or save root to replay
+    input: PathBuf,
+
+    #[command(flatten)]
+    node: NodeArgs,
+
+    /// Playback speed multiplier, 0 to publish as fast as possible
+    #[arg(short, long, default_value_t = 1.0)]
+    speed: f64,
+
+    /// Skip this many seconds from the start of the recording
+    #[arg(long, default_value_t = 0.0)]
+    start: f64,
+
+    /// Stop this many seconds after the start of the recording
+    #[arg(long)]
+    end: Option<f64>,
+
+    /// Start over once the end is reached
+    #[arg(short, long)]
+    repeat: bool,
+
+    /// Only replay these topics (comma separated)
+    #[arg(short, long, value_delimiter = ',')]
+    topics: Vec<String>,
+
+    /// Only replay the sessions recorded for this vehicle
+    #[arg(long = "only-vehicle")]
+    only_vehicle: Option<String>,
+
+    /// Also replay the command and control topics. They can arm and drive a vehicle with the recorded id!
+    #[arg(long)]
+    replay_commands: bool,
+
+    /// Prefix put in front of the recorded vehicle ids
+    #[arg(long, default_value = "replay-")]
+    vehicle_prefix: String,
+
+    /// Replay under the recorded vehicle ids. The nodes of a live vehicle with one of them act on the samples!
+    #[arg(long, conflicts_with = "vehicle_prefix")]
+    keep_vehicle_ids: bool,
+}
+
+/// A DDS writer that takes recorded samples.
+trait ReplayWriter {
+    /// Decode a recorded sample and write it on its topic, with the prefix in front of its vehicle id.
+    fn publish(&self, message: &LogMessage, vehicle_prefix: &str) -> Result<(), String>;
+}
+
+struct TopicWriter<T: Topic> {
+    writer: DataWriter<T::Data>,
+}
+
+impl<T: Topic> ReplayWriter for TopicWriter<T> {
+    fn publish(&self, message: &LogMessage, vehicle_prefix: &str) -> Result<(), String> {
+        let mut sample: T::Data = json::from_slice(&message.data).map_err(|e| e.to_string())?;
+        if !vehicle_prefix.is_empty() {
+            let id = format!("{}{}", vehicle_prefix, T::vehicle_id(&sample));
+            T::set_vehicle_id(&mut sample, id);
+        }
+        T::write_blocking(&self.writer, &sample).map_err(|e| format!("{:?}", e))
+    }
+}
+
+/// Whether samples of a topic act on a vehicle.
+fn is_command(profile: QosProfile) -> bool {
+    matches!(profile, QosProfile::Command | QosProfile::Control)
+}
+
+/// Declares a function creating a writer for every registered topic, keyed by topic name. The command and control
+/// topics get None instead, unless `commands` is set.
+macro_rules! replay_writers {
+    ($($topic:ty),*) => {
+        fn replay_writers(participant: &DomainParticipant, publisher: &Publisher, commands: bool)
+                          -> HashMap<&'static str, Option<Box<dyn ReplayWriter>>> {
+            let mut writers: HashMap<&'static str, Option<Box<dyn ReplayWriter>>> = HashMap::new();
+            $(
+                if commands || !is_command(<$topic as Topic>::PROFILE) {
+                    let writer = publish_blocking::<$topic>(participant, publisher).unwrap();
+                    writers.insert(<$topic as Topic>::NAME, Some(Box::new(TopicWriter::<$topic> { writer })));
+                } else {
+                    writers.insert(<$topic as Topic>::NAME, None);
+                }
+            )*
+            writers
+        }
+    };
+}
+
+for_each_topic!(replay_writers);
+
+/// Time of the first message in the recording (ns).
+fn first_log_time(files: &[PathBuf]) -> Option<u64> {
+    let mut first = None;
+    for file in files {
+        let _ = log_reader::read_file(file, |message| {
+            first = Some(message.log_time);
+            false
+        });
+        if first.is_some() {
+            break;
+        }
+    }
+    first
+}
+
+fn main() {
+    let cli = CommandLineParameters::parse();
+    let node = Node::init("kf_replay", env!("CARGO_PKG_VERSION"), &cli.node, "./kf_replay.toml");
+
+    let files = log_reader::filter_vehicle(log_reader::input_files(&cli.input), cli.only_vehicle.as_deref());
+    if files.is_empty() {
+        log::error!("No log files found in {:?}", cli.input);
+        std::process::exit(1);
+    }
+    log::info!("Replaying {} log files on domain {}.", files.len(), node.domain());
+
+    // The files are replayed in path order, so the sessions of different vehicles would play one after the other.
+    let vehicles: HashSet<String> = files.iter().filter_map(|f| log_reader::file_vehicle(f)).collect();
+    if vehicles.len() > 1 {
+        log::warn!("The recording holds {} vehicles, which will be replayed one after the other. \
+            Run one kf_replay per vehicle with --only-vehicle to replay them together.", vehicles.len());
+    }
+
+    let recording_start = match first_log_time(&files) {
+        Some(val) => val,
+        None => {
+            log::error!("The recording is empty.");
+            std::process::exit(1);
+        }
+    };
+    let seek_time = recording_start + (cli.start * 1e9) as u64;
+    let end_time = cli.end.map(|end| recording_start + (end * 1e9) as u64);
+    let topics: HashSet<String> = cli.topics.iter().cloned().collect();
+
+    let participant = node.participant();
+    let publisher = participant
+        .create_publisher(QosKind::Default, None, NO_STATUS)
+        .unwrap();
+    let writers = replay_writers(&participant, &publisher, cli.replay_commands);
+    let mut held_back: Vec<&str> = writers.iter().filter(|(_, w)| w.is_none()).map(|(name, _)| *name).collect();
+    if !held_back.is_empty() {
+        held_back.sort();
+        log::info!("Not replaying the command and control topics {}, use --replay-commands to replay them.",
+                   held_back.join(", "));
+    }
+
+    let vehicle_prefix = if cli.keep_vehicle_ids { "" } else { cli.vehicle_prefix.as_str() };
+    if vehicle_prefix.is_empty() {
+        log::warn!("Replaying under the recorded vehicle ids, the nodes of a live vehicle with one of them will act on \
+            the samples.");
+    } else {
+        log::info!("Replaying as vehicles {}<recorded id>, use --keep-vehicle-ids to keep the recorded ones.",
+                   vehicle_prefix);
+    }
+
+    // Give the other participants a moment to discover the writers so the first samples aren't lost.
+    node.sleep(Duration::from_secs(1));
+    node.ready();
+
+    loop {
+        let playback_start = Instant::now();
+        let mut skipped_topics = HashSet::new();
+        let mut published = 0u64;
+
+        let result = log_reader::read_files(&files, |message| {
+            if !node.running() {
+                return false;
+            }
+            if message.log_time < seek_time {
+                return true;
+            }
+            if end_time.map(|end| message.log_time > end).unwrap_or(false) {
+                return false;
+            }
+            if !topics.is_empty() && !topics.contains(&message.topic) {
+                return true;
+            }
+
+            if cli.speed > 0.0 {
+                let offset = Duration::from_nanos(((message.log_time - seek_time) as f64 / cli.speed) as u64);
+                let elapsed = playback_start.elapsed();
+                if offset > elapsed {
+                    node.sleep(offset - elapsed);
+                }
+            }
+
+            let result = match writers.get(message.topic.as_str()) {
+                Some(Some(writer)) => writer.publish(&message, vehicle_prefix),
+                Some(None) => return true,
+                None => Err(format!("Topic {} can't be replayed", message.topic)),
+            };
+            match result {
+                Ok(_) => published += 1,
+                Err(e) => {
+                    // Only complain once per topic
+                    if skipped_topics.insert(message.topic.clone()) {
+                        log::warn!("Skipping {}: {}", message.topic, e);
+                    }
+                }
+            }
+            true
+        });
+
+        if let Err(e) = result {
+            log::error!("Failed to read the recording: {}", e);
+            std::process::exit(1);
+        }
+        log::info!("Replayed {} samples in {:.1} s.", published, playback_start.elapsed().as_secs_f64());
+
+        if !cli.repeat || !node.running() {
+            break;
+        }
+    }
+
+    node.close(participant);
+}
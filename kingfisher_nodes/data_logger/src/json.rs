@@ -0,0 +1,228 @@
+//! Decoding of the recorded json samples.
+//!
+//! json has no NaN, so serde_json writes NaN floats as null and then refuses to read the null back into a float.
+//! Many fields use NaN for "unknown", e.g. the DOP of a GPS fix before the first sky report. These functions decode
+//! like serde_json, but read a null float as NaN.
+use serde::de::value::{MapDeserializer, SeqDeserializer, StringDeserializer};
+use serde::de::{
+    DeserializeOwned, DeserializeSeed, Deserializer, EnumAccess, Error as _, IntoDeserializer, VariantAccess, Visitor
+};
+use serde::forward_to_deserialize_any;
+use serde_json::{Error, Value};
+
+/// Decode a json sample, reading null floats as NaN.
+pub fn from_slice<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error> {
+    from_value(serde_json::from_slice(data)?)
+}
+
+/// Decode a parsed json sample, reading null floats as NaN.
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, Error> {
+    T::deserialize(Sample(value))
+}
+
+/// A json value that reads as NaN where a float is expected and it's null.
+struct Sample(Value);
+
+impl<'de> IntoDeserializer<'de, Error> for Sample {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for Sample {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Array(values) => {
+                let mut seq = SeqDeserializer::new(values.into_iter().map(Sample));
+                let result = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(result)
+            }
+            Value::Object(entries) => {
+                let mut map = MapDeserializer::new(entries.into_iter().map(|(k, v)| (k, Sample(v))));
+                let result = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(result)
+            }
+            // Nothing to look inside of, serde_json handles these.
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Null => visitor.visit_f32(f32::NAN),
+            value => value.deserialize_f32(visitor),
+        }
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Null => visitor.visit_f64(f64::NAN),
+            value => value.deserialize_f64(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            value => visitor.visit_some(Sample(value)),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Unit variants are written as a string, the others as an object with the variant as the only key.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::String(variant) => visitor.visit_enum(variant.into_deserializer()),
+            Value::Object(entries) if entries.len() == 1 => {
+                let (variant, value) = entries.into_iter().next().unwrap();
+                visitor.visit_enum(Variant { variant, value: Sample(value) })
+            }
+            value => Err(Error::custom(format!("expected an enum variant, found {}", value))),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 char str string bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// An enum variant with data.
+struct Variant {
+    variant: String,
+    value: Sample,
+}
+
+impl<'de> EnumAccess<'de> for Variant {
+    type Error = Error;
+    type Variant = Sample;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Sample), Error> {
+        let name: StringDeserializer<Error> = self.variant.into_deserializer();
+        let variant = seed.deserialize(name)?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Sample {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        match self.0 {
+            Value::Null => Ok(()),
+            value => Err(Error::custom(format!("expected a unit variant, found {}", value))),
+        }
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<S::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_any(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_any(visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_reader::read_file;
+    use crate::log_writer::{channel_schema, ChannelDescription, LogRecord, LogWriter};
+    use kingfisher_data_types::dds_topics::{GpsData, GpsFix, GPS_TOPIC};
+
+    /// A fix from before the first sky report, without DOP.
+    fn fix_without_dop() -> GpsData {
+        GpsData {
+            id: "kingfisher".to_string(),
+            time: 1_700_000_000.5,
+            latitude: 48.4284,
+            longitude: -123.3656,
+            altitude: 12.0,
+            velocity: 1.5,
+            direction: 270.0,
+            fix: GpsFix::Fix3D,
+            good_satellites: 7,
+            visible_satellites: 9,
+            hdop: f32::NAN,
+            vdop: f32::NAN,
+            horizontal_error: 2.5,
+            vertical_error: f32::NAN,
+            velocity_error: 0.3,
+            direction_error: f32::NAN,
+        }
+    }
+
+    #[test]
+    fn null_floats_read_back_as_nan() {
+        let data = serde_json::to_vec(&fix_without_dop()).unwrap();
+        assert!(serde_json::from_slice::<GpsData>(&data).is_err());
+
+        let fix: GpsData = from_slice(&data).unwrap();
+        assert!(fix.hdop.is_nan() && fix.vdop.is_nan() && fix.vertical_error.is_nan());
+        assert_eq!(fix.horizontal_error, 2.5);
+        assert_eq!(fix.latitude, 48.4284);
+        assert!(matches!(fix.fix, GpsFix::Fix3D));
+    }
+
+    #[test]
+    fn gps_fix_with_nan_matches_its_channel_schema() {
+        let schema = serde_json::to_value(channel_schema::<GpsData>()).unwrap();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+        let sample = serde_json::to_value(fix_without_dop()).unwrap();
+        assert!(sample["hdop"].is_null());
+        assert!(validator.is_valid(&sample));
+        // Still a schema that catches broken samples
+        let mut broken = sample.clone();
+        broken["hdop"] = Value::String("unknown".to_string());
+        assert!(!validator.is_valid(&broken));
+    }
+
+    #[test]
+    fn null_is_still_none_for_options() {
+        let value: Option<f32> = from_value(Value::Null).unwrap();
+        assert_eq!(value, None);
+        let values: Vec<Option<f64>> = from_slice(b"[1.5, null]").unwrap();
+        assert_eq!(values, vec![Some(1.5), None]);
+    }
+
+    #[test]
+    fn gps_fix_with_nan_survives_a_log_file() {
+        let path = std::env::temp_dir().join(format!("kf_nan_round_trip_{}.mcap", std::process::id()));
+        // Left behind by an earlier run that failed
+        let _ = std::fs::remove_file(&path);
+        let channels = [ChannelDescription::new::<GpsData>(GPS_TOPIC, "GpsData")];
+        let mut writer = LogWriter::create(&path, &channels).unwrap();
+        let record = LogRecord::new(GPS_TOPIC, "kingfisher", &fix_without_dop(), None).unwrap();
+        writer.write(&record).unwrap();
+        writer.finish().unwrap();
+
+        let mut fixes = Vec::new();
+        read_file(&path, |message| {
+            fixes.push(from_slice::<GpsData>(&message.data).unwrap());
+            true
+        }).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(fixes.len(), 1);
+        assert!(fixes[0].hdop.is_nan());
+        assert_eq!(fixes[0].velocity, 1.5);
+    }
+}
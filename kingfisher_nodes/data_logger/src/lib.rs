@@ -0,0 +1,5 @@
+pub mod json;
+pub mod log_reader;
+pub mod log_writer;
+pub mod session;
+pub mod storage;
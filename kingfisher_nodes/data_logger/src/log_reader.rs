@@ -0,0 +1,108 @@
+//! Reads back the MCAP files written by the data logger.
+use crate::log_writer::{LogError, LOG_FILE_EXTENSION, TYPE_NAME_KEY};
+use crate::session::read_metadata;
+use enumset::enum_set;
+use mcap::read::{MessageStream, Options};
+use std::path::{Path, PathBuf};
+
+/// A single recorded sample.
+#[derive(Debug, Clone)]
+pub struct LogMessage {
+    pub topic: String,
+    /// DDS type name of the topic, empty for files without the metadata
+    pub type_name: String,
+    /// Time the sample was received by the logger (ns since the unix epoch)
+    pub log_time: u64,
+    /// Time the sample was written by the publisher (ns since the unix epoch)
+    pub publish_time: u64,
+    /// json encoded sample
+    pub data: Vec<u8>,
+}
+
+fn collect_log_files(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_file() {
+        if path.extension().map(|e| e == LOG_FILE_EXTENSION).unwrap_or(false) {
+            files.push(path.to_path_buf());
+        }
+        return;
+    }
+    if let Ok(entries) = std::fs::read_dir(path) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            collect_log_files(&entry.path(), files);
+        }
+    }
+}
+
+/// Every log file under a path, which may be a single file, a session directory, a day directory or the whole
+/// save root. The logger's naming scheme means the sorted paths are in recording order.
+pub fn input_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    collect_log_files(path, &mut files);
+    files.sort();
+    files
+}
+
+/// Vehicle a log file was recorded for, from the metadata of its session.
+pub fn file_vehicle(path: &Path) -> Option<String> {
+    let session_dir = path.parent()?;
+    read_metadata(session_dir).ok().map(|m| m.vehicle_id)
+}
+
+/// Only keep the files recorded for the given vehicle, or every file if it is None.
+pub fn filter_vehicle(files: Vec<PathBuf>, vehicle_id: Option<&str>) -> Vec<PathBuf> {
+    match vehicle_id {
+        Some(id) => files
+            .into_iter()
+            .filter(|f| file_vehicle(f).as_deref() == Some(id))
+            .collect(),
+        None => files,
+    }
+}
+
+/// Call `visit` with every message of a log file, in file order, until it returns false.
+/// Files that weren't closed cleanly are read up to the point where they were cut off.
+pub fn read_file<F>(path: &Path, mut visit: F) -> Result<bool, LogError>
+where
+    F: FnMut(LogMessage) -> bool,
+{
+    let contents = std::fs::read(path)?;
+    for message in MessageStream::new_with_options(&contents, enum_set!(Options::IgnoreEndMagic))? {
+        let message = match message {
+            Ok(val) => val,
+            Err(e) => {
+                log::warn!("Stopped reading {:?} at a bad record: {}", path, e);
+                break;
+            }
+        };
+
+        let type_name = match message.channel.metadata.get(TYPE_NAME_KEY) {
+            Some(val) => val.clone(),
+            None => message.channel.schema.as_ref().map(|s| s.name.clone()).unwrap_or_default(),
+        };
+
+        let keep_going = visit(LogMessage {
+            topic: message.channel.topic.clone(),
+            type_name,
+            log_time: message.log_time,
+            publish_time: message.publish_time,
+            data: message.data.into_owned(),
+        });
+        if !keep_going {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Call `visit` with every message of a set of files, stopping early if it returns false.
+pub fn read_files<F>(files: &[PathBuf], mut visit: F) -> Result<bool, LogError>
+where
+    F: FnMut(LogMessage) -> bool,
+{
+    for file in files {
+        if !read_file(file, &mut visit)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
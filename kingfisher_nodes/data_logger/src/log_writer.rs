@@ -0,0 +1,235 @@
+//! Writes DDS samples into self-describing MCAP files (https://mcap.dev).
+//! Each topic gets its own channel, messages are json encoded and the channel schema is the json schema of the type.
+//! NaN floats are written as null, so the schema lets every float be null. Decode the messages with `json::from_slice`
+//! to get the NaNs back.
+use mcap::{records::{MessageHeader, Metadata}, McapError, Writer};
+use schemars::schema::{InstanceType, RootSchema, SchemaObject, SingleOrVec};
+use schemars::visit::{visit_schema_object, Visitor};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+pub const LOG_FILE_EXTENSION: &str = "mcap";
+pub const SCHEMA_ENCODING: &str = "jsonschema";
+pub const MESSAGE_ENCODING: &str = "json";
+
+/// Channel metadata key holding the DDS type name of the topic.
+pub const TYPE_NAME_KEY: &str = "dds_type";
+
+#[derive(Debug)]
+pub enum LogError {
+    Io(std::io::Error),
+    Mcap(McapError),
+    Json(serde_json::Error),
+    UnknownTopic(String),
+}
+
+impl std::fmt::Display for LogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogError::Io(e) => write!(f, "I/O error: {}", e),
+            LogError::Mcap(e) => write!(f, "MCAP error: {}", e),
+            LogError::Json(e) => write!(f, "Serialization error: {}", e),
+            LogError::UnknownTopic(topic) => write!(f, "No channel registered for topic {}", topic),
+        }
+    }
+}
+
+impl std::error::Error for LogError {}
+
+impl From<std::io::Error> for LogError {
+    fn from(e: std::io::Error) -> Self {
+        LogError::Io(e)
+    }
+}
+
+impl From<McapError> for LogError {
+    fn from(e: McapError) -> Self {
+        LogError::Mcap(e)
+    }
+}
+
+impl From<serde_json::Error> for LogError {
+    fn from(e: serde_json::Error) -> Self {
+        LogError::Json(e)
+    }
+}
+
+/// A sample received from DDS, already serialized and ready to be written.
+#[derive(Debug)]
+pub struct LogRecord {
+    pub topic: &'static str,
+    /// Vehicle the sample came from
+    pub vehicle_id: String,
+    /// Time the sample was received by the logger (ns since the unix epoch)
+    pub receive_time: u64,
+    /// Time the sample was written by the publisher (ns since the unix epoch), receive time if unknown
+    pub publish_time: u64,
+    pub data: Vec<u8>,
+}
+
+impl LogRecord {
+    /// Serialize a sample for the given topic.
+    pub fn new<T: Serialize>(topic: &'static str, vehicle_id: &str, sample: &T, publish_time: Option<u64>) -> Result<Self, LogError> {
+        let receive_time = now_nanos();
+        Ok(LogRecord {
+            topic,
+            vehicle_id: vehicle_id.to_string(),
+            receive_time,
+            publish_time: publish_time.unwrap_or(receive_time),
+            data: serde_json::to_vec(sample)?,
+        })
+    }
+}
+
+/// Current time in nanoseconds since the unix epoch.
+pub fn now_nanos() -> u64 {
+    match std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH) {
+        Ok(val) => val.as_nanos() as u64,
+        Err(e) => {
+            log::error!("Failed to get system time: {:?}", e);
+            0
+        }
+    }
+}
+
+/// Description of a topic to be recorded.
+#[derive(Clone)]
+pub struct ChannelDescription {
+    pub topic: &'static str,
+    pub type_name: &'static str,
+    pub schema: Vec<u8>,
+}
+
+impl ChannelDescription {
+    /// Describe a topic carrying the type T.
+    pub fn new<T: JsonSchema>(topic: &'static str, type_name: &'static str) -> Self {
+        let schema = serde_json::to_vec(&channel_schema::<T>()).unwrap_or_default();
+        ChannelDescription {
+            topic,
+            type_name,
+            schema,
+        }
+    }
+}
+
+/// The json schema of the messages of a channel carrying the type T.
+pub fn channel_schema<T: JsonSchema>() -> RootSchema {
+    let mut schema = schemars::schema_for!(T);
+    NullableFloats.visit_root_schema(&mut schema);
+    schema
+}
+
+/// Lets the floats of a schema be null, which is how serde_json writes NaN.
+struct NullableFloats;
+
+impl Visitor for NullableFloats {
+    fn visit_schema_object(&mut self, schema: &mut SchemaObject) {
+        if let Some(SingleOrVec::Single(kind)) = &schema.instance_type {
+            if **kind == InstanceType::Number {
+                schema.instance_type = Some(vec![InstanceType::Number, InstanceType::Null].into());
+            }
+        }
+        visit_schema_object(self, schema);
+    }
+}
+
+/// An open log file.
+pub struct LogWriter {
+    path: PathBuf,
+    writer: Writer<BufWriter<File>>,
+    /// Second handle on the file so it can be synced under the buffered writer
+    file: File,
+    opened: std::time::Instant,
+    channels: HashMap<&'static str, u16>,
+    sequence: u32,
+    bytes_written: u64,
+}
+
+impl LogWriter {
+    /// Create a new log file with a channel for each topic. Fails rather than truncate a file that already exists.
+    pub fn create(path: &Path, channels: &[ChannelDescription]) -> Result<Self, LogError> {
+        let file = File::create_new(path)?;
+        let sync_handle = file.try_clone()?;
+        let mut writer = Writer::new(BufWriter::new(file))?;
+
+        let mut channel_ids = HashMap::new();
+        for channel in channels {
+            let schema_id = writer.add_schema(channel.type_name, SCHEMA_ENCODING, &channel.schema)?;
+            let metadata = BTreeMap::from([(TYPE_NAME_KEY.to_string(), channel.type_name.to_string())]);
+            let channel_id = writer.add_channel(schema_id, channel.topic, MESSAGE_ENCODING, &metadata)?;
+            channel_ids.insert(channel.topic, channel_id);
+        }
+
+        Ok(LogWriter {
+            path: path.to_path_buf(),
+            writer,
+            file: sync_handle,
+            opened: std::time::Instant::now(),
+            channels: channel_ids,
+            sequence: 0,
+            bytes_written: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Message bytes written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Time since the file was created.
+    pub fn age(&self) -> std::time::Duration {
+        self.opened.elapsed()
+    }
+
+    /// Close the current chunk and get everything written so far onto the disk,
+    /// so at most one sync interval is lost on a power cut.
+    pub fn sync(&mut self) -> Result<(), LogError> {
+        self.writer.flush()?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Append a record to its topic's channel.
+    pub fn write(&mut self, record: &LogRecord) -> Result<(), LogError> {
+        let channel_id = *self
+            .channels
+            .get(record.topic)
+            .ok_or_else(|| LogError::UnknownTopic(record.topic.to_string()))?;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.writer.write_to_known_channel(
+            &MessageHeader {
+                channel_id,
+                sequence: self.sequence,
+                log_time: record.receive_time,
+                publish_time: record.publish_time,
+            },
+            &record.data,
+        )?;
+        self.bytes_written += record.data.len() as u64;
+        Ok(())
+    }
+
+    /// Store a named set of key/value pairs in the file, used for session markers.
+    pub fn write_metadata(&mut self, name: &str, values: BTreeMap<String, String>) -> Result<(), LogError> {
+        self.writer.write_metadata(&Metadata {
+            name: name.to_string(),
+            metadata: values,
+        })?;
+        Ok(())
+    }
+
+    /// Write the summary section and close the file.
+    pub fn finish(mut self) -> Result<(), LogError> {
+        self.writer.finish()?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
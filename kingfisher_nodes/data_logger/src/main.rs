@@ -1,288 +1,180 @@
 //! Utility for saving data from multiple DDS topics
-use config::Config;
 use clap::Parser;
-use kingfisher_data_types::dds_topics::{
-    SystemStatusCpu, SYSTEM_STATUS_CPU_TOPIC, SYSTEM_STATUS_DISK_TOPIC, SYSTEM_STATUS_MEMORY_TOPIC, 
-    SYSTEM_STATUS_NETWORK_TOPIC, GPS_TOPIC, IMU_TOPIC, SystemStatusMemory,
-    SystemStatusDisk, SystemStatusNetwork, GpsData,
-    ImuData
+use kingfisher_data_types::for_each_topic;
+use kingfisher_data_types::topic_registry::{
+    publish, subscribe, take_samples, LoggerCommandTopic, LoggerStatusTopic, SystemStatusDiskTopic, Topic,
 };
+use data_logger::log_writer::{ChannelDescription, LogRecord};
+use data_logger::storage::QuotaPolicy;
 
 use dust_dds::{
-    dds_async::domain_participant_factory::DomainParticipantFactoryAsync, 
-    infrastructure::{error::DdsError, qos::QosKind, status::NO_STATUS}, 
-    subscription::sample_info::{ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE}, 
+    dds_async::{
+        domain_participant::DomainParticipantAsync, subscriber::SubscriberAsync,
+    },
+    infrastructure::{qos::QosKind, status::NO_STATUS},
 };
+use kingfisher_node::{Node, NodeArgs};
+use std::path::PathBuf;
+use tokio::sync::mpsc;
 
+mod recorder;
 
+use crate::recorder::{LoggerEvent, Recorder, RecorderSettings};
+
+/// Most samples taken from a reader at once
+const MAX_SAMPLES: i32 = 25;
 
 /// Parser for command line parameters
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct CommandLineParameters {
-    /// Path to the config file
-    #[arg(short, long)]
-    config_file: Option<String>,
+    #[command(flatten)]
+    node: NodeArgs,
 }
 
-// struct Listener<T> {
-//     listener: Sender<T>
-// }
+/// Take samples from a topic, serialize them and pass them on to the recorder.
+async fn log_topic<T: Topic>(participant: DomainParticipantAsync, subscriber: SubscriberAsync, recorder: mpsc::Sender<LoggerEvent>) {
+    let reader = match subscribe::<T>(&participant, &subscriber).await {
+        Ok(val) => val,
+        Err(e) => {
+            log::error!("Failed to subscribe to {}, it won't be logged: {:?}", T::NAME, e);
+            return;
+        }
+    };
+
+    loop {
+        for sample in take_samples::<T>(&reader, MAX_SAMPLES).await {
+            log::debug!("{:?}", sample.data);
+
+            let record = match LogRecord::new(T::NAME, T::vehicle_id(&sample.data), &sample.data, sample.source_time) {
+                Ok(val) => val,
+                Err(e) => {
+                    log::error!("Failed to serialize sample on {}: {}", T::NAME, e);
+                    continue;
+                }
+            };
+
+            if recorder.send(LoggerEvent::Record(record)).await.is_err() {
+                log::error!("Recorder task has stopped, no longer logging {}.", T::NAME);
+                return;
+            }
+        }
+    }
+}
+
+/// Pass the samples of a topic on to the recorder as events.
+async fn forward_topic<T: Topic>(participant: DomainParticipantAsync, subscriber: SubscriberAsync, recorder: mpsc::Sender<LoggerEvent>, event: fn(T::Data) -> LoggerEvent) {
+    let reader = match subscribe::<T>(&participant, &subscriber).await {
+        Ok(val) => val,
+        Err(e) => {
+            log::error!("Failed to subscribe to {}, it won't be recorded: {:?}", T::NAME, e);
+            return;
+        }
+    };
+    loop {
+        for sample in take_samples::<T>(&reader, 10).await {
+            if recorder.send(event(sample.data)).await.is_err() {
+                log::error!("Recorder task has stopped.");
+                return;
+            }
+        }
+    }
+}
 
-// impl<T: 'static> DataReaderListenerAsync<'_> for Listener<T>{
-//     type Foo = T;
+/// Every registered topic gets its own channel in the log file and a task feeding it.
+macro_rules! log_all_topics {
+    ($($topic:ty),*) => {
+        fn log_channels() -> Vec<ChannelDescription> {
+            vec![$(ChannelDescription::new::<<$topic as Topic>::Data>(<$topic as Topic>::NAME, <$topic as Topic>::TYPE_NAME)),*]
+        }
 
-//     async fn on_data_available(&mut self, the_reader: DataReaderAsync<T>) {
-//         if let Ok(samples) =
-//             the_reader.take(1, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await
-//         {
-//             let sample = samples[0].data().unwrap();
-//             log::info!("Read sample: {:?}", sample);
-//         }
-//     }
-//     async fn on_subscription_matched(
-//         &mut self,
-//         _the_reader: DataReaderAsync<T>,
-//         status: dust_dds::infrastructure::status::SubscriptionMatchedStatus,
-//     ) {
-//         if status.current_count == 0 {
-//             log::info!("Connected.");
-//         }
-//     }
+        fn spawn_log_tasks(participant: &DomainParticipantAsync, subscriber: &SubscriberAsync, recorder: &mpsc::Sender<LoggerEvent>) {
+            $(tokio::spawn(log_topic::<$topic>(participant.clone(), subscriber.clone(), recorder.clone()));)*
+        }
+    };
+}
 
-// }
+for_each_topic!(log_all_topics);
 
+/// A whole number setting, `default` if it isn't set. Values below `min` are refused, as they'd wrap around when
+/// taken as unsigned.
+fn int_setting(settings: &config::Config, name: &str, default: i64, min: i64) -> Result<u64, String> {
+    let value = match settings.get_int(name) {
+        Ok(val) => val,
+        Err(config::ConfigError::NotFound(_)) => default,
+        Err(e) => return Err(format!("{}: {}", name, e)),
+    };
+    if value < min {
+        return Err(format!("{} must be at least {}", name, min));
+    }
+    Ok(value as u64)
+}
 
-// async fn run_reader_task<T: DdsDeserialize<'static> + Debug + 'static>(reader: &'static DataReaderAsync<T>) {
-    
-//     loop {
-//         let data = match reader.read(10, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await {
-//             Ok(val) => &val,
-//             Err(e) => match e {
-//                 DdsError::NoData => {
-//                     tokio::time::sleep(tokio::time::Duration::from_millis(100)).await; 
-//                     continue;
-//                 },
-//                 _ => {
-//                     log::error!("Unexpected error: {:?}", e);
-//                     continue;
-//                 }
-//             }
-//         };
-        
-//         for sample in data {        
-//             log::info!("{:?}", sample.data().unwrap());
-//         }
-        
-        
-//     }
-    
-// }
+fn recorder_settings(node: &Node) -> Result<RecorderSettings, String> {
+    let settings = node.settings();
+    let save_root: String = settings.get_string("save_root").unwrap_or("/data".to_string());
+    let auto_save: bool = settings.get_bool("auto_save").unwrap_or(false);
+    let quota_policy = settings.get_string("quota_policy").unwrap_or("delete_oldest".to_string());
+    Ok(RecorderSettings {
+        save_root: PathBuf::from(save_root),
+        auto_save,
+        rotate_bytes: int_setting(settings, "rotate_size_mb", 256, 1)? * 1024 * 1024,
+        rotate_interval: std::time::Duration::from_secs(int_setting(settings, "rotate_interval", 600, 1)?),
+        sync_interval: std::time::Duration::from_secs(int_setting(settings, "sync_interval", 5, 1)?),
+        // 0 turns the quota off
+        quota_bytes: int_setting(settings, "quota_mb", 0, 0)? * 1024 * 1024,
+        min_free_bytes: int_setting(settings, "min_free_mb", 512, 0)? * 1024 * 1024,
+        quota_policy: match QuotaPolicy::from_config(&quota_policy) {
+            Some(val) => val,
+            None => {
+                log::error!("Unknown quota policy {}, using delete_oldest.", quota_policy);
+                QuotaPolicy::DeleteOldest
+            }
+        },
+        save_disk: settings.get_string("save_disk").ok(),
+        vehicle_id: node.vehicle_id().to_string(),
+        vehicles: node.vehicle_filter(),
+        watchdog: node.watchdog(),
+    })
+}
 
 #[tokio::main]
 async fn main() {
-    
-    //switch this to syslog later: https://rust-lang-nursery.github.io/rust-cookbook/development_tools/debugging/log.html#log-to-the-unix-syslog
-    env_logger::init();
-    log::info!("Starting data logger.");
-    
     let cli = CommandLineParameters::parse();
-    let config_file = match cli.config_file {
-        Some(val) => val,
-        None => String::from("./data_logger.toml")
+    let node = Node::init("data_logger", env!("CARGO_PKG_VERSION"), &cli.node, "./data_logger.toml");
+    let recorder_settings = match recorder_settings(&node) {
+        Ok(val) => val,
+        Err(e) => {
+            log::error!("Invalid settings: {}", e);
+            std::process::exit(1);
+        }
     };
-    
-    let settings = Config::builder()
-    .add_source(config::File::with_name(&config_file))
-    .build()
-    .unwrap();
-    
-    let save_root: String = settings.get_string("save_root").unwrap_or("/data".to_string());
-    let auto_save: bool = settings.get_bool("auto_save").unwrap_or(false);
-    
+
     //Setting up DDS
-    let domain_id = kingfisher_data_types::DEFAULT_DOMAIN;
-    let participant_factory = DomainParticipantFactoryAsync::new();
-    
-    let participant = participant_factory
-    .create_participant(domain_id, QosKind::Default, None, NO_STATUS)
-    .await
-    .unwrap();
-    
-    //Topics
-    //System Status topics
-    let topic_memory = participant
-    .create_topic::<SystemStatusMemory>(SYSTEM_STATUS_MEMORY_TOPIC, "SystemStatusMemory", QosKind::Default, None, NO_STATUS)
-    .await
-    .unwrap();
-    let topic_disk = participant
-    .create_topic::<SystemStatusDisk>(SYSTEM_STATUS_DISK_TOPIC, "SystemStatusDisk", QosKind::Default, None, NO_STATUS)
-    .await
-    .unwrap();
-    let topic_network = participant.create_topic::<SystemStatusNetwork>(SYSTEM_STATUS_NETWORK_TOPIC, "SystemStatusNetwork", QosKind::Default, None, NO_STATUS)
-    .await
-    .unwrap();
-    let topic_cpu = participant.create_topic::<SystemStatusCpu>(SYSTEM_STATUS_CPU_TOPIC, "SystemStatusCpu", QosKind::Default, None, NO_STATUS)
-    .await
-    .unwrap();
+    let participant = node.participant_async().await;
 
-    let topic_imu = participant.create_topic::<ImuData>(IMU_TOPIC, "ImuData", QosKind::Default, None, NO_STATUS)
-    .await
-    .unwrap();
-    
-    //GPS topics
-    let topic_gps = participant.create_topic::<GpsData>(GPS_TOPIC, "GpsData", QosKind::Default, None, NO_STATUS).await.unwrap();
-    
     let subscriber = participant
     .create_subscriber(QosKind::Default, None, NO_STATUS)
     .await
     .unwrap();
-    
-    let reader_memory = subscriber.create_datareader::<SystemStatusMemory>(&topic_memory, QosKind::Default, None, NO_STATUS).await.unwrap();
-    let reader_disk = subscriber.create_datareader::<SystemStatusDisk>(&topic_disk, QosKind::Default, None, NO_STATUS).await.unwrap();
-    let reader_network = subscriber.create_datareader::<SystemStatusNetwork>(&topic_network, QosKind::Default, None, NO_STATUS).await.unwrap();
-    let reader_cpu = subscriber.create_datareader::<SystemStatusCpu>(&topic_cpu, QosKind::Default, None, NO_STATUS).await.unwrap();
-    let reader_imu = subscriber.create_datareader::<ImuData>(&topic_imu, QosKind::Default, None, NO_STATUS).await.unwrap();
-    let reader_gps = subscriber.create_datareader::<GpsData>(&topic_gps, QosKind::Default, None, NO_STATUS).await.unwrap();
-    
-    tokio::spawn( async move {
-        loop {
-            let data = match reader_memory.take(10, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await {
-                Ok(val) => val,
-                Err(e) => match e {
-                    DdsError::NoData => {
-                        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await; 
-                        Vec::new()
-                    },
-                    _ => {
-                        log::error!("Unexpected error: {:?}", e);
-                        Vec::new()
-                    }
-                }
-            };
-            
-            for sample in data {
-                log::info!("{:?}", sample.data().unwrap());
-            }
-            
-        }
-    });
-    
+    let publisher = participant
+    .create_publisher(QosKind::Default, None, NO_STATUS)
+    .await
+    .unwrap();
+    let status_writer = publish::<LoggerStatusTopic>(&participant, &publisher).await.unwrap();
 
-    tokio::spawn( async move {
-        loop {
-            let data = match reader_cpu.take(10, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await {
-                Ok(val) => val,
-                Err(e) => match e {
-                    DdsError::NoData => {
-                        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await; 
-                        Vec::new()
-                    },
-                    _ => {
-                        log::error!("Unexpected error: {:?}", e);
-                        Vec::new()
-                    }
-                }
-            };
-            
-            for sample in data {
-                log::info!("{:?}", sample.data().unwrap());
-            }
-            
-        }
-    });
+    let (recorder_tx, recorder_rx) = mpsc::channel(256);
+    let recorder = Recorder::new(recorder_settings, log_channels(), status_writer);
+    let recorder = tokio::spawn(recorder.run(recorder_rx));
+    tokio::spawn(forward_topic::<LoggerCommandTopic>(participant.clone(), subscriber.clone(), recorder_tx.clone(), LoggerEvent::Command));
+    tokio::spawn(forward_topic::<SystemStatusDiskTopic>(participant.clone(), subscriber.clone(), recorder_tx.clone(), LoggerEvent::DiskStatus));
+    spawn_log_tasks(&participant, &subscriber, &recorder_tx);
 
-    tokio::spawn( async move {
-        loop {
-            let data = match reader_disk.take(10, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await {
-                Ok(val) => val,
-                Err(e) => match e {
-                    DdsError::NoData => {
-                        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await; 
-                        Vec::new()
-                    },
-                    _ => {
-                        log::error!("Unexpected error: {:?}", e);
-                        Vec::new()
-                    }
-                }
-            };
-            
-            for sample in data {
-                log::info!("{:?}", sample.data().unwrap());
-            }
-            
-        }
-    });
-    
-    tokio::spawn( async move {loop {
-        let data = match reader_network.take(10, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await {
-            Ok(val) => val,
-            Err(e) => match e {
-                DdsError::NoData => {
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await; 
-                    Vec::new()
-                },
-                _ => {
-                    log::error!("Unexpected error: {:?}", e);
-                    Vec::new()
-                }
-            }
-        };
-        
-        for sample in data {
-            log::info!("{:?}", sample.data().unwrap());
-        }
-        
-    }
-    });
+    node.ready();
+    node.wait_for_shutdown().await;
 
-    tokio::spawn( async move {
-        loop {
-            let data = match reader_imu.take(25, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await {
-                Ok(val) => val,
-                Err(e) => match e {
-                    DdsError::NoData => {
-                        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await; 
-                        Vec::new()
-                    },
-                    _ => {
-                        log::error!("Unexpected error: {:?}", e);
-                        Vec::new()
-                    }
-                }
-            };
-            
-            for sample in data {
-                log::info!("{:?}", sample.data().unwrap());
-            }
-            
-        }
-    });
-    
-    tokio::spawn( async move {
-        loop {
-            let data = match reader_gps.take(10, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await {
-                Ok(val) => val,
-                Err(e) => match e {
-                    DdsError::NoData => {
-                        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await; 
-                        Vec::new()
-                    },
-                    _ => {
-                        log::error!("Unexpected error: {:?}", e);
-                        Vec::new()
-                    }
-                }
-            };
-            
-            for sample in data {
-                log::info!("{:?}", sample.data().unwrap());
-            }
-            
-        }
-    });
-    loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
-    }
+    // Let the recorder finish the file so it has a valid summary section.
+    let _ = recorder_tx.send(LoggerEvent::Shutdown).await;
+    let _ = recorder.await;
+    node.close_async(participant).await;
 }
@@ -0,0 +1,331 @@
+//! Task that owns the current recording: writes the samples into a session per vehicle, rotates files, keeps the
+//! disk usage in check and reports on its progress.
+use data_logger::log_writer::{ChannelDescription, LogRecord};
+use data_logger::session::Session;
+use data_logger::storage::{self, QuotaPolicy};
+use dust_dds::dds_async::data_writer::DataWriterAsync;
+use kingfisher_data_types::dds_topics::{LoggerCommand, LoggerCommandKind, LoggerStatus, SystemStatusDisk};
+use kingfisher_node::{VehicleFilter, Watchdog};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// How often the logger status is published
+const STATUS_PERIOD: u64 = 1;
+
+/// Disk status samples older than this are ignored in favour of asking the filesystem directly.
+const DISK_STATUS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Messages handled by the recorder task.
+pub enum LoggerEvent {
+    Record(LogRecord),
+    Command(LoggerCommand),
+    DiskStatus(SystemStatusDisk),
+    Shutdown,
+}
+
+/// Recorder configuration, read from data_logger.toml
+pub struct RecorderSettings {
+    pub save_root: PathBuf,
+    pub auto_save: bool,
+    /// Start a new file once the current one holds this many bytes
+    pub rotate_bytes: u64,
+    /// Start a new file once the current one is this old
+    pub rotate_interval: Duration,
+    /// How often files are flushed to disk
+    pub sync_interval: Duration,
+    /// Maximum space all the sessions may use, 0 for no limit
+    pub quota_bytes: u64,
+    /// Free space to always leave on the save partition
+    pub min_free_bytes: u64,
+    pub quota_policy: QuotaPolicy,
+    /// Name of the save partition in the state_monitor disk status, if it is monitored
+    pub save_disk: Option<String>,
+    /// Vehicle the logger runs on. Only its commands and disk status are acted on.
+    pub vehicle_id: String,
+    /// Vehicles to record, from the `vehicles` config list
+    pub vehicles: VehicleFilter,
+    /// Petted for every status published, so systemd notices a recorder that got stuck
+    pub watchdog: Watchdog,
+}
+
+/// Free space (bytes) on the partition holding the given path.
+fn free_disk_space(path: &Path) -> u64 {
+    match nix::sys::statvfs::statvfs(path) {
+        Ok(stats) => stats.blocks_available() as u64 * stats.fragment_size() as u64,
+        Err(e) => {
+            log::error!("Failed to get free space for {:?}: {:?}", path, e);
+            0
+        }
+    }
+}
+
+/// Name and notes of the recording in progress, given to the session of every vehicle that shows up.
+struct Recording {
+    name: String,
+    notes: String,
+    started: Instant,
+}
+
+pub struct Recorder {
+    settings: RecorderSettings,
+    channels: Vec<ChannelDescription>,
+    status_writer: DataWriterAsync<LoggerStatus>,
+    recording: Option<Recording>,
+    /// Session of each vehicle recorded so far
+    sessions: BTreeMap<String, Session>,
+    /// Free space on the save disk reported by state_monitor and when it was received
+    disk_status: Option<(u64, Instant)>,
+}
+
+impl Recorder {
+    pub fn new(settings: RecorderSettings, channels: Vec<ChannelDescription>, status_writer: DataWriterAsync<LoggerStatus>) -> Self {
+        Recorder {
+            settings,
+            channels,
+            status_writer,
+            recording: None,
+            sessions: BTreeMap::new(),
+            disk_status: None,
+        }
+    }
+
+    /// Free space on the save partition, preferring the state_monitor numbers when they are fresh.
+    fn free_disk(&self) -> u64 {
+        match self.disk_status {
+            Some((free, received)) if received.elapsed() < DISK_STATUS_TIMEOUT => free,
+            _ => free_disk_space(&self.settings.save_root),
+        }
+    }
+
+    /// Bytes that have to be released to get back within the quota and free space limits.
+    fn storage_shortfall(&self) -> u64 {
+        let over_quota = if self.settings.quota_bytes > 0 {
+            storage::used_bytes(&self.settings.save_root).saturating_sub(self.settings.quota_bytes)
+        } else {
+            0
+        };
+        let under_free = self.settings.min_free_bytes.saturating_sub(self.free_disk());
+        over_quota.max(under_free)
+    }
+
+    /// Make room according to the quota policy. Returns false if there still isn't enough space to record.
+    fn enforce_storage_limits(&mut self) -> bool {
+        let shortfall = self.storage_shortfall();
+        if shortfall == 0 {
+            return true;
+        }
+
+        match self.settings.quota_policy {
+            QuotaPolicy::DeleteOldest => {
+                let current: Vec<PathBuf> = self.sessions.values().map(|s| s.directory().to_path_buf()).collect();
+                let freed = storage::delete_oldest(&self.settings.save_root, &current, shortfall);
+                // The numbers from state_monitor are stale now.
+                self.disk_status = None;
+                if freed >= shortfall {
+                    return true;
+                }
+                log::error!("Only {} of {} bytes could be freed, the current sessions are all that's left.", freed, shortfall);
+                false
+            }
+            QuotaPolicy::Refuse => {
+                log::error!("Storage limit exceeded by {} bytes, refusing to record.", shortfall);
+                false
+            }
+        }
+    }
+
+    /// Start a recording. The vehicle sessions are opened as their first samples come in.
+    fn start_recording(&mut self, name: &str, notes: &str) {
+        self.stop_recording();
+        if !self.enforce_storage_limits() {
+            log::error!("Not starting recording {}, out of space.", name);
+            return;
+        }
+
+        log::info!("Started recording {}", name);
+        self.recording = Some(Recording {
+            name: name.to_string(),
+            notes: notes.to_string(),
+            started: Instant::now(),
+        });
+    }
+
+    /// Close the sessions of every vehicle.
+    fn stop_recording(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            log::info!("Stopped recording {}", recording.name);
+        }
+        for (_, session) in std::mem::take(&mut self.sessions) {
+            stop_session(session);
+        }
+    }
+
+    /// Write a sample into the session of its vehicle, starting the session if needed.
+    fn record(&mut self, record: LogRecord) {
+        let recording = match &self.recording {
+            Some(val) => val,
+            None => return,
+        };
+        if !self.settings.vehicles.accepts(&record.vehicle_id) {
+            return;
+        }
+
+        if !self.sessions.contains_key(&record.vehicle_id) {
+            match Session::start(&self.settings.save_root, &recording.name, &recording.notes, &record.vehicle_id, &self.channels) {
+                Ok(val) => {
+                    log::info!("Started session {} for vehicle {} in {:?}", val.name(), record.vehicle_id, val.directory());
+                    self.sessions.insert(record.vehicle_id.clone(), val);
+                }
+                Err(e) => {
+                    log::error!("Failed to start session {} for vehicle {}: {}", recording.name, record.vehicle_id, e);
+                    // Give up on the recording rather than retrying on every sample.
+                    self.stop_recording();
+                    return;
+                }
+            }
+        }
+
+        if let Some(session) = self.sessions.get_mut(&record.vehicle_id) {
+            if let Err(e) = session.write(&record) {
+                log::error!("Failed to write {} sample for vehicle {}: {}", record.topic, record.vehicle_id, e);
+            }
+        }
+    }
+
+    fn handle_command(&mut self, command: LoggerCommand) {
+        // Commands are addressed to the logger of one vehicle.
+        if command.id != self.settings.vehicle_id {
+            return;
+        }
+        log::info!("Received logger command: {:?}", command);
+        match command.command {
+            LoggerCommandKind::StartSession => {
+                self.start_recording(&command.name, &command.notes);
+            }
+            LoggerCommandKind::StopSession => match self.recording {
+                Some(_) => self.stop_recording(),
+                None => log::warn!("Stop requested but no session is running."),
+            },
+            LoggerCommandKind::AddMarker => match self.recording {
+                Some(_) => {
+                    for current in self.sessions.values_mut() {
+                        if let Err(e) = current.add_marker(&command.marker) {
+                            log::error!("Failed to add marker: {}", e);
+                        }
+                    }
+                }
+                None => log::warn!("Marker \"{}\" dropped, no session is running.", command.marker),
+            },
+        }
+    }
+
+    fn handle_disk_status(&mut self, status: SystemStatusDisk) {
+        // Other vehicles' disks say nothing about ours.
+        if status.id != self.settings.vehicle_id {
+            return;
+        }
+        if let Some(save_disk) = &self.settings.save_disk {
+            if let Some(disk) = status.disk_info.iter().find(|d| &d.name == save_disk) {
+                self.disk_status = Some((disk.bytes_available, Instant::now()));
+            }
+        }
+    }
+
+    /// Periodic flush, rotation and space check.
+    fn housekeeping(&mut self) {
+        for current in self.sessions.values_mut() {
+            if let Err(e) = current.sync() {
+                log::error!("Failed to sync session {}: {}", current.name(), e);
+            }
+
+            if current.file_bytes_written() >= self.settings.rotate_bytes || current.file_age() >= self.settings.rotate_interval {
+                match current.rotate() {
+                    Ok(_) => log::info!("Rotated session {} to {:?}", current.name(), current.current_file()),
+                    Err(e) => log::error!("Failed to rotate session {}: {}", current.name(), e),
+                }
+            }
+        }
+
+        if self.recording.is_some() && !self.enforce_storage_limits() {
+            log::error!("Stopping the current recording, out of space.");
+            self.stop_recording();
+        }
+    }
+
+    async fn publish_status(&self) {
+        let status = match self.recording.as_ref() {
+            Some(recording) => LoggerStatus {
+                id: self.settings.vehicle_id.clone(),
+                recording: true,
+                session_name: recording.name.clone(),
+                current_file: self.sessions.get(&self.settings.vehicle_id)
+                    .map(|s| s.current_file().to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                vehicles: self.sessions.keys().cloned().collect(),
+                bytes_written: self.sessions.values().map(|s| s.bytes_written()).sum(),
+                duration: recording.started.elapsed().as_secs_f64(),
+                free_disk: self.free_disk(),
+            },
+            None => LoggerStatus {
+                id: self.settings.vehicle_id.clone(),
+                recording: false,
+                session_name: String::new(),
+                current_file: String::new(),
+                vehicles: Vec::new(),
+                bytes_written: 0,
+                duration: 0.0,
+                free_disk: self.free_disk(),
+            },
+        };
+        match self.status_writer.write(&status, None).await {
+            Ok(_) => self.settings.watchdog.pet(),
+            Err(e) => log::error!("Failed to publish logger status: {:?}", e),
+        }
+    }
+
+    /// Main loop of the recorder task.
+    pub async fn run(mut self, mut events: mpsc::Receiver<LoggerEvent>) {
+        // Anything left unfinished by a power cut has to be fixed before new files are added.
+        let recovered = storage::recover_sessions(&self.settings.save_root);
+        if recovered > 0 {
+            log::warn!("Recovered {} sessions that were not closed cleanly.", recovered);
+        }
+
+        if self.settings.auto_save {
+            self.start_recording("auto", "Started automatically at launch.");
+        } else {
+            log::info!("Auto save is disabled, waiting for a StartSession command.");
+        }
+
+        let mut status_interval = tokio::time::interval(Duration::from_secs(STATUS_PERIOD));
+        let mut housekeeping_interval = tokio::time::interval(self.settings.sync_interval);
+
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    match event {
+                        Some(LoggerEvent::Record(record)) => self.record(record),
+                        Some(LoggerEvent::Command(command)) => self.handle_command(command),
+                        Some(LoggerEvent::DiskStatus(status)) => self.handle_disk_status(status),
+                        Some(LoggerEvent::Shutdown) | None => break
+                    }
+                }
+                _ = housekeeping_interval.tick() => self.housekeeping(),
+                _ = status_interval.tick() => self.publish_status().await
+            }
+        }
+
+        self.stop_recording();
+    }
+}
+
+/// Stop a session, logging any failure.
+fn stop_session(session: Session) {
+    let name = session.name().to_string();
+    match session.stop() {
+        Ok(directory) => log::info!("Stopped session {}, saved to {:?}", name, directory),
+        Err(e) => log::error!("Failed to close session {}: {}", name, e),
+    }
+}
@@ -0,0 +1,220 @@
+//! Recording sessions: a dated directory under the save root holding the log file and a json metadata sidecar.
+use crate::log_writer::{ChannelDescription, LogError, LogRecord, LogWriter, LOG_FILE_EXTENSION};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const METADATA_FILE: &str = "session.json";
+pub const LOG_FILE_STEM: &str = "kingfisher";
+
+/// A marker added to a session by an operator.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionMarker {
+    /// RFC3339 time the marker was added
+    pub time: String,
+    pub text: String,
+}
+
+/// Contents of the json sidecar written next to the log files.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionMetadata {
+    pub name: String,
+    pub notes: String,
+    pub vehicle_id: String,
+    pub start_time: String,
+    pub end_time: Option<String>,
+    /// Log files in the session directory, in recording order
+    pub files: Vec<String>,
+    pub topics: Vec<String>,
+    pub bytes_written: u64,
+    pub markers: Vec<SessionMarker>,
+    /// Set when the session was not closed cleanly and its files were repaired
+    #[serde(default)]
+    pub recovered: bool,
+}
+
+/// Read the metadata sidecar of a session directory.
+pub fn read_metadata(session_dir: &Path) -> Result<SessionMetadata, LogError> {
+    let contents = std::fs::read(session_dir.join(METADATA_FILE))?;
+    Ok(serde_json::from_slice(&contents)?)
+}
+
+/// Name of the nth log file of a session.
+fn log_file_name(index: usize) -> String {
+    format!("{}_{:04}.{}", LOG_FILE_STEM, index, LOG_FILE_EXTENSION)
+}
+
+/// Write a sidecar out, replacing the previous version atomically.
+pub fn write_metadata(session_dir: &Path, metadata: &SessionMetadata) -> Result<(), LogError> {
+    let temporary = session_dir.join(format!("{}.tmp", METADATA_FILE));
+    let mut file = std::fs::File::create(&temporary)?;
+    file.write_all(&serde_json::to_vec_pretty(metadata)?)?;
+    file.sync_all()?;
+    std::fs::rename(&temporary, session_dir.join(METADATA_FILE))?;
+    Ok(())
+}
+
+/// Keep only characters that are safe in a directory name.
+fn sanitize(name: &str) -> String {
+    let cleaned: String = name
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    if cleaned.is_empty() {
+        String::from("session")
+    } else {
+        cleaned
+    }
+}
+
+/// Create a directory that didn't exist yet. A session started again within the same second gets a number on the
+/// end, so it can't write over the one that just stopped.
+fn new_directory(parent: &Path, name: &str) -> Result<PathBuf, LogError> {
+    for attempt in 1.. {
+        let directory = match attempt {
+            1 => parent.join(name),
+            n => parent.join(format!("{}_{}", name, n)),
+        };
+        match std::fs::create_dir(&directory) {
+            Ok(()) => return Ok(directory),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    unreachable!()
+}
+
+/// An active recording session.
+pub struct Session {
+    directory: PathBuf,
+    writer: LogWriter,
+    channels: Vec<ChannelDescription>,
+    metadata: SessionMetadata,
+    started: std::time::Instant,
+    /// Bytes written to the files that have already been rotated out
+    completed_bytes: u64,
+}
+
+impl Session {
+    /// Create the session directory (save_root/YYYY-MM-DD/HHMMSS_name_vehicle), open the log and write the sidecar.
+    pub fn start(save_root: &Path, name: &str, notes: &str, vehicle_id: &str, channels: &[ChannelDescription]) -> Result<Self, LogError> {
+        let now = chrono::Local::now();
+        let day = save_root.join(now.format("%Y-%m-%d").to_string());
+        std::fs::create_dir_all(&day)?;
+        let directory = new_directory(&day, &format!("{}_{}_{}", now.format("%H%M%S"), sanitize(name), sanitize(vehicle_id)))?;
+
+        let file_name = log_file_name(0);
+        let writer = LogWriter::create(&directory.join(&file_name), channels)?;
+
+        let metadata = SessionMetadata {
+            name: name.to_string(),
+            notes: notes.to_string(),
+            vehicle_id: vehicle_id.to_string(),
+            start_time: now.to_rfc3339(),
+            end_time: None,
+            files: vec![file_name],
+            topics: channels.iter().map(|c| c.topic.to_string()).collect(),
+            bytes_written: 0,
+            markers: Vec::new(),
+            recovered: false,
+        };
+
+        let session = Session {
+            directory,
+            writer,
+            channels: channels.to_vec(),
+            metadata,
+            started: std::time::Instant::now(),
+            completed_bytes: 0,
+        };
+        session.save_metadata()?;
+        Ok(session)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.metadata.name
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn current_file(&self) -> &Path {
+        self.writer.path()
+    }
+
+    /// Bytes written over all the files of the session.
+    pub fn bytes_written(&self) -> u64 {
+        self.completed_bytes + self.writer.bytes_written()
+    }
+
+    /// Bytes written to the current file.
+    pub fn file_bytes_written(&self) -> u64 {
+        self.writer.bytes_written()
+    }
+
+    /// Time since the current file was opened.
+    pub fn file_age(&self) -> std::time::Duration {
+        self.writer.age()
+    }
+
+    /// Seconds since the session started.
+    pub fn duration(&self) -> f64 {
+        self.started.elapsed().as_secs_f64()
+    }
+
+    pub fn write(&mut self, record: &LogRecord) -> Result<(), LogError> {
+        self.writer.write(record)
+    }
+
+    /// Close the current file and carry on in a new one.
+    pub fn rotate(&mut self) -> Result<(), LogError> {
+        let file_name = log_file_name(self.metadata.files.len());
+        let writer = LogWriter::create(&self.directory.join(&file_name), &self.channels)?;
+        let previous = std::mem::replace(&mut self.writer, writer);
+        self.completed_bytes += previous.bytes_written();
+        self.metadata.files.push(file_name);
+        self.metadata.bytes_written = self.bytes_written();
+        self.save_metadata()?;
+        previous.finish()
+    }
+
+    /// Flush the current file to disk and bring the sidecar up to date.
+    pub fn sync(&mut self) -> Result<(), LogError> {
+        self.writer.sync()?;
+        self.metadata.bytes_written = self.bytes_written();
+        self.save_metadata()
+    }
+
+    /// Add a marker to both the log file and the sidecar.
+    pub fn add_marker(&mut self, text: &str) -> Result<(), LogError> {
+        let marker = SessionMarker {
+            time: chrono::Local::now().to_rfc3339(),
+            text: text.to_string(),
+        };
+        self.writer.write_metadata(
+            "marker",
+            BTreeMap::from([
+                ("time".to_string(), marker.time.clone()),
+                ("text".to_string(), marker.text.clone()),
+            ]),
+        )?;
+        self.metadata.markers.push(marker);
+        self.save_metadata()
+    }
+
+    fn save_metadata(&self) -> Result<(), LogError> {
+        write_metadata(&self.directory, &self.metadata)
+    }
+
+    /// Close the log file and finalize the sidecar.
+    pub fn stop(mut self) -> Result<PathBuf, LogError> {
+        self.metadata.bytes_written = self.bytes_written();
+        self.metadata.end_time = Some(chrono::Local::now().to_rfc3339());
+        self.save_metadata()?;
+        self.writer.finish()?;
+        Ok(self.directory)
+    }
+}
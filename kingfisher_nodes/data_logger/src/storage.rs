@@ -0,0 +1,217 @@
+//! Housekeeping of the save root: disk quota enforcement and repair of sessions cut short by a power loss.
+use crate::log_writer::{LogError, LOG_FILE_EXTENSION};
+use crate::session::{read_metadata, write_metadata, METADATA_FILE};
+use enumset::enum_set;
+use mcap::read::{MessageStream, Options};
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// What to do once the quota or the free disk limit is reached.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuotaPolicy {
+    /// Delete the oldest sessions to make room
+    DeleteOldest,
+    /// Stop recording until an operator makes room
+    Refuse,
+}
+
+impl QuotaPolicy {
+    pub fn from_config(value: &str) -> Option<Self> {
+        match value {
+            "delete_oldest" => Some(QuotaPolicy::DeleteOldest),
+            "refuse" => Some(QuotaPolicy::Refuse),
+            _ => None,
+        }
+    }
+}
+
+/// A session directory and the space it takes up.
+#[derive(Debug)]
+pub struct SessionDirectory {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+/// Sorted subdirectories of a directory, empty if it can't be read.
+fn subdirectories(path: &Path) -> Vec<PathBuf> {
+    let mut directories: Vec<PathBuf> = match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_dir())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    directories.sort();
+    directories
+}
+
+/// Total size of the files in a directory.
+fn directory_size(path: &Path) -> u64 {
+    match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| e.metadata().ok())
+            .filter(|m| m.is_file())
+            .map(|m| m.len())
+            .sum(),
+        Err(_) => 0,
+    }
+}
+
+/// Log files in a session directory, in recording order.
+pub fn log_files(session_dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = match std::fs::read_dir(session_dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().map(|e| e == LOG_FILE_EXTENSION).unwrap_or(false))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    files.sort();
+    files
+}
+
+/// All sessions under the save root, oldest first.
+/// The save_root/YYYY-MM-DD/HHMMSS_name_vehicle layout sorts chronologically.
+pub fn list_sessions(save_root: &Path) -> Vec<SessionDirectory> {
+    subdirectories(save_root)
+        .iter()
+        .flat_map(|day| subdirectories(day))
+        .filter(|session| session.join(METADATA_FILE).exists() || !log_files(session).is_empty())
+        .map(|session| SessionDirectory {
+            bytes: directory_size(&session),
+            path: session,
+        })
+        .collect()
+}
+
+/// Space used by all the sessions under the save root.
+pub fn used_bytes(save_root: &Path) -> u64 {
+    list_sessions(save_root).iter().map(|s| s.bytes).sum()
+}
+
+/// Delete the oldest sessions, never the ones being recorded, until at least `bytes_to_free` are released.
+/// Returns the number of bytes released.
+pub fn delete_oldest(save_root: &Path, current: &[PathBuf], bytes_to_free: u64) -> u64 {
+    let mut freed = 0;
+    for session in list_sessions(save_root) {
+        if freed >= bytes_to_free {
+            break;
+        }
+        if current.contains(&session.path) {
+            continue;
+        }
+
+        match std::fs::remove_dir_all(&session.path) {
+            Ok(_) => {
+                log::warn!("Deleted session {:?} ({} MB) to stay within the disk quota.", session.path, session.bytes / 1024 / 1024);
+                freed += session.bytes;
+            }
+            Err(e) => {
+                log::error!("Failed to delete session {:?}: {:?}", session.path, e);
+            }
+        }
+
+        // Clean up the day directory once it's empty.
+        if let Some(day) = session.path.parent() {
+            if subdirectories(day).is_empty() {
+                let _ = std::fs::remove_dir(day);
+            }
+        }
+    }
+    freed
+}
+
+/// Whether a log file is missing its closing magic, i.e. it was never finished.
+pub fn is_truncated(path: &Path) -> Result<bool, LogError> {
+    let mut file = File::open(path)?;
+    let length = file.metadata()?.len();
+    if length < mcap::MAGIC.len() as u64 {
+        return Ok(true);
+    }
+    file.seek(SeekFrom::End(-(mcap::MAGIC.len() as i64)))?;
+    let mut end = vec![0; mcap::MAGIC.len()];
+    file.read_exact(&mut end)?;
+    Ok(end != mcap::MAGIC)
+}
+
+/// Rewrite a truncated log file with every message that can still be read, returning the number of messages kept.
+pub fn repair_log(path: &Path) -> Result<usize, LogError> {
+    let contents = std::fs::read(path)?;
+    let repaired_path = path.with_extension(format!("{}.repair", LOG_FILE_EXTENSION));
+
+    let mut messages = 0;
+    {
+        let mut writer = mcap::Writer::new(BufWriter::new(File::create(&repaired_path)?))?;
+        for message in MessageStream::new_with_options(&contents, enum_set!(Options::IgnoreEndMagic))? {
+            match message {
+                Ok(message) => {
+                    writer.write(&message)?;
+                    messages += 1;
+                }
+                // Everything after the first bad record was lost in the power cut.
+                Err(e) => {
+                    log::warn!("Log {:?} is cut short after {} messages: {}", path, messages, e);
+                    break;
+                }
+            }
+        }
+        writer.finish()?;
+    }
+
+    File::open(&repaired_path)?.sync_all()?;
+    std::fs::rename(&repaired_path, path)?;
+    Ok(messages)
+}
+
+/// Repair every session that wasn't closed cleanly. Must run before a new session is started.
+pub fn recover_sessions(save_root: &Path) -> usize {
+    let mut recovered = 0;
+    for session in list_sessions(save_root) {
+        let mut repaired = false;
+        for file in log_files(&session.path) {
+            match is_truncated(&file) {
+                Ok(true) => match repair_log(&file) {
+                    Ok(messages) => {
+                        log::info!("Repaired {:?}, {} messages recovered.", file, messages);
+                        repaired = true;
+                    }
+                    Err(e) => log::error!("Failed to repair {:?}: {}", file, e),
+                },
+                Ok(false) => (),
+                Err(e) => log::error!("Failed to check {:?}: {}", file, e),
+            }
+        }
+
+        // Left over partial sidecar writes
+        let _ = std::fs::remove_file(session.path.join(format!("{}.tmp", METADATA_FILE)));
+
+        match read_metadata(&session.path) {
+            Ok(mut metadata) if metadata.end_time.is_none() || repaired => {
+                // Best guess at the end of the session is the last time a file was written.
+                let last_write = log_files(&session.path)
+                    .iter()
+                    .filter_map(|f| f.metadata().and_then(|m| m.modified()).ok())
+                    .max();
+                if metadata.end_time.is_none() {
+                    metadata.end_time = last_write.map(|t| chrono::DateTime::<chrono::Local>::from(t).to_rfc3339());
+                }
+                metadata.files = log_files(&session.path)
+                    .iter()
+                    .filter_map(|f| f.file_name().map(|n| n.to_string_lossy().to_string()))
+                    .collect();
+                metadata.recovered = true;
+                match write_metadata(&session.path, &metadata) {
+                    Ok(_) => recovered += 1,
+                    Err(e) => log::error!("Failed to update metadata of {:?}: {}", session.path, e),
+                }
+            }
+            Ok(_) => (),
+            Err(e) => log::error!("Failed to read metadata of {:?}: {}", session.path, e),
+        }
+    }
+    recovered
+}
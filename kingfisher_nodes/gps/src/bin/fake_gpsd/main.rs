@@ -0,0 +1,251 @@
+//! Fake gpsd server for exercising the GPS node without a receiver.
+//! Serves the gpsd json protocol on localhost, replaying a recorded track or generating a synthetic one.
+use clap::{Parser, ValueEnum};
+use gps::gpsd::{Report, Satellite, Sky, Tpv};
+use serde_json::json;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod track;
+
+use crate::track::TrackPoint;
+
+const DEVICE: &str = "/dev/fake_gps";
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Pattern {
+    Circle,
+    Lawnmower,
+}
+
+/// Parser for command line parameters
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct CommandLineParameters {
+    /// Port to serve the gpsd protocol on
+    #[arg(short, long, default_value_t = gps::gpsd::DEFAULT_PORT)]
+    port: u16,
+
+    /// Replay a recorded GPX track
+    #[arg(long, conflicts_with_all = ["nmea", "pattern"])]
+    gpx: Option<String>,
+
+    /// Replay a recorded NMEA log
+    #[arg(long, conflicts_with = "pattern")]
+    nmea: Option<String>,
+
+    /// Generate a synthetic path
+    #[arg(long, value_enum, default_value_t = Pattern::Circle)]
+    pattern: Pattern,
+
+    /// Playback speed multiplier
+    #[arg(short, long, default_value_t = 1.0, value_parser = positive)]
+    speed_factor: f64,
+
+    /// Start over once the end of the track is reached
+    #[arg(short, long)]
+    repeat: bool,
+
+    /// Latitude of the synthetic path center/start
+    #[arg(long, default_value_t = 48.4284, allow_negative_numbers = true)]
+    latitude: f64,
+
+    /// Longitude of the synthetic path center/start
+    #[arg(long, default_value_t = -123.3656, allow_negative_numbers = true)]
+    longitude: f64,
+
+    /// Circle radius or lawnmower leg length (m)
+    #[arg(long, default_value_t = 100.0, value_parser = positive)]
+    size: f64,
+
+    /// Lawnmower leg spacing (m)
+    #[arg(long, default_value_t = 20.0, value_parser = positive)]
+    spacing: f64,
+
+    /// Number of lawnmower legs
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u32).range(1..))]
+    legs: u32,
+
+    /// Synthetic path speed (m/s)
+    #[arg(long, default_value_t = 2.0, value_parser = positive)]
+    velocity: f64,
+}
+
+/// Parse a number above zero, the playback and path generation divide by these.
+fn positive(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(val) if val.is_finite() && val > 0.0 => Ok(val),
+        Ok(_) => Err("must be above 0".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Clients that have enabled watch mode
+type Watchers = Arc<Mutex<Vec<TcpStream>>>;
+
+/// Send a json line, returning false if the client has gone away.
+fn send_line(stream: &mut TcpStream, line: &str) -> bool {
+    stream.write_all(line.as_bytes()).is_ok() && stream.write_all(b"\n").is_ok()
+}
+
+/// Talk the connection handshake with a client and register it as a watcher once it asks.
+fn handle_client(stream: TcpStream, watchers: Watchers) {
+    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    log::info!("Client connected: {}", peer);
+
+    let mut writer = match stream.try_clone() {
+        Ok(val) => val,
+        Err(e) => {
+            log::error!("Failed to clone client stream: {:?}", e);
+            return;
+        }
+    };
+
+    let version = json!({"class": "VERSION", "release": "3.25", "rev": "fake_gpsd", "proto_major": 3, "proto_minor": 15});
+    if !send_line(&mut writer, &version.to_string()) {
+        return;
+    }
+
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(val) => val,
+            Err(_) => break,
+        };
+
+        if line.starts_with("?WATCH") {
+            let enable = !line.contains("\"enable\":false");
+            let devices = json!({"class": "DEVICES", "devices": [{"class": "DEVICE", "path": DEVICE, "driver": "fake", "activated": true}]});
+            let watch = json!({"class": "WATCH", "enable": enable, "json": true});
+            send_line(&mut writer, &devices.to_string());
+            send_line(&mut writer, &watch.to_string());
+
+            if enable {
+                match writer.try_clone() {
+                    Ok(val) => watchers.lock().unwrap().push(val),
+                    Err(e) => log::error!("Failed to clone client stream: {:?}", e),
+                }
+            }
+        } else if line.starts_with("?VERSION") {
+            send_line(&mut writer, &version.to_string());
+        }
+    }
+    log::info!("Client disconnected: {}", peer);
+}
+
+/// Build the TPV and SKY reports for a track point.
+fn reports(point: &TrackPoint) -> (Report, Report) {
+    let time = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+    let tpv = Tpv {
+        device: Some(DEVICE.into()),
+        mode: 3,
+        status: Some(1),
+        time: Some(time.clone()),
+        lat: Some(point.latitude),
+        lon: Some(point.longitude),
+        alt_hae: Some(point.altitude),
+        speed: Some(point.speed),
+        track: Some(point.track),
+        eph: Some(2.5),
+        epv: Some(4.0),
+        eps: Some(0.3),
+        epd: Some(5.0),
+    };
+
+    // A fixed constellation is good enough to exercise the sky view.
+    let satellites = [(2, 67.0, 120.0, 44.0), (5, 45.0, 310.0, 40.0), (12, 30.0, 45.0, 37.0), (15, 22.0, 200.0, 33.0),
+                      (18, 55.0, 260.0, 42.0), (24, 12.0, 80.0, 25.0), (25, 8.0, 170.0, 19.0), (29, 35.0, 15.0, 38.0)]
+        .iter()
+        .map(|&(prn, el, az, ss)| Satellite { prn, el: Some(el), az: Some(az), ss: Some(ss), used: ss > 20.0 })
+        .collect();
+    let sky = Sky {
+        device: Some(DEVICE.into()),
+        time: Some(time),
+        hdop: Some(0.9),
+        vdop: Some(1.4),
+        satellites,
+    };
+
+    (Report::Tpv(tpv), Report::Sky(sky))
+}
+
+/// Walk the track and stream reports to every watching client.
+fn playback(points: Vec<TrackPoint>, speed_factor: f64, repeat: bool, watchers: Watchers) {
+    loop {
+        let mut previous_time = points[0].time;
+        for point in &points {
+            let delay = (point.time - previous_time).max(0.0) / speed_factor;
+            std::thread::sleep(Duration::from_secs_f64(delay));
+            previous_time = point.time;
+
+            let (tpv, sky) = reports(point);
+            let tpv = serde_json::to_string(&tpv).unwrap();
+            let sky = serde_json::to_string(&sky).unwrap();
+
+            let mut clients = watchers.lock().unwrap();
+            clients.retain_mut(|client| send_line(client, &sky) && send_line(client, &tpv));
+            log::debug!("Served ({}, {}) to {} clients", point.latitude, point.longitude, clients.len());
+        }
+
+        if !repeat {
+            log::info!("End of track reached.");
+            return;
+        }
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let cli = CommandLineParameters::parse();
+
+    let points = if let Some(path) = &cli.gpx {
+        track::load_gpx(path)
+    } else if let Some(path) = &cli.nmea {
+        track::load_nmea(path)
+    } else {
+        Ok(match cli.pattern {
+            Pattern::Circle => track::circle(cli.latitude, cli.longitude, cli.size, cli.velocity),
+            Pattern::Lawnmower => track::lawnmower(cli.latitude, cli.longitude, cli.size, cli.spacing, cli.legs as usize, cli.velocity),
+        })
+    };
+    let points = match points {
+        Ok(val) if !val.is_empty() => val,
+        Ok(_) => {
+            log::error!("Track is empty.");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    log::info!("Loaded {} track points ({:.0} s).", points.len(), points[points.len() - 1].time);
+
+    let listener = match TcpListener::bind(("127.0.0.1", cli.port)) {
+        Ok(val) => val,
+        Err(e) => {
+            log::error!("Failed to bind to port {}: {:?}", cli.port, e);
+            std::process::exit(1);
+        }
+    };
+    log::info!("Fake gpsd listening on 127.0.0.1:{}", cli.port);
+
+    let watchers: Watchers = Arc::new(Mutex::new(Vec::new()));
+    let playback_watchers = watchers.clone();
+    let speed_factor = cli.speed_factor;
+    let repeat = cli.repeat;
+    std::thread::spawn(move || {
+        playback(points, speed_factor, repeat, playback_watchers);
+    });
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let watchers = watchers.clone();
+                std::thread::spawn(move || handle_client(stream, watchers));
+            }
+            Err(e) => log::error!("Failed to accept connection: {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Track sources for the fake gpsd server: recorded GPX/NMEA logs or generated paths.
+use std::fs;
+
+const EARTH_RADIUS: f64 = 6_371_000.0;
+const KNOTS_TO_MPS: f32 = 0.514_444;
+
+/// A single point of the track to be served.
+#[derive(Debug, Clone)]
+pub struct TrackPoint {
+    /// Time since the start of the track (s)
+    pub time: f64,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: f64,
+    /// Speed over ground (m/s)
+    pub speed: f32,
+    /// Course over ground (degrees from true north)
+    pub track: f32,
+}
+
+/// Distance (m) between two points.
+pub fn distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_phi = phi2 - phi1;
+    let d_lambda = (lon2 - lon1).to_radians();
+    let a = (d_phi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (d_lambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().atan2((1.0 - a).sqrt())
+}
+
+/// Initial bearing (degrees from true north) from the first point to the second.
+pub fn bearing(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lambda = (lon2 - lon1).to_radians();
+    let y = d_lambda.sin() * phi2.cos();
+    let x = phi1.cos() * phi2.sin() - phi1.sin() * phi2.cos() * d_lambda.cos();
+    (y.atan2(x).to_degrees() + 360.0) % 360.0
+}
+
+/// Move a point by north/east offsets in meters.
+fn offset(latitude: f64, longitude: f64, north: f64, east: f64) -> (f64, f64) {
+    let lat = latitude + (north / EARTH_RADIUS).to_degrees();
+    let lon = longitude + (east / (EARTH_RADIUS * latitude.to_radians().cos())).to_degrees();
+    (lat, lon)
+}
+
+/// Fill in speed and course from consecutive positions for logs that don't record them.
+fn fill_motion(points: &mut [TrackPoint]) {
+    for i in 1..points.len() {
+        let (prev, cur) = (&points[i - 1], &points[i]);
+        let dt = cur.time - prev.time;
+        if dt <= 0.0 {
+            continue;
+        }
+        let speed = (distance(prev.latitude, prev.longitude, cur.latitude, cur.longitude) / dt) as f32;
+        let track = bearing(prev.latitude, prev.longitude, cur.latitude, cur.longitude) as f32;
+        points[i].speed = speed;
+        points[i].track = track;
+    }
+    if points.len() > 1 {
+        points[0].speed = points[1].speed;
+        points[0].track = points[1].track;
+    }
+}
+
+/// Read the value of an xml attribute out of a tag.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let pattern = format!("{}=\"", name);
+    let start = tag.find(&pattern)? + pattern.len();
+    let end = tag.get(start..)?.find('"')? + start;
+    Some(tag.get(start..end)?.to_string())
+}
+
+/// Read the text content of an xml element.
+fn element(body: &str, name: &str) -> Option<String> {
+    let start = body.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = body.get(start..)?.find(&format!("</{}>", name))? + start;
+    Some(body.get(start..end)?.trim().to_string())
+}
+
+/// Load the track points out of a GPX file.
+pub fn load_gpx(path: &str) -> Result<Vec<TrackPoint>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let mut points = Vec::new();
+    let mut start_time = None;
+
+    for chunk in contents.split("<trkpt").skip(1) {
+        let tag_end = chunk.find('>').ok_or("Unterminated trkpt tag")?;
+        let tag = &chunk[..tag_end];
+        let body = match chunk.find("</trkpt>") {
+            Some(end) => &chunk[tag_end..end],
+            None => &chunk[tag_end..],
+        };
+
+        let latitude: f64 = attribute(tag, "lat").and_then(|v| v.parse().ok()).ok_or("trkpt without lat")?;
+        let longitude: f64 = attribute(tag, "lon").and_then(|v| v.parse().ok()).ok_or("trkpt without lon")?;
+        let altitude = element(body, "ele").and_then(|v| v.parse().ok()).unwrap_or(0.0);
+        let time = match element(body, "time").map(|v| chrono::DateTime::parse_from_rfc3339(&v)) {
+            Some(Ok(t)) => t.timestamp_micros() as f64 / 1e6,
+            // No timestamps, fall back on one point per second.
+            _ => points.len() as f64,
+        };
+        let start = *start_time.get_or_insert(time);
+
+        points.push(TrackPoint {
+            time: time - start,
+            latitude,
+            longitude,
+            altitude,
+            speed: 0.0,
+            track: 0.0,
+        });
+    }
+
+    if points.is_empty() {
+        return Err(format!("No track points found in {}", path));
+    }
+    fill_motion(&mut points);
+    Ok(points)
+}
+
+/// Convert an NMEA ddmm.mmmm coordinate and hemisphere into decimal degrees.
+fn nmea_coordinate(value: &str, hemisphere: &str) -> Option<f64> {
+    let raw: f64 = value.parse().ok()?;
+    let degrees = (raw / 100.0).trunc();
+    let decimal = degrees + (raw - degrees * 100.0) / 60.0;
+    match hemisphere {
+        "S" | "W" => Some(-decimal),
+        _ => Some(decimal),
+    }
+}
+
+/// Convert an NMEA hhmmss.ss time into seconds of the day.
+fn nmea_time(value: &str) -> Option<f64> {
+    let hours: f64 = value.get(0..2)?.parse().ok()?;
+    let minutes: f64 = value.get(2..4)?.parse().ok()?;
+    let seconds: f64 = value.get(4..)?.parse().ok()?;
+    Some(hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+/// Load the track out of an NMEA log. Positions come from RMC sentences, altitude from the last GGA sentence.
+pub fn load_nmea(path: &str) -> Result<Vec<TrackPoint>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let mut points: Vec<TrackPoint> = Vec::new();
+    let mut altitude = 0.0;
+    let mut day_offset = 0.0;
+    let mut start_time = None;
+
+    for line in contents.lines() {
+        // Strip the checksum, we trust the log.
+        let sentence = line.trim().split('*').next().unwrap_or("");
+        let fields: Vec<&str> = sentence.split(',').collect();
+        if fields[0].len() < 6 || !fields[0].starts_with('$') {
+            continue;
+        }
+
+        // Skip the talker id, e.g. $GP or $GN. A line of garbage may not split on a character boundary there.
+        match fields[0].get(3..).unwrap_or("") {
+            "GGA" if fields.len() > 9 => {
+                altitude = fields[9].parse().unwrap_or(altitude);
+            }
+            "RMC" if fields.len() > 8 => {
+                // Void fix
+                if fields[2] != "A" {
+                    continue;
+                }
+                let (Some(time), Some(latitude), Some(longitude)) = (
+                    nmea_time(fields[1]),
+                    nmea_coordinate(fields[3], fields[4]),
+                    nmea_coordinate(fields[5], fields[6]),
+                ) else {
+                    continue;
+                };
+
+                let start = *start_time.get_or_insert(time);
+                let mut time = time + day_offset - start;
+
+                // Handle logs that run past midnight.
+                if let Some(last) = points.last() {
+                    if time < last.time - 43200.0 {
+                        day_offset += 86400.0;
+                        time += 86400.0;
+                    }
+                }
+
+                points.push(TrackPoint {
+                    time,
+                    latitude,
+                    longitude,
+                    altitude,
+                    speed: fields[7].parse::<f32>().unwrap_or(0.0) * KNOTS_TO_MPS,
+                    track: fields[8].parse().unwrap_or(0.0),
+                });
+            }
+            _ => (),
+        }
+    }
+
+    if points.is_empty() {
+        return Err(format!("No valid RMC sentences found in {}", path));
+    }
+    Ok(points)
+}
+
+/// Generate one lap of a circle around the given center at a constant speed, one point per second.
+pub fn circle(latitude: f64, longitude: f64, radius: f64, speed: f64) -> Vec<TrackPoint> {
+    let circumference = 2.0 * std::f64::consts::PI * radius;
+    let duration = (circumference / speed).ceil() as usize;
+
+    (0..duration)
+        .map(|t| {
+            let angle = speed * t as f64 / radius;
+            let (lat, lon) = offset(latitude, longitude, radius * angle.cos(), radius * angle.sin());
+            TrackPoint {
+                time: t as f64,
+                latitude: lat,
+                longitude: lon,
+                altitude: 0.0,
+                speed: speed as f32,
+                // Moving clockwise, the course is 90 degrees ahead of the bearing from the center.
+                track: ((angle.to_degrees() + 90.0) % 360.0) as f32,
+            }
+        })
+        .collect()
+}
+
+/// Generate a lawnmower survey pattern: legs running north/south, stepping east, one point per second.
+pub fn lawnmower(latitude: f64, longitude: f64, leg_length: f64, spacing: f64, legs: usize, speed: f64) -> Vec<TrackPoint> {
+    // Corners of the pattern as north/east offsets from the start.
+    let mut corners: Vec<(f64, f64)> = vec![(0.0, 0.0)];
+    for leg in 0..legs {
+        let east = leg as f64 * spacing;
+        let north = if leg % 2 == 0 { leg_length } else { 0.0 };
+        corners.push((north, east));
+        if leg + 1 < legs {
+            corners.push((north, east + spacing));
+        }
+    }
+
+    let mut points = Vec::new();
+    let mut time = 0.0;
+    for pair in corners.windows(2) {
+        let ((n0, e0), (n1, e1)) = (pair[0], pair[1]);
+        let length = (n1 - n0).hypot(e1 - e0);
+        let track = ((e1 - e0).atan2(n1 - n0).to_degrees() + 360.0) % 360.0;
+        let steps = (length / speed).ceil() as usize;
+        for step in 0..steps {
+            let fraction = step as f64 / steps as f64;
+            let (lat, lon) = offset(latitude, longitude, n0 + (n1 - n0) * fraction, e0 + (e1 - e0) * fraction);
+            points.push(TrackPoint {
+                time,
+                latitude: lat,
+                longitude: lon,
+                altitude: 0.0,
+                speed: speed as f32,
+                track: track as f32,
+            });
+            time += 1.0;
+        }
+    }
+    points
+}
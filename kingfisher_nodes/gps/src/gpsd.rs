@@ -0,0 +1,131 @@
+//! Minimal client side of the gpsd JSON protocol (https://gpsd.gitlab.io/gpsd/gpsd_json.html)
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+pub const DEFAULT_HOST: &str = "localhost";
+pub const DEFAULT_PORT: u16 = 2947;
+
+/// Command sent to gpsd to start streaming json reports.
+pub const WATCH_COMMAND: &str = "?WATCH={\"enable\":true,\"json\":true};\n";
+
+/// Time-Position-Velocity report
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Tpv {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// 0 = unknown, 1 = no fix, 2 = 2D, 3 = 3D
+    pub mode: u8,
+    /// 0 = unknown, 1 = normal, 2 = DGPS, 3 = RTK fixed, 4 = RTK float, 5 = DR, 6 = GNSS + DR, 7 = time only, 8 = simulated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lat: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lon: Option<f64>,
+    #[serde(rename = "altHAE", skip_serializing_if = "Option::is_none")]
+    pub alt_hae: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub track: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eph: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub epv: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eps: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub epd: Option<f32>,
+}
+
+/// A single satellite entry of a SKY report
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Satellite {
+    #[serde(rename = "PRN")]
+    pub prn: i16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub el: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub az: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ss: Option<f32>,
+    /// Not every receiver reports it, the satellite counts as unused then
+    #[serde(default)]
+    pub used: bool,
+}
+
+/// Sky view report
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Sky {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hdop: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vdop: Option<f32>,
+    #[serde(default)]
+    pub satellites: Vec<Satellite>,
+}
+
+/// Reports we care about. Everything else (VERSION, DEVICES, WATCH, ...) is parsed as Other.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "class")]
+pub enum Report {
+    #[serde(rename = "TPV")]
+    Tpv(Tpv),
+    #[serde(rename = "SKY")]
+    Sky(Sky),
+    #[serde(other)]
+    Other,
+}
+
+/// Connection to a gpsd daemon that is streaming json reports.
+pub struct GpsdConnection {
+    reader: BufReader<TcpStream>,
+    line: String,
+}
+
+impl GpsdConnection {
+    /// Connect to gpsd and enable watch mode. Reads give up with WouldBlock or TimedOut after `read_timeout`.
+    pub fn connect(host: &str, port: u16, read_timeout: Duration) -> std::io::Result<Self> {
+        let mut stream = TcpStream::connect((host, port))?;
+        stream.set_read_timeout(Some(read_timeout))?;
+        stream.write_all(WATCH_COMMAND.as_bytes())?;
+        Ok(GpsdConnection {
+            reader: BufReader::new(stream),
+            line: String::new(),
+        })
+    }
+
+    /// Wait for the next report, up to the read timeout.
+    pub fn next_report(&mut self) -> std::io::Result<Report> {
+        // A read that timed out keeps the part of the line it got, this one carries on from there.
+        if self.reader.read_line(&mut self.line)? == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "gpsd closed the connection"));
+        }
+        let report = serde_json::from_str(self.line.trim())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+        self.line.clear();
+        report
+    }
+}
+
+/// Convert a gpsd ISO8601 time string into seconds since the unix epoch.
+pub fn parse_time(time: &Option<String>) -> f64 {
+    match time {
+        Some(val) => match chrono::DateTime::parse_from_rfc3339(val) {
+            Ok(t) => t.timestamp_micros() as f64 / 1e6,
+            Err(e) => {
+                log::error!("Failed to parse gpsd time {}: {:?}", val, e);
+                f64::NAN
+            }
+        },
+        None => f64::NAN,
+    }
+}
@@ -1,86 +1,164 @@
 //! Program that publishes GPS data to DDS
-use kingfisher_data_types::{dds_topics::{GpsData, GpsFix, GPS_TOPIC}, DEFAULT_ID};
-use dust_dds::{
-    domain::domain_participant_factory::DomainParticipantFactory,
-    infrastructure::{qos::QosKind, status::NO_STATUS},
-};
-use gpsd_client::*;
+use kingfisher_data_types::dds_topics::{GpsData, GpsFix, GpsSatellites, SatelliteInfo};
+use kingfisher_data_types::topic_registry::{publish_blocking, GpsSatellitesTopic, GpsTopic};
+use kingfisher_node::{Node, NodeArgs};
+use dust_dds::infrastructure::{qos::QosKind, status::NO_STATUS};
+use gps::gpsd::{self, GpsdConnection, Report, Sky, Tpv};
+use clap::Parser;
 
-const UPDATE_RATE: u64 = 1;
+/// Delay before trying to reconnect to gpsd
+const RECONNECT_DELAY: u64 = 1;
+/// Longest wait for a report before checking for a shutdown
+const READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Parser for command line parameters
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct CommandLineParameters {
+    #[command(flatten)]
+    node: NodeArgs,
+
+    /// Host running gpsd
+    #[arg(long, default_value_t = String::from(gpsd::DEFAULT_HOST))]
+    host: String,
+
+    /// Port gpsd is listening on
+    #[arg(short, long, default_value_t = gpsd::DEFAULT_PORT)]
+    port: u16,
+}
+
+/// Map the gpsd mode and status fields onto our fix type.
+fn fix_type(tpv: &Tpv) -> GpsFix {
+    match (tpv.mode, tpv.status) {
+        (0..=1, _) => GpsFix::None,
+        (_, Some(2)) => GpsFix::DGps,
+        (_, Some(3)) => GpsFix::RtkFixed,
+        (_, Some(4)) => GpsFix::RtkFloat,
+        (_, Some(5)) | (_, Some(6)) => GpsFix::DeadReckoning,
+        (2, _) => GpsFix::Fix2D,
+        _ => GpsFix::Fix3D
+    }
+}
 
 fn main() {
-    //switch this to syslog later: https://rust-lang-nursery.github.io/rust-cookbook/development_tools/debugging/log.html#log-to-the-unix-syslog
-    env_logger::init();
-    log::info!("Starting GPS publisher.");
-    
-    // Connecting to the gpsd socket server.
-    let mut gps: GPS = match GPS::connect() {
-        Ok(t) => t,
-        Err(e) => {
-            log::error!("Failed to connect to gpsd daemon: {e}");
-            panic!("Failed to connect to gpsd_daemon");
-        }
-    };
+    let cli = CommandLineParameters::parse();
+    let node = Node::init("gps", env!("CARGO_PKG_VERSION"), &cli.node, "./gps.toml");
     
     //Set up DDS topic and participant.
-    let domain_id = kingfisher_data_types::DEFAULT_DOMAIN;
-    let participant_factory = DomainParticipantFactory::get_instance();
-    
-    let participant = participant_factory
-    .create_participant(domain_id, QosKind::Default, None, NO_STATUS)
-    .unwrap();
-    
-    let topic_gps = participant
-    .create_topic::<GpsData>(GPS_TOPIC, "GpsData", QosKind::Default, None, NO_STATUS)
-    .unwrap();
+    let participant = node.participant();
     
     let publisher = participant
     .create_publisher(QosKind::Default, None, NO_STATUS)
     .unwrap();
     
-    let gps_writer = publisher
-    .create_datawriter::<GpsData>(&topic_gps, QosKind::Default, None, NO_STATUS)
-    .unwrap();
-    
-    loop {
-        // Getting the data from the gps device.
-        match gps.current_data() {
-            Ok(data) => {
+    let gps_writer = publish_blocking::<GpsTopic>(&participant, &publisher).unwrap();
+    let satellites_writer = publish_blocking::<GpsSatellitesTopic>(&participant, &publisher).unwrap();
 
-                log::info!("{:?}", data);
-                let fix = match data.mode {
-                    Fix::None => GpsFix::None,
-                    Fix::Fix2D => GpsFix::Fix2D,
-                    Fix::Fix3D => GpsFix::Fix3D
-                };
-                
-                let gps_data = GpsData {
-                    id: DEFAULT_ID.into(),
-                    latitude: data.lat,
-                    longitude: data.lon,
-                    altitude: data.alt_hae,
-                    velocity: data.speed,
-                    direction: data.track,
-                    fix: fix,
-                    good_satellites: data.sats_valid 
-                };
-                
-                match gps_writer.write(&gps_data, None) {
-                    Ok(_) => {
-                        log::info!("GPS data published.");
-                    } Err(e) => {
-                        log::error!("Failed to write GPS data to DDS: {:?}", e);
-                    }
-                };
+    // The DOP values and satellite counts only come in SKY reports, so keep the last one around for the TPV reports.
+    let mut last_sky = Sky::default();
+    let mut ready = false;
+
+    while node.running() {
+        // Connecting to the gpsd socket server.
+        let mut gps = match GpsdConnection::connect(&cli.host, cli.port, READ_TIMEOUT) {
+            Ok(t) => {
+                node.clear_error();
+                // Up once gpsd is, the first time round.
+                if !ready {
+                    node.ready();
+                    ready = true;
+                }
+                t
             },
             Err(e) => {
-                log::error!("Could not read GPS data from gpsd: {:?}", e);
+                node.report_error(&format!("Failed to connect to gpsd daemon: {e}"));
+                node.sleep(std::time::Duration::from_secs(RECONNECT_DELAY));
+                continue;
+            }
+        };
+
+        while node.running() {
+            // Getting the data from the gps device.
+            let report = match gps.next_report() {
+                Ok(val) => val,
+                Err(e) => match e.kind() {
+                    // Nothing from gpsd for a while, e.g. no device attached.
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => continue,
+                    std::io::ErrorKind::InvalidData => {
+                        log::error!("Could not parse gpsd report: {:?}", e);
+                        continue;
+                    },
+                    _ => {
+                        node.report_error(&format!("Could not read GPS data from gpsd: {:?}", e));
+                        break;
+                    }
+                }
+            };
+
+            match report {
+                Report::Tpv(data) => {
+                    log::debug!("{:?}", data);
+                    let gps_data = GpsData {
+                        id: node.vehicle_id().into(),
+                        time: gpsd::parse_time(&data.time),
+                        latitude: data.lat.unwrap_or(f64::NAN),
+                        longitude: data.lon.unwrap_or(f64::NAN),
+                        altitude: data.alt_hae.unwrap_or(f64::NAN),
+                        velocity: data.speed.unwrap_or(f32::NAN),
+                        direction: data.track.unwrap_or(f32::NAN),
+                        fix: fix_type(&data),
+                        good_satellites: last_sky.satellites.iter().filter(|s| s.used).count() as u8,
+                        visible_satellites: last_sky.satellites.len() as u8,
+                        hdop: last_sky.hdop.unwrap_or(f32::NAN),
+                        vdop: last_sky.vdop.unwrap_or(f32::NAN),
+                        horizontal_error: data.eph.unwrap_or(f32::NAN),
+                        vertical_error: data.epv.unwrap_or(f32::NAN),
+                        velocity_error: data.eps.unwrap_or(f32::NAN),
+                        direction_error: data.epd.unwrap_or(f32::NAN)
+                    };
+                    
+                    match gps_writer.write(&gps_data, None) {
+                        Ok(_) => {
+                            log::debug!("GPS data published.");
+                            node.progress();
+                        } Err(e) => {
+                            log::error!("Failed to write GPS data to DDS: {:?}", e);
+                        }
+                    };
+                },
+                Report::Sky(data) => {
+                    // Some receivers send partial SKY reports without the satellite list, keep the previous one in that case.
+                    if data.satellites.is_empty() {
+                        last_sky.hdop = data.hdop.or(last_sky.hdop);
+                        last_sky.vdop = data.vdop.or(last_sky.vdop);
+                        continue;
+                    }
+
+                    let satellites = GpsSatellites {
+                        id: node.vehicle_id().into(),
+                        time: gpsd::parse_time(&data.time),
+                        satellites: data.satellites.iter().map(|s| SatelliteInfo {
+                            prn: s.prn,
+                            elevation: s.el.unwrap_or(f32::NAN),
+                            azimuth: s.az.unwrap_or(f32::NAN),
+                            snr: s.ss.unwrap_or(0.0),
+                            used: s.used
+                        }).collect()
+                    };
+
+                    match satellites_writer.write(&satellites, None) {
+                        Ok(_) => {
+                            log::debug!("GPS satellites published.");
+                        } Err(e) => {
+                            log::error!("Failed to write GPS satellites to DDS: {:?}", e);
+                        }
+                    };
+                    last_sky = data;
+                },
+                Report::Other => ()
             }
         }
-        
-        
-        std::thread::sleep(std::time::Duration::from_secs(UPDATE_RATE));
     }
-    
-    //gps.close(); //unreachable
-}
\ No newline at end of file
+
+    node.close(participant);
+}
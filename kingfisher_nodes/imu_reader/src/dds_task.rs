@@ -2,48 +2,32 @@
 use kingfisher_data_types::imu_types::ImuMessages;
 use tokio::sync::mpsc;
 
-use kingfisher_data_types::{dds_topics::{ImuData, IMU_TOPIC}, DEFAULT_ID};
-use dust_dds::{
-    domain::domain_participant_factory::DomainParticipantFactory,
-    infrastructure::{qos::QosKind, status::NO_STATUS},
-};
+use kingfisher_data_types::dds_topics::ImuData;
+use dust_dds::publication::data_writer::DataWriter;
+use kingfisher_node::Watchdog;
 use std::time::SystemTime;
 
 pub struct DDSTask {
-    from_serial: mpsc::Receiver<ImuMessages>
+    from_serial: mpsc::Receiver<ImuMessages>,
+    imu_writer: DataWriter<ImuData>,
+    vehicle_id: String,
+    watchdog: Watchdog
 }
 
 impl DDSTask {
     
     /// Create a new DDS Task
-    pub fn new (from_serial: mpsc::Receiver<ImuMessages>) -> Self {
+    pub fn new (from_serial: mpsc::Receiver<ImuMessages>, imu_writer: DataWriter<ImuData>, vehicle_id: String, watchdog: Watchdog) -> Self {
         DDSTask {
-            from_serial
+            from_serial,
+            imu_writer,
+            vehicle_id,
+            watchdog
         }
     }
     
     /// Create a new DDS task.
     pub async fn run(&mut self) {
-        //Set up DDS topic and participant.
-        let domain_id = kingfisher_data_types::DEFAULT_DOMAIN;
-        let participant_factory = DomainParticipantFactory::get_instance();
-        
-        let participant = participant_factory
-        .create_participant(domain_id, QosKind::Default, None, NO_STATUS)
-        .unwrap();
-        
-        let topic_imu = participant
-        .create_topic::<ImuData>(IMU_TOPIC, "ImuData", QosKind::Default, None, NO_STATUS)
-        .unwrap();
-        
-        let publisher = participant
-        .create_publisher(QosKind::Default, None, NO_STATUS)
-        .unwrap();
-        
-        let imu_writer = publisher
-        .create_datawriter::<ImuData>(&topic_imu, QosKind::Default, None, NO_STATUS)
-        .unwrap();
-
         loop {
             match self.from_serial.recv().await {
                 Some(val) => {
@@ -58,16 +42,17 @@ impl DDSTask {
                                 }
                             };
                             let imu_data = ImuData {
-                                id: DEFAULT_ID.into(),
+                                id: self.vehicle_id.clone(),
                                 time: current_time, 
                                 accelerometer: vec!(ax, ay, az),
                                 gyroscope: vec!(gx, gy, gz),
                                 magnetometer: vec!(mx, my, mz)
                             };
                             log::info! ("{:?}", imu_data);
-                            match imu_writer.write(&imu_data, None) {
+                            match self.imu_writer.write(&imu_data, None) {
                                 Ok(_) => {
                                     //log::info!("IMU data published.");
+                                    self.watchdog.pet();
                                 } Err(e) => {
                                     log::error!("Failed to write IMU data to DDS: {:?}", e);
                                 }
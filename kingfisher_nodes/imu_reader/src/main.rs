@@ -1,7 +1,9 @@
 use tokio::sync::mpsc;
 use tokio_serial::SerialPortBuilderExt;
-use tokio::signal;
 use clap::Parser;
+use dust_dds::infrastructure::{qos::QosKind, status::NO_STATUS};
+use kingfisher_data_types::topic_registry::{publish_blocking, ImuTopic};
+use kingfisher_node::{Node, NodeArgs};
 
 mod dds_task;
 mod serial_task;
@@ -13,6 +15,9 @@ use crate::dds_task::DDSTask;
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    #[command(flatten)]
+    node: NodeArgs,
+
     ///The device path to a serial port
     #[arg(short, long, default_value_t = String::from("/dev/boat_control") )]
     port: String,
@@ -25,10 +30,8 @@ struct Cli {
 
 #[tokio::main]
 async fn main() {
-    pretty_env_logger::init();
-
-    log::info!("Starting the IMU interface.");
     let cli = Cli::parse();
+    let node = Node::init("imu_reader", env!("CARGO_PKG_VERSION"), &cli.node, "./imu_reader.toml");
 
     let port_name = &cli.port;
     let baud_rate = cli.baudrate;
@@ -53,17 +56,18 @@ async fn main() {
         serial_task.run().await;
     });
 
-    let mut dds_task = DDSTask::new(serial_rx);
+    let participant = node.participant();
+    let publisher = participant
+        .create_publisher(QosKind::Default, None, NO_STATUS)
+        .unwrap();
+    let imu_writer = publish_blocking::<ImuTopic>(&participant, &publisher).unwrap();
+
+    let mut dds_task = DDSTask::new(serial_rx, imu_writer, node.vehicle_id().to_string(), node.watchdog());
     tokio::spawn(async move {
         dds_task.run().await;
     });
 
-    match signal::ctrl_c().await {
-        Ok(()) => {},
-        Err(err) => {
-            eprintln!("Unable to listen for shutdown signal: {}", err);
-            // we also shut down in case of error
-        },
-    }
-
-}
\ No newline at end of file
+    node.ready();
+    node.wait_for_shutdown().await;
+    node.close(participant);
+}
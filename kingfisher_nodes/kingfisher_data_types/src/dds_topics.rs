@@ -1,41 +1,100 @@
 //! This module contains the hard coded names of various DDS topics use by different systems.
 
 use dust_dds::topic_definition::type_support::DdsType;
-use serde::Serialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 pub const MICROCONTROLLER_STATUS_TOPIC: &str = "mcu_status";
 pub const MICROCONTROLLER_CONTROL_TOPIC: &str = "mcu_control";
 pub const GPS_TOPIC: &str = "gps_data";
+pub const GPS_SATELLITES_TOPIC: &str = "gps_satellites";
 pub const IMU_TOPIC: &str = "imu_data";
 
+pub const MISSION_TOPIC: &str = "mission";
+pub const GEOFENCE_TOPIC: &str = "geofence";
+pub const GO_TO_TOPIC: &str = "mission/go_to";
+pub const MISSION_COMMAND_TOPIC: &str = "mission/command";
+pub const MISSION_STATUS_TOPIC: &str = "mission/status";
+pub const NAVIGATION_SETPOINT_TOPIC: &str = "navigation/setpoint";
+pub const CONTROLLER_GAINS_TOPIC: &str = "controller/gains";
+pub const CONTROLLER_STATUS_TOPIC: &str = "controller/status";
+
 pub const SYSTEM_STATUS_CPU_TOPIC: &str = "system_status/cpu";
 pub const SYSTEM_STATUS_MEMORY_TOPIC: &str = "system_status/memory";
 pub const SYSTEM_STATUS_NETWORK_TOPIC: &str = "system_status/network";
 pub const SYSTEM_STATUS_DISK_TOPIC: &str = "system_status/disk";
+pub const SYSTEM_STATUS_THERMAL_TOPIC: &str = "system_status/thermal";
+pub const SYSTEM_STATUS_PROCESSES_TOPIC: &str = "system_status/processes";
+
+pub const LOGGER_COMMAND_TOPIC: &str = "data_logger/command";
+pub const LOGGER_STATUS_TOPIC: &str = "data_logger/status";
 
-#[derive(DdsType, Debug, Clone, Serialize)]
+pub const NODE_HEARTBEAT_TOPIC: &str = "node/heartbeat";
+pub const SYSTEM_HEALTH_TOPIC: &str = "system_health";
+pub const ALARM_TOPIC: &str = "alarm";
+
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum GpsFix {
     None,
     Fix2D,
-    Fix3D
+    Fix3D,
+    DGps,
+    RtkFloat,
+    RtkFixed,
+    DeadReckoning
 }
 
 ///GPS Types
-#[derive(DdsType, Debug, Clone, Serialize)]
+/// Accuracy estimates are the gpsd 95% confidence values, NaN if the receiver doesn't report them.
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct GpsData {
     #[dust_dds(key)]
     pub id: String,
+    /// GPS time of the fix in seconds since the unix epoch
+    pub time: f64,
     pub latitude: f64,
     pub longitude: f64,
     pub altitude: f64,
     pub velocity: f32,
     pub direction: f32,
     pub fix: GpsFix,
-    pub good_satellites: u8
+    pub good_satellites: u8,
+    pub visible_satellites: u8,
+    pub hdop: f32,
+    pub vdop: f32,
+    /// Horizontal position error (m)
+    pub horizontal_error: f32,
+    /// Vertical position error (m)
+    pub vertical_error: f32,
+    /// Speed error (m/s)
+    pub velocity_error: f32,
+    /// Direction error (degrees)
+    pub direction_error: f32
+}
+
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SatelliteInfo {
+    pub prn: i16,
+    /// Elevation in degrees
+    pub elevation: f32,
+    /// Azimuth in degrees from true north
+    pub azimuth: f32,
+    /// Signal to noise ratio (dBHz)
+    pub snr: f32,
+    pub used: bool
+}
+
+///Sky view from the last gpsd SKY report
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GpsSatellites {
+    #[dust_dds(key)]
+    pub id: String,
+    pub time: f64,
+    pub satellites: Vec<SatelliteInfo>
 }
 
 ///IMU Types
-#[derive(DdsType, Debug, Clone, Serialize)]
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ImuData {
     #[dust_dds(key)]
     pub id: String,
@@ -45,22 +104,266 @@ pub struct ImuData {
     pub magnetometer: Vec<f32>,
 }
 
+///Microcontroller types
+
+/// Tele-operation command for the microcontroller, sent at a steady rate while a driver is in control. The
+/// microcontroller node stops the motors when the commands stop for longer than the control deadline.
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MicroControl {
+    #[dust_dds(key)]
+    pub id: String,
+    /// Who sends the commands, only one sender at a time can drive
+    pub source: String,
+    /// The throttles are only applied while armed, disarming stops the motors
+    pub armed: bool,
+    pub port_light: bool,
+    pub starboard_light: bool,
+    pub port_power: bool,
+    pub starboard_power: bool,
+    /// Throttle from -1 (full reverse) to 1 (full ahead)
+    pub port_throttle: f32,
+    pub starboard_throttle: f32,
+    /// Time the command was sent in seconds since the unix epoch
+    pub time: f64
+}
+
+/// Outputs of the microcontroller and the raw readings of the RC receiver, as last reported by the microcontroller.
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MicroStatus {
+    #[dust_dds(key)]
+    pub id: String,
+    pub port_light: bool,
+    pub starboard_light: bool,
+    pub port_power: bool,
+    pub starboard_power: bool,
+    /// Throttle being applied from -1 (full reverse) to 1 (full ahead)
+    pub port_throttle: f32,
+    pub starboard_throttle: f32,
+    /// The RC override switch is on. The microcontroller ignores the throttles sent over DDS until it's off, the
+    /// lights and relays still follow the commands.
+    pub rc_override: bool,
+    /// Raw RC receiver readings (ADC counts)
+    pub rc_throttle: u16,
+    pub rc_turn: u16,
+    pub rc_switch: u16,
+    /// Source of the commands currently driving, empty if no one is
+    pub driver: String,
+    /// Time of the report in seconds since the unix epoch
+    pub time: f64
+}
+
+///Navigation types
+
+/// A position on the WGS84 ellipsoid, in degrees.
+#[derive(DdsType, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GeoPoint {
+    pub latitude: f64,
+    pub longitude: f64
+}
+
+#[derive(DdsType, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Waypoint {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// The waypoint counts as reached within this distance (m)
+    pub acceptance_radius: f32,
+    /// Speed to travel to the waypoint at (m/s)
+    pub speed: f32,
+    /// Time to hold position once the waypoint is reached (s)
+    pub loiter_time: f32
+}
+
+/// Waypoints for a vehicle to visit in order. A new mission replaces the previous one.
+#[derive(DdsType, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Mission {
+    #[dust_dds(key)]
+    pub id: String,
+    pub name: String,
+    pub waypoints: Vec<Waypoint>
+}
+
+/// A polygon the vehicle has to stay inside of (keep in) or outside of.
+#[derive(DdsType, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GeofenceZone {
+    pub name: String,
+    pub keep_in: bool,
+    /// Corners in order, the last one joins back to the first
+    pub points: Vec<GeoPoint>
+}
+
+#[derive(DdsType, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Geofence {
+    #[dust_dds(key)]
+    pub id: String,
+    pub zones: Vec<GeofenceZone>
+}
+
+/// Head straight to a position, e.g. clicked on the dashboard map. Replaces the current mission.
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GoTo {
+    #[dust_dds(key)]
+    pub id: String,
+    /// Who sent the request
+    pub source: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Time the request was sent in seconds since the unix epoch
+    pub time: f64
+}
+
+#[derive(DdsType, Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum MissionCommandKind {
+    /// Start the loaded mission from its first waypoint
+    Start,
+    Pause,
+    Resume,
+    /// Stop the mission, it has to be started again from the beginning
+    Abort,
+    /// Give up on the current waypoint and head for the next one
+    Skip,
+    /// Load a mission file from the mission directory, replacing the current mission
+    Load,
+    /// Save the current mission into the mission directory
+    Save
+}
+
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MissionCommand {
+    #[dust_dds(key)]
+    pub id: String,
+    pub command: MissionCommandKind,
+    /// File name in the mission directory, used by Load and Save. The extension, .json or .gpx, sets the format.
+    pub file: String
+}
+
+#[derive(DdsType, Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum MissionState {
+    /// No mission running
+    Idle,
+    /// Heading for the current waypoint
+    Running,
+    /// Holding position at the current waypoint for its loiter time
+    Loitering,
+    Paused,
+    /// Every waypoint was reached
+    Completed,
+    Aborted
+}
+
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MissionStatus {
+    #[dust_dds(key)]
+    pub id: String,
+    pub mission_name: String,
+    pub state: MissionState,
+    /// Index of the waypoint being headed for
+    pub current_waypoint: u32,
+    pub waypoint_count: u32,
+    /// Distance to the current waypoint (m), NaN without a GPS fix
+    pub distance_to_waypoint: f32,
+    /// Bearing to the current waypoint in degrees from true north, NaN without a GPS fix
+    pub bearing_to_waypoint: f32,
+    /// Loiter time left at the current waypoint (s)
+    pub loiter_remaining: f32,
+    /// Why the last command or mission was rejected, or why the vehicle isn't moving, empty if all is well
+    pub message: String,
+    /// Time of the status in seconds since the unix epoch
+    pub time: f64
+}
+
+/// What the vehicle should be doing, for the controller node. Published at a steady rate.
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NavigationSetpoint {
+    #[dust_dds(key)]
+    pub id: String,
+    /// Whether to drive at all, the controller stops the thrusters otherwise
+    pub active: bool,
+    /// Heading to steer in degrees from true north
+    pub heading: f32,
+    /// Speed over ground (m/s)
+    pub speed: f32,
+    /// Time of the setpoint in seconds since the unix epoch
+    pub time: f64
+}
+
+/// Gains of the heading and speed controller. The throttles are from -1 to 1, as on the control topic.
+#[derive(DdsType, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ControllerGains {
+    /// Turn throttle per degree of heading error
+    pub heading_kp: f32,
+    /// Turn throttle per degree second of heading error
+    pub heading_ki: f32,
+    /// Turn throttle per degree per second of yaw rate, damps the turns
+    pub heading_kd: f32,
+    /// Largest turn throttle the integral term may build up
+    pub integral_limit: f32,
+    /// Forward throttle per m/s of the speed setpoint
+    pub speed_feed_forward: f32,
+    /// Forward throttle per m/s of speed error
+    pub speed_kp: f32,
+    /// Throttle limit of each thruster, at most 1
+    pub max_throttle: f32
+}
+
+/// New gains for the controller of a vehicle, applied straight away.
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ControllerTuning {
+    #[dust_dds(key)]
+    pub id: String,
+    /// Who sent the gains
+    pub source: String,
+    pub gains: ControllerGains
+}
+
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ControllerStatus {
+    #[dust_dds(key)]
+    pub id: String,
+    /// Whether the controller is driving the thrusters
+    pub engaged: bool,
+    /// Why the controller isn't driving although it has an active setpoint, empty if it is or has none
+    pub message: String,
+    /// Heading being steered for and the estimate of the current one (degrees), NaN when unknown
+    pub heading_setpoint: f32,
+    pub heading: f32,
+    /// Yaw rate from the IMU (degrees/s), NaN when unknown
+    pub yaw_rate: f32,
+    pub speed_setpoint: f32,
+    /// Speed over ground from the GPS (m/s), NaN when unknown
+    pub speed: f32,
+    /// Turn throttle built up by the integral term
+    pub integral: f32,
+    pub port_throttle: f32,
+    pub starboard_throttle: f32,
+    pub gains: ControllerGains,
+    /// Time of the status in seconds since the unix epoch
+    pub time: f64
+}
+
 ///Types for System Status
 
-#[derive(DdsType, Debug, Clone, Serialize)]
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct CpuInfo {
     pub name: String,
-    pub usage: f32
+    pub usage: f32,
+    /// Current clock (MHz), drops when the CPU throttles
+    pub frequency: u64
 }
 
-#[derive(DdsType, Debug, Clone, Serialize)]
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SystemStatusCpu {
     #[dust_dds(key)]
     pub id: String,
-    pub cpus: Vec<CpuInfo>
+    pub cpus: Vec<CpuInfo>,
+    /// Load averages over 1, 5 and 15 minutes
+    pub load_one: f64,
+    pub load_five: f64,
+    pub load_fifteen: f64,
+    /// Time since boot (s)
+    pub uptime: u64
 }
 
-#[derive(DdsType, Debug, Clone, Serialize)]
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SystemStatusMemory {
     #[dust_dds(key)]
     pub id: String,
@@ -70,7 +373,7 @@ pub struct SystemStatusMemory {
     pub used_swap: u64
 }
 
-#[derive(DdsType, Debug, Clone, Serialize)]
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct NetworkInfo {
     pub name: String,
     pub ip_address: Vec<String>,
@@ -78,25 +381,222 @@ pub struct NetworkInfo {
     pub bytes_received: u64,
     pub transmit_errors: u64,
     pub receive_errors: u64,
+    /// Throughput since the previous update (bytes/s)
+    pub send_rate: f64,
+    pub receive_rate: f64,
+    /// Wireless link quality in the driver's units, NaN for wired interfaces and below when not reported
+    pub link_quality: f32,
+    /// Wireless signal level (dBm)
+    pub signal: f32,
+    /// Wireless noise level (dBm)
+    pub noise: f32,
+    /// Wireless transmit bitrate (Mbit/s)
+    pub bitrate: f32,
 }
 
-#[derive(DdsType, Debug, Clone, Serialize)]
+/// Round trip to a host probed by state_monitor, e.g. the shore station.
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PingInfo {
+    pub host: String,
+    /// Round trip time of the last probe (ms), NaN if it was lost
+    pub rtt: f32,
+    /// Lost probes over the last 20 (%)
+    pub loss: f32
+}
+
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SystemStatusNetwork {
     #[dust_dds(key)]
     pub id: String,
-    pub network_info: Vec<NetworkInfo>    
+    pub network_info: Vec<NetworkInfo>,
+    pub pings: Vec<PingInfo>
 }
 
-#[derive(DdsType, Debug, Clone, Serialize)]
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DiskInfo {
     pub name: String,
     pub bytes_used: u64,
     pub bytes_available: u64
 }
 
-#[derive(DdsType, Debug, Clone, Serialize)]
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SystemStatusDisk {
     #[dust_dds(key)]
     pub id: String,
     pub disk_info: Vec<DiskInfo>,
-}
\ No newline at end of file
+}
+
+/// Temperatures in degrees C, NaN if the sensor doesn't report the value.
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ThermalInfo {
+    pub name: String,
+    pub temperature: f32,
+    /// Highest temperature seen since the sensor was read first
+    pub max: f32,
+    /// Temperature the hardware shuts down or throttles at
+    pub critical: f32
+}
+
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SystemStatusThermal {
+    #[dust_dds(key)]
+    pub id: String,
+    pub sensors: Vec<ThermalInfo>
+}
+
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ProcessInfo {
+    pub name: String,
+    /// False if no process with the name is running, the other fields are then from the last one seen
+    pub running: bool,
+    pub pid: u32,
+    /// CPU usage (%), can go over 100 on multi core systems
+    pub cpu_usage: f32,
+    /// Resident memory (bytes)
+    pub memory: u64,
+    /// Time since the process started (s)
+    pub run_time: u64,
+    /// Number of times a new process with the name showed up since state_monitor started
+    pub restarts: u32
+}
+
+/// The kingfisher node processes listed in the state_monitor config.
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SystemStatusProcesses {
+    #[dust_dds(key)]
+    pub id: String,
+    pub processes: Vec<ProcessInfo>
+}
+
+///Data logger types
+
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub enum LoggerCommandKind {
+    /// Start a new recording session, stopping the current one if needed
+    StartSession,
+    StopSession,
+    /// Add a text marker to the current session
+    AddMarker
+}
+
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LoggerCommand {
+    #[dust_dds(key)]
+    pub id: String,
+    pub command: LoggerCommandKind,
+    /// Session name, used by StartSession
+    pub name: String,
+    /// Free form session notes, used by StartSession
+    pub notes: String,
+    /// Marker text, used by AddMarker
+    pub marker: String
+}
+
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LoggerStatus {
+    #[dust_dds(key)]
+    pub id: String,
+    pub recording: bool,
+    pub session_name: String,
+    /// Path of the file currently being written for the logger's own vehicle, empty if not recording
+    pub current_file: String,
+    /// Vehicles being recorded, each into its own session directory
+    pub vehicles: Vec<String>,
+    /// Bytes written for all the vehicles
+    pub bytes_written: u64,
+    /// Session duration (s)
+    pub duration: f64,
+    /// Free space left on the save partition (bytes)
+    pub free_disk: u64
+}
+
+///Node health types
+
+#[derive(DdsType, Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum NodeState {
+    /// Setting up, not producing data yet
+    Starting,
+    Running,
+    /// Running, but something is wrong, see last_error
+    Degraded,
+    /// Shutting down cleanly
+    Stopping
+}
+
+/// Published at 1 Hz by every node.
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NodeHeartbeat {
+    #[dust_dds(key)]
+    pub id: String,
+    #[dust_dds(key)]
+    pub node: String,
+    pub pid: u32,
+    pub version: String,
+    /// Time since the node started (s)
+    pub uptime: f64,
+    pub state: NodeState,
+    /// Most recent error reported by the node, empty if none
+    pub last_error: String
+}
+
+#[derive(DdsType, Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum NodeHealthStatus {
+    /// Heartbeats arriving on time
+    Alive,
+    /// Heartbeats late, the node may be hung
+    Stale,
+    /// No heartbeat for a long time, or the node lost its liveliness
+    Missing,
+    /// The node shut down cleanly
+    Stopped
+}
+
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NodeHealth {
+    pub name: String,
+    pub status: NodeHealthStatus,
+    /// State from the last heartbeat, Starting if none was ever received
+    pub state: NodeState,
+    pub pid: u32,
+    pub version: String,
+    pub uptime: f64,
+    pub last_error: String,
+    /// Time since the last heartbeat (s), negative if none was ever received
+    pub age: f64
+}
+
+/// Summary of the nodes of a vehicle, published by the state_monitor supervisor.
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SystemHealth {
+    #[dust_dds(key)]
+    pub id: String,
+    /// True if every node is alive and none is degraded
+    pub healthy: bool,
+    pub nodes: Vec<NodeHealth>
+}
+
+///Alarm types
+
+#[derive(DdsType, Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum AlarmSeverity {
+    Info,
+    Warning,
+    Critical
+}
+
+/// Raised and cleared by the state_monitor alarm rules. Only changes are published, the last sample of every alarm
+/// is kept for late joiners.
+#[derive(DdsType, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Alarm {
+    #[dust_dds(key)]
+    pub id: String,
+    /// Rule name, followed by the disk, interface, sensor, process or topic it was raised for
+    #[dust_dds(key)]
+    pub name: String,
+    pub severity: AlarmSeverity,
+    pub message: String,
+    /// True when raised, false when cleared
+    pub raised: bool,
+    /// Time of the change in seconds since the unix epoch
+    pub time: f64
+}
@@ -0,0 +1,220 @@
+//! Distances and bearings between positions, for the map and the navigation nodes.
+//!
+//! Positions are on a sphere of the mean earth radius, which is well within the GPS error at the distances a
+//! vehicle covers.
+use crate::dds_topics::{GeoPoint, GeofenceZone, Waypoint};
+
+/// Mean earth radius (m)
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+impl GeoPoint {
+    pub fn new(latitude: f64, longitude: f64) -> Self {
+        GeoPoint { latitude, longitude }
+    }
+
+    /// Great circle distance (m).
+    pub fn distance_to(&self, other: &GeoPoint) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.longitude - self.longitude).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().atan2((1.0 - a).sqrt())
+    }
+
+    /// Initial bearing of the great circle to the other point, in degrees from true north (0..360).
+    pub fn bearing_to(&self, other: &GeoPoint) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let dlon = (other.longitude - self.longitude).to_radians();
+        let y = dlon.sin() * lat2.cos();
+        let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos();
+        y.atan2(x).to_degrees().rem_euclid(360.0)
+    }
+
+    /// The point at a distance (m) along a bearing (degrees from true north).
+    pub fn offset(&self, distance: f64, bearing: f64) -> GeoPoint {
+        let lat1 = self.latitude.to_radians();
+        let lon1 = self.longitude.to_radians();
+        let angle = distance / EARTH_RADIUS;
+        let bearing = bearing.to_radians();
+        let lat2 = (lat1.sin() * angle.cos() + lat1.cos() * angle.sin() * bearing.cos()).asin();
+        let lon2 = lon1
+            + (bearing.sin() * angle.sin() * lat1.cos()).atan2(angle.cos() - lat1.sin() * lat2.sin());
+        GeoPoint::new(lat2.to_degrees(), (lon2.to_degrees() + 540.0).rem_euclid(360.0) - 180.0)
+    }
+
+    /// East and north offsets (m) from an origin, on a plane tangent to the earth there. Only accurate for a few
+    /// kilometres around the origin.
+    pub fn local_from(&self, origin: &GeoPoint) -> (f64, f64) {
+        let east = (self.longitude - origin.longitude).to_radians() * EARTH_RADIUS * origin.latitude.to_radians().cos();
+        let north = (self.latitude - origin.latitude).to_radians() * EARTH_RADIUS;
+        (east, north)
+    }
+}
+
+impl From<&Waypoint> for GeoPoint {
+    fn from(waypoint: &Waypoint) -> Self {
+        GeoPoint::new(waypoint.latitude, waypoint.longitude)
+    }
+}
+
+impl GeofenceZone {
+    /// Whether a point is inside the polygon, whichever way round the zone is.
+    pub fn contains(&self, point: &GeoPoint) -> bool {
+        // Ray casting on the tangent plane of the point, crossing the edges east of it.
+        let corners: Vec<(f64, f64)> = self.points.iter().map(|p| p.local_from(point)).collect();
+        let mut inside = false;
+        for (i, &(x1, y1)) in corners.iter().enumerate() {
+            let (x2, y2) = corners[(i + 1) % corners.len()];
+            if (y1 > 0.0) != (y2 > 0.0) && x1 - y1 * (x2 - x1) / (y2 - y1) > 0.0 {
+                inside = !inside;
+            }
+        }
+        inside
+    }
+
+    /// Whether the vehicle may be at a point as far as this zone is concerned.
+    pub fn allows(&self, point: &GeoPoint) -> bool {
+        self.contains(point) == self.keep_in
+    }
+
+    /// Whether the straight line between two points crosses an edge of the polygon.
+    pub fn crosses(&self, from: &GeoPoint, to: &GeoPoint) -> bool {
+        // On the tangent plane at the start of the line, like contains.
+        let end = to.local_from(from);
+        let corners: Vec<(f64, f64)> = self.points.iter().map(|p| p.local_from(from)).collect();
+        (0..corners.len()).any(|i| segments_cross((0.0, 0.0), end, corners[i], corners[(i + 1) % corners.len()]))
+    }
+}
+
+/// Whether two line segments cross, each having the ends of the other on either side of it.
+fn segments_cross(a: (f64, f64), b: (f64, f64), c: (f64, f64), d: (f64, f64)) -> bool {
+    // Which side of the line through p and q the point r is on
+    let side = |p: (f64, f64), q: (f64, f64), r: (f64, f64)| {
+        ((q.0 - p.0) * (r.1 - p.1) - (q.1 - p.1) * (r.0 - p.0)).signum()
+    };
+    side(a, b, c) != side(a, b, d) && side(c, d, a) != side(c, d, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Length of one degree of a great circle (m)
+    const DEGREE: f64 = EARTH_RADIUS * std::f64::consts::PI / 180.0;
+
+    fn assert_near(value: f64, expected: f64, tolerance: f64) {
+        assert!((value - expected).abs() <= tolerance, "{} is not within {} of {}", value, tolerance, expected);
+    }
+
+    fn zone(keep_in: bool, corners: &[(f64, f64)]) -> GeofenceZone {
+        GeofenceZone {
+            name: "test".to_string(),
+            keep_in,
+            points: corners.iter().map(|&(latitude, longitude)| GeoPoint::new(latitude, longitude)).collect(),
+        }
+    }
+
+    #[test]
+    fn distances_along_great_circles() {
+        let origin = GeoPoint::new(0.0, 0.0);
+        assert_near(origin.distance_to(&GeoPoint::new(1.0, 0.0)), 111_195.08, 0.01);
+        assert_near(origin.distance_to(&GeoPoint::new(0.0, 1.0)), 111_195.08, 0.01);
+        assert_near(origin.distance_to(&GeoPoint::new(90.0, 0.0)), 10_007_557.22, 0.01);
+        assert_near(origin.distance_to(&GeoPoint::new(0.0, 180.0)), 20_015_114.44, 0.01);
+        // Meridians converge, a degree of longitude at 60° is half as long.
+        assert_near(GeoPoint::new(60.0, 0.0).distance_to(&GeoPoint::new(60.0, 0.001)), 0.001 * DEGREE / 2.0, 0.001);
+        // Across the antimeridian
+        assert_near(GeoPoint::new(0.0, 179.5).distance_to(&GeoPoint::new(0.0, -179.5)), DEGREE, 0.01);
+        assert_eq!(origin.distance_to(&origin), 0.0);
+    }
+
+    #[test]
+    fn bearings_from_true_north() {
+        let origin = GeoPoint::new(0.0, 0.0);
+        assert_near(origin.bearing_to(&GeoPoint::new(1.0, 0.0)), 0.0, 1e-9);
+        assert_near(origin.bearing_to(&GeoPoint::new(0.0, 1.0)), 90.0, 1e-9);
+        assert_near(origin.bearing_to(&GeoPoint::new(-1.0, 0.0)), 180.0, 1e-9);
+        assert_near(origin.bearing_to(&GeoPoint::new(0.0, -1.0)), 270.0, 1e-9);
+        // The great circle to a point due east leaves towards the equator, not along the parallel.
+        assert_near(GeoPoint::new(45.0, 0.0).bearing_to(&GeoPoint::new(45.0, 90.0)), 54.7356, 1e-4);
+        assert_near(GeoPoint::new(0.0, 179.5).bearing_to(&GeoPoint::new(0.0, -179.5)), 90.0, 1e-9);
+    }
+
+    #[test]
+    fn offsets_land_where_expected() {
+        let east = GeoPoint::new(0.0, 0.0).offset(DEGREE, 90.0);
+        assert_near(east.latitude, 0.0, 1e-9);
+        assert_near(east.longitude, 1.0, 1e-9);
+        let north = GeoPoint::new(10.0, 20.0).offset(DEGREE, 0.0);
+        assert_near(north.latitude, 11.0, 1e-9);
+        assert_near(north.longitude, 20.0, 1e-9);
+        // Wraps around the antimeridian
+        let wrapped = GeoPoint::new(0.0, 179.5).offset(DEGREE, 90.0);
+        assert_near(wrapped.longitude, -179.5, 1e-9);
+
+        // The distance and bearing lead back to the offset point.
+        let start = GeoPoint::new(48.4284, -123.3656);
+        let end = start.offset(1234.5, 222.0);
+        assert_near(start.distance_to(&end), 1234.5, 1e-6);
+        assert_near(start.bearing_to(&end), 222.0, 1e-6);
+    }
+
+    #[test]
+    fn local_offsets_east_and_north() {
+        let origin = GeoPoint::new(60.0, 10.0);
+        let (east, north) = GeoPoint::new(60.001, 10.0).local_from(&origin);
+        assert_near(east, 0.0, 1e-9);
+        assert_near(north, 0.001 * DEGREE, 1e-6);
+        let (east, north) = GeoPoint::new(60.0, 9.999).local_from(&origin);
+        assert_near(east, -0.001 * DEGREE / 2.0, 1e-6);
+        assert_near(north, 0.0, 1e-9);
+    }
+
+    #[test]
+    fn points_in_polygons() {
+        // An L shape, the notch at the north east corner is outside.
+        let shape = zone(true, &[
+            (0.0, 0.0), (0.0, 0.002), (0.001, 0.002), (0.001, 0.001), (0.002, 0.001), (0.002, 0.0)
+        ]);
+        assert!(shape.contains(&GeoPoint::new(0.0005, 0.0005)));
+        assert!(shape.contains(&GeoPoint::new(0.0005, 0.0015)));
+        assert!(shape.contains(&GeoPoint::new(0.0015, 0.0005)));
+        assert!(!shape.contains(&GeoPoint::new(0.0015, 0.0015)));
+        assert!(!shape.contains(&GeoPoint::new(-0.0005, 0.0005)));
+        assert!(!shape.contains(&GeoPoint::new(0.0005, 0.003)));
+
+        // The corners may go either way round.
+        let mut reversed = shape.clone();
+        reversed.points.reverse();
+        assert!(reversed.contains(&GeoPoint::new(0.0005, 0.0005)));
+        assert!(!reversed.contains(&GeoPoint::new(0.0015, 0.0015)));
+    }
+
+    #[test]
+    fn lines_crossing_edges() {
+        let square = zone(false, &[(0.0, 0.0), (0.0, 0.001), (0.001, 0.001), (0.001, 0.0)]);
+        // Through the square, and past it
+        assert!(square.crosses(&GeoPoint::new(0.0005, -0.001), &GeoPoint::new(0.0005, 0.002)));
+        assert!(!square.crosses(&GeoPoint::new(0.002, -0.001), &GeoPoint::new(0.002, 0.002)));
+        // Into it
+        assert!(square.crosses(&GeoPoint::new(0.0005, -0.001), &GeoPoint::new(0.0005, 0.0005)));
+
+        // Between the arms of an L, both ends inside but the line cuts across the notch.
+        let shape = zone(true, &[
+            (0.0, 0.0), (0.0, 0.002), (0.001, 0.002), (0.001, 0.001), (0.002, 0.001), (0.002, 0.0)
+        ]);
+        assert!(shape.crosses(&GeoPoint::new(0.0019, 0.0005), &GeoPoint::new(0.0005, 0.0019)));
+        assert!(!shape.crosses(&GeoPoint::new(0.0005, 0.0005), &GeoPoint::new(0.0005, 0.0015)));
+    }
+
+    #[test]
+    fn keep_in_and_keep_out_zones() {
+        let corners = [(48.0, -124.0), (48.0, -123.0), (49.0, -123.0), (49.0, -124.0)];
+        let inside = GeoPoint::new(48.5, -123.5);
+        let outside = GeoPoint::new(50.0, -123.5);
+        assert!(zone(true, &corners).allows(&inside));
+        assert!(!zone(true, &corners).allows(&outside));
+        assert!(!zone(false, &corners).allows(&inside));
+        assert!(zone(false, &corners).allows(&outside));
+    }
+}
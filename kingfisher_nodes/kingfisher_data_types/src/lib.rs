@@ -6,5 +6,14 @@ pub mod imu_types;
 #[cfg(feature = "std")]
 pub mod dds_topics;
 
+#[cfg(feature = "std")]
+pub mod topic_registry;
+
+#[cfg(feature = "std")]
+pub mod qos_profiles;
+
+#[cfg(feature = "std")]
+pub mod geo;
+
 pub const DEFAULT_DOMAIN: i32 = 50;
 pub const DEFAULT_ID: &str = "Kingfisher";
\ No newline at end of file
@@ -0,0 +1,237 @@
+//! Named QoS profiles of the topics.
+//!
+//! Every topic in the registry uses one of these profiles for its readers and writers. The settings of a profile,
+//! or of a single topic, can be overridden from the `[qos]` table of the node config, e.g.
+//!
+//! ```toml
+//! [qos.profiles.sensor]
+//! history_depth = 10
+//!
+//! [qos.topics."imu_data"]
+//! reliable = true
+//! ```
+//!
+//! Readers and writers only match when their QoS is compatible, so overrides should be the same on every node.
+use crate::topic_registry::{ALL_TOPICS, HEARTBEAT_LEASE, HEARTBEAT_PERIOD};
+use dust_dds::infrastructure::{
+    qos::{DataReaderQos, DataWriterQos, QosKind},
+    qos_policy::{
+        DeadlineQosPolicy, DurabilityQosPolicy, DurabilityQosPolicyKind, HistoryQosPolicy, HistoryQosPolicyKind,
+        LivelinessQosPolicy, LivelinessQosPolicyKind, ReliabilityQosPolicy, ReliabilityQosPolicyKind,
+    },
+    time::{Duration, DurationKind},
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Deadline of the control topics (s), a controller that stops commanding this long is reported.
+pub const CONTROL_DEADLINE: f64 = 0.5;
+/// How long a reliable writer may block when the history of a reader is full (s)
+const MAX_BLOCKING_TIME: f64 = 0.1;
+
+/// Reader and writer QoS of a topic.
+pub struct TopicQos {
+    pub reader: QosKind<DataReaderQos>,
+    pub writer: QosKind<DataWriterQos>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QosProfile {
+    /// High rate sensor streams: best effort, keep the last samples. A late sample is worse than a lost one.
+    Sensor,
+    /// Status and config: reliable and transient local, so late joiners get the last sample of every instance.
+    Status,
+    /// One-off commands: reliable, but not delivered to late joiners.
+    Command,
+    /// Periodic control messages: reliable, with a deadline so a quiet controller is noticed.
+    Control,
+    /// Node heartbeats: deadline and automatic liveliness, see `HEARTBEAT_PERIOD`.
+    Heartbeat,
+}
+
+impl QosProfile {
+    pub const ALL: [QosProfile; 5] =
+        [QosProfile::Sensor, QosProfile::Status, QosProfile::Command, QosProfile::Control, QosProfile::Heartbeat];
+
+    /// Name of the profile in the config.
+    pub fn name(&self) -> &'static str {
+        match self {
+            QosProfile::Sensor => "sensor",
+            QosProfile::Status => "status",
+            QosProfile::Command => "command",
+            QosProfile::Control => "control",
+            QosProfile::Heartbeat => "heartbeat",
+        }
+    }
+
+    fn settings(&self) -> QosSettings {
+        match self {
+            // Deep enough for the logger to poll the IMU without losing samples.
+            QosProfile::Sensor => QosSettings {
+                reliable: false,
+                transient_local: false,
+                history_depth: 100,
+                deadline: None,
+                liveliness_lease: None,
+            },
+            QosProfile::Status => QosSettings {
+                reliable: true,
+                transient_local: true,
+                history_depth: 1,
+                deadline: None,
+                liveliness_lease: None,
+            },
+            QosProfile::Command => QosSettings {
+                reliable: true,
+                transient_local: false,
+                history_depth: 10,
+                deadline: None,
+                liveliness_lease: None,
+            },
+            QosProfile::Control => QosSettings {
+                reliable: true,
+                transient_local: false,
+                history_depth: 1,
+                deadline: Some(CONTROL_DEADLINE),
+                liveliness_lease: None,
+            },
+            QosProfile::Heartbeat => QosSettings {
+                reliable: true,
+                transient_local: false,
+                history_depth: 1,
+                deadline: Some(2.0 * HEARTBEAT_PERIOD as f64),
+                liveliness_lease: Some(HEARTBEAT_LEASE as f64),
+            },
+        }
+    }
+}
+
+/// Settings a profile is made of.
+#[derive(Debug, Clone)]
+struct QosSettings {
+    reliable: bool,
+    transient_local: bool,
+    /// Samples kept per instance, 0 keeps all
+    history_depth: u32,
+    /// Maximum time between samples of an instance (s)
+    deadline: Option<f64>,
+    /// Automatic liveliness lease (s)
+    liveliness_lease: Option<f64>,
+}
+
+/// Settings of a profile or topic to change, from the config.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct QosOverride {
+    pub reliable: Option<bool>,
+    pub transient_local: Option<bool>,
+    /// Samples kept per instance, 0 keeps all
+    pub history_depth: Option<u32>,
+    /// Maximum time between samples (s), 0 disables the deadline
+    pub deadline: Option<f64>,
+}
+
+/// The `[qos]` table of the node config.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct QosOverrides {
+    /// Overrides by profile name
+    #[serde(default)]
+    pub profiles: HashMap<String, QosOverride>,
+    /// Overrides by topic name, applied after the profile ones
+    #[serde(default)]
+    pub topics: HashMap<String, QosOverride>,
+}
+
+static OVERRIDES: OnceLock<QosOverrides> = OnceLock::new();
+
+/// Install the QoS overrides of this process. Must be called before the first reader or writer is created, later
+/// calls are ignored.
+pub fn set_overrides(overrides: QosOverrides) {
+    for name in overrides.profiles.keys() {
+        if !QosProfile::ALL.iter().any(|p| p.name() == name) {
+            log::warn!("QoS override for unknown profile {}.", name);
+        }
+    }
+    for name in overrides.topics.keys() {
+        if !ALL_TOPICS.iter().any(|(topic, _)| topic == name) {
+            log::warn!("QoS override for unknown topic {}.", name);
+        }
+    }
+    if OVERRIDES.set(overrides).is_err() {
+        log::warn!("QoS overrides already set, ignoring the new ones.");
+    }
+}
+
+impl QosOverride {
+    fn apply(&self, settings: &mut QosSettings) {
+        if let Some(reliable) = self.reliable {
+            settings.reliable = reliable;
+        }
+        if let Some(transient_local) = self.transient_local {
+            settings.transient_local = transient_local;
+        }
+        if let Some(history_depth) = self.history_depth {
+            settings.history_depth = history_depth;
+        }
+        if let Some(deadline) = self.deadline {
+            settings.deadline = if deadline > 0.0 { Some(deadline) } else { None };
+        }
+    }
+}
+
+fn duration(seconds: f64) -> DurationKind {
+    DurationKind::Finite(Duration::new(seconds.trunc() as i32, (seconds.fract() * 1e9) as u32))
+}
+
+/// Reader and writer QoS of a topic using the given profile, with the overrides applied.
+pub fn topic_qos(profile: QosProfile, topic: &str) -> TopicQos {
+    let mut settings = profile.settings();
+    if let Some(overrides) = OVERRIDES.get() {
+        if let Some(o) = overrides.profiles.get(profile.name()) {
+            o.apply(&mut settings);
+        }
+        if let Some(o) = overrides.topics.get(topic) {
+            o.apply(&mut settings);
+        }
+    }
+
+    let reliability = ReliabilityQosPolicy {
+        kind: if settings.reliable { ReliabilityQosPolicyKind::Reliable } else { ReliabilityQosPolicyKind::BestEffort },
+        max_blocking_time: duration(MAX_BLOCKING_TIME),
+    };
+    let durability = DurabilityQosPolicy {
+        kind: if settings.transient_local { DurabilityQosPolicyKind::TransientLocal } else { DurabilityQosPolicyKind::Volatile },
+    };
+    let history = HistoryQosPolicy {
+        kind: match settings.history_depth {
+            0 => HistoryQosPolicyKind::KeepAll,
+            depth => HistoryQosPolicyKind::KeepLast(depth),
+        },
+    };
+    let deadline = DeadlineQosPolicy {
+        period: settings.deadline.map(duration).unwrap_or(DurationKind::Infinite),
+    };
+    let liveliness = LivelinessQosPolicy {
+        kind: LivelinessQosPolicyKind::Automatic,
+        lease_duration: settings.liveliness_lease.map(duration).unwrap_or(DurationKind::Infinite),
+    };
+
+    TopicQos {
+        reader: QosKind::Specific(DataReaderQos {
+            reliability: reliability.clone(),
+            durability: durability.clone(),
+            history: history.clone(),
+            deadline: deadline.clone(),
+            liveliness: liveliness.clone(),
+            ..Default::default()
+        }),
+        writer: QosKind::Specific(DataWriterQos {
+            reliability,
+            durability,
+            history,
+            deadline,
+            liveliness,
+            ..Default::default()
+        }),
+    }
+}
@@ -0,0 +1,258 @@
+//! Typed registry of the DDS topics used by the system.
+//!
+//! Every topic is declared once in the table at the bottom of this file, which ties the topic name to the type
+//! name and the Rust type carried on it. Nodes create their readers and writers through the `Topic` marker types,
+//! so using the wrong type for a topic is a compile error, and nodes that handle every topic (the logger, replay)
+//! iterate over the table with `for_each_topic!`. The QoS of every topic comes from its profile in `qos_profiles`.
+use crate::dds_topics::*;
+use crate::qos_profiles::{topic_qos, QosProfile, TopicQos};
+use dust_dds::{
+    dds_async::{
+        data_reader::DataReaderAsync, data_writer::DataWriterAsync, domain_participant::DomainParticipantAsync,
+        publisher::PublisherAsync, subscriber::SubscriberAsync,
+    },
+    domain::domain_participant::DomainParticipant,
+    infrastructure::{
+        error::{DdsError, DdsResult},
+        qos::QosKind,
+        status::NO_STATUS,
+    },
+    publication::{data_writer::DataWriter, publisher::Publisher},
+    subscription::{
+        data_reader::DataReader,
+        sample_info::{ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE},
+        subscriber::Subscriber,
+    },
+};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Serialize};
+use std::future::Future;
+
+/// How long to wait before polling a reader again when it has no data.
+const POLL_PERIOD: u64 = 100;
+
+/// Nodes publish their heartbeat this often (s).
+pub const HEARTBEAT_PERIOD: u64 = 1;
+/// A heartbeat writer that hasn't written for this long (s) has lost its liveliness.
+pub const HEARTBEAT_LEASE: i32 = 3;
+
+/// A sample taken from a reader.
+#[derive(Debug, Clone)]
+pub struct Received<T> {
+    pub data: T,
+    /// Time the publisher wrote the sample (ns since the unix epoch), if known
+    pub source_time: Option<u64>,
+}
+
+/// A DDS topic of the system. Implemented by the marker types declared in the topic table.
+pub trait Topic: Send + Sync + 'static {
+    /// Type carried on the topic
+    type Data: Serialize + DeserializeOwned + JsonSchema + Clone + std::fmt::Debug + Send + Sync + 'static;
+    /// DDS topic name
+    const NAME: &'static str;
+    /// DDS type name
+    const TYPE_NAME: &'static str;
+
+    /// Vehicle a sample belongs to, the key of every topic.
+    fn vehicle_id(data: &Self::Data) -> &str;
+    /// Move a sample to another vehicle.
+    fn set_vehicle_id(data: &mut Self::Data, id: String);
+    /// Profile the topic's QoS is based on
+    const PROFILE: QosProfile;
+    /// Reader and writer QoS, the profile with the config overrides applied.
+    fn qos() -> TopicQos {
+        topic_qos(Self::PROFILE, Self::NAME)
+    }
+
+    fn create_reader(participant: &DomainParticipantAsync, subscriber: &SubscriberAsync) -> impl Future<Output = DdsResult<DataReaderAsync<Self::Data>>> + Send;
+    fn create_writer(participant: &DomainParticipantAsync, publisher: &PublisherAsync) -> impl Future<Output = DdsResult<DataWriterAsync<Self::Data>>> + Send;
+    fn take(reader: &DataReaderAsync<Self::Data>, max_samples: i32) -> impl Future<Output = DdsResult<Vec<Received<Self::Data>>>> + Send;
+    fn write(writer: &DataWriterAsync<Self::Data>, data: &Self::Data) -> impl Future<Output = DdsResult<()>> + Send;
+
+    /// Blocking API versions for the nodes that don't run an async runtime.
+    fn create_reader_blocking(participant: &DomainParticipant, subscriber: &Subscriber) -> DdsResult<DataReader<Self::Data>>;
+    fn create_writer_blocking(participant: &DomainParticipant, publisher: &Publisher) -> DdsResult<DataWriter<Self::Data>>;
+    fn take_blocking(reader: &DataReader<Self::Data>, max_samples: i32) -> DdsResult<Vec<Received<Self::Data>>>;
+    fn write_blocking(writer: &DataWriter<Self::Data>, data: &Self::Data) -> DdsResult<()>;
+}
+
+/// Create the topic and a reader for it.
+pub async fn subscribe<T: Topic>(participant: &DomainParticipantAsync, subscriber: &SubscriberAsync) -> DdsResult<DataReaderAsync<T::Data>> {
+    T::create_reader(participant, subscriber).await
+}
+
+/// Create the topic and a writer for it.
+pub async fn publish<T: Topic>(participant: &DomainParticipantAsync, publisher: &PublisherAsync) -> DdsResult<DataWriterAsync<T::Data>> {
+    T::create_writer(participant, publisher).await
+}
+
+/// Create the topic and a reader for it with the blocking API.
+pub fn subscribe_blocking<T: Topic>(participant: &DomainParticipant, subscriber: &Subscriber) -> DdsResult<DataReader<T::Data>> {
+    T::create_reader_blocking(participant, subscriber)
+}
+
+/// Create the topic and a writer for it with the blocking API.
+pub fn publish_blocking<T: Topic>(participant: &DomainParticipant, publisher: &Publisher) -> DdsResult<DataWriter<T::Data>> {
+    T::create_writer_blocking(participant, publisher)
+}
+
+/// Wait until the reader has data and take up to max_samples from it.
+pub async fn take_samples<T: Topic>(reader: &DataReaderAsync<T::Data>, max_samples: i32) -> Vec<Received<T::Data>> {
+    loop {
+        match T::take(reader, max_samples).await {
+            Ok(val) if !val.is_empty() => return val,
+            Ok(_) | Err(DdsError::NoData) => (),
+            Err(e) => {
+                log::error!("Unexpected error reading {}: {:?}", T::NAME, e);
+            }
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(POLL_PERIOD)).await;
+    }
+}
+
+/// Unpack the data of the taken samples. Samples without data only signal an instance being disposed or its
+/// writer going away, which the readers learn from the status instead.
+macro_rules! unpack_samples {
+    ($topic:ty, $samples:expr) => {{
+        let mut received = Vec::with_capacity($samples.len());
+        for sample in $samples {
+            if !sample.sample_info().valid_data {
+                continue;
+            }
+            match sample.data() {
+                Ok(data) => received.push(Received {
+                    data,
+                    source_time: sample.sample_info().source_timestamp
+                        .map(|t| t.sec() as u64 * 1_000_000_000 + t.nanosec() as u64),
+                }),
+                Err(e) => log::error!("Failed to unpack {} sample: {:?}", <$topic as Topic>::NAME, e),
+            }
+        }
+        received
+    }};
+}
+
+/// Declares a marker type implementing `Topic` for each entry of the table, and the `for_each_topic!` macro.
+macro_rules! topic_table {
+    ($($(#[$doc:meta])* $marker:ident: $data:ident => $name:ident ($profile:ident)),* $(,)?) => {
+        $(
+            $(#[$doc])*
+            pub struct $marker;
+
+            impl Topic for $marker {
+                type Data = $data;
+                const NAME: &'static str = $name;
+                const TYPE_NAME: &'static str = stringify!($data);
+
+                const PROFILE: QosProfile = QosProfile::$profile;
+
+                fn vehicle_id(data: &$data) -> &str {
+                    &data.id
+                }
+
+                fn set_vehicle_id(data: &mut $data, id: String) {
+                    data.id = id;
+                }
+
+                async fn create_reader(participant: &DomainParticipantAsync, subscriber: &SubscriberAsync) -> DdsResult<DataReaderAsync<$data>> {
+                    // Several readers and writers of one topic can share a participant.
+                    let topic = match participant.lookup_topicdescription(Self::NAME).await? {
+                        Some(val) => val,
+                        None => participant
+                            .create_topic::<$data>(Self::NAME, Self::TYPE_NAME, QosKind::Default, None, NO_STATUS)
+                            .await?,
+                    };
+                    subscriber
+                        .create_datareader::<$data>(&topic, Self::qos().reader, None, NO_STATUS)
+                        .await
+                }
+
+                async fn create_writer(participant: &DomainParticipantAsync, publisher: &PublisherAsync) -> DdsResult<DataWriterAsync<$data>> {
+                    // Several readers and writers of one topic can share a participant.
+                    let topic = match participant.lookup_topicdescription(Self::NAME).await? {
+                        Some(val) => val,
+                        None => participant
+                            .create_topic::<$data>(Self::NAME, Self::TYPE_NAME, QosKind::Default, None, NO_STATUS)
+                            .await?,
+                    };
+                    publisher
+                        .create_datawriter::<$data>(&topic, Self::qos().writer, None, NO_STATUS)
+                        .await
+                }
+
+                async fn take(reader: &DataReaderAsync<$data>, max_samples: i32) -> DdsResult<Vec<Received<$data>>> {
+                    let samples = reader.take(max_samples, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await?;
+                    Ok(unpack_samples!(Self, samples))
+                }
+
+                async fn write(writer: &DataWriterAsync<$data>, data: &$data) -> DdsResult<()> {
+                    writer.write(data, None).await
+                }
+
+                fn create_reader_blocking(participant: &DomainParticipant, subscriber: &Subscriber) -> DdsResult<DataReader<$data>> {
+                    let topic = match participant.lookup_topicdescription(Self::NAME)? {
+                        Some(val) => val,
+                        None => participant.create_topic::<$data>(Self::NAME, Self::TYPE_NAME, QosKind::Default, None, NO_STATUS)?,
+                    };
+                    subscriber.create_datareader::<$data>(&topic, Self::qos().reader, None, NO_STATUS)
+                }
+
+                fn create_writer_blocking(participant: &DomainParticipant, publisher: &Publisher) -> DdsResult<DataWriter<$data>> {
+                    let topic = match participant.lookup_topicdescription(Self::NAME)? {
+                        Some(val) => val,
+                        None => participant.create_topic::<$data>(Self::NAME, Self::TYPE_NAME, QosKind::Default, None, NO_STATUS)?,
+                    };
+                    publisher.create_datawriter::<$data>(&topic, Self::qos().writer, None, NO_STATUS)
+                }
+
+                fn take_blocking(reader: &DataReader<$data>, max_samples: i32) -> DdsResult<Vec<Received<$data>>> {
+                    let samples = reader.take(max_samples, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE)?;
+                    Ok(unpack_samples!(Self, samples))
+                }
+
+                fn write_blocking(writer: &DataWriter<$data>, data: &$data) -> DdsResult<()> {
+                    writer.write(data, None)
+                }
+            }
+        )*
+
+        /// Name and type name of every registered topic.
+        pub const ALL_TOPICS: &[(&str, &str)] = &[$(($name, stringify!($data))),*];
+
+        /// Invokes the given macro with the marker type of every registered topic, e.g.
+        /// `for_each_topic!(my_macro)` expands to `my_macro!(GpsTopic, ImuTopic, ...)` with full paths.
+        #[macro_export]
+        macro_rules! for_each_topic {
+            ($callback:ident) => {
+                $callback! { $($crate::topic_registry::$marker),* }
+            };
+        }
+    };
+}
+
+topic_table! {
+    SystemStatusCpuTopic: SystemStatusCpu => SYSTEM_STATUS_CPU_TOPIC (Status),
+    SystemStatusMemoryTopic: SystemStatusMemory => SYSTEM_STATUS_MEMORY_TOPIC (Status),
+    SystemStatusNetworkTopic: SystemStatusNetwork => SYSTEM_STATUS_NETWORK_TOPIC (Status),
+    SystemStatusDiskTopic: SystemStatusDisk => SYSTEM_STATUS_DISK_TOPIC (Status),
+    SystemStatusThermalTopic: SystemStatusThermal => SYSTEM_STATUS_THERMAL_TOPIC (Status),
+    SystemStatusProcessesTopic: SystemStatusProcesses => SYSTEM_STATUS_PROCESSES_TOPIC (Status),
+    GpsTopic: GpsData => GPS_TOPIC (Sensor),
+    GpsSatellitesTopic: GpsSatellites => GPS_SATELLITES_TOPIC (Sensor),
+    ImuTopic: ImuData => IMU_TOPIC (Sensor),
+    MicroControlTopic: MicroControl => MICROCONTROLLER_CONTROL_TOPIC (Control),
+    MicroStatusTopic: MicroStatus => MICROCONTROLLER_STATUS_TOPIC (Status),
+    MissionTopic: Mission => MISSION_TOPIC (Status),
+    GeofenceTopic: Geofence => GEOFENCE_TOPIC (Status),
+    GoToTopic: GoTo => GO_TO_TOPIC (Command),
+    MissionCommandTopic: MissionCommand => MISSION_COMMAND_TOPIC (Command),
+    MissionStatusTopic: MissionStatus => MISSION_STATUS_TOPIC (Status),
+    NavigationSetpointTopic: NavigationSetpoint => NAVIGATION_SETPOINT_TOPIC (Control),
+    ControllerTuningTopic: ControllerTuning => CONTROLLER_GAINS_TOPIC (Command),
+    ControllerStatusTopic: ControllerStatus => CONTROLLER_STATUS_TOPIC (Status),
+    LoggerCommandTopic: LoggerCommand => LOGGER_COMMAND_TOPIC (Command),
+    LoggerStatusTopic: LoggerStatus => LOGGER_STATUS_TOPIC (Status),
+    NodeHeartbeatTopic: NodeHeartbeat => NODE_HEARTBEAT_TOPIC (Heartbeat),
+    SystemHealthTopic: SystemHealth => SYSTEM_HEALTH_TOPIC (Status),
+    AlarmTopic: Alarm => ALARM_TOPIC (Status),
+}
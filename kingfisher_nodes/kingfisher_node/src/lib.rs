@@ -0,0 +1,449 @@
+//! Common runtime for the Kingfisher DDS nodes.
+//!
+//! Provides the command line and config file options every node shares (DDS domain, vehicle id, log level and
+//! config path), the logging setup, DDS participant creation, the 1 Hz node heartbeat, systemd notifications and a
+//! clean shutdown on SIGINT/SIGTERM.
+//!
+//! Nodes report the progress of their main loop with `Node::progress` or their `Watchdog`. Once a node has done so,
+//! its heartbeat stops when the progress does, so a hung main loop shows up as a stale node.
+mod systemd;
+
+pub use systemd::Watchdog;
+
+use clap::Args;
+use config::{Config, ConfigError};
+use dust_dds::{
+    dds_async::{domain_participant::DomainParticipantAsync, domain_participant_factory::DomainParticipantFactoryAsync},
+    domain::{domain_participant::DomainParticipant, domain_participant_factory::DomainParticipantFactory},
+    infrastructure::{qos::QosKind, status::NO_STATUS},
+};
+use kingfisher_data_types::dds_topics::{NodeHeartbeat, NodeState};
+use kingfisher_data_types::qos_profiles::{self, QosOverrides};
+use kingfisher_data_types::topic_registry::{publish_blocking, NodeHeartbeatTopic, Topic, HEARTBEAT_PERIOD};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How often sleeping nodes check for a shutdown request
+const SHUTDOWN_POLL_PERIOD: Duration = Duration::from_millis(100);
+
+/// Prefix of the environment variables that override config file settings, e.g. KINGFISHER_DOMAIN
+const ENV_PREFIX: &str = "KINGFISHER";
+/// The heartbeat stops when a node that reports progress hasn't for this long, the same as the systemd watchdog
+/// timeout of the units
+const PROGRESS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Command line options shared by every node. Add to a node's parser with `#[command(flatten)]`.
+#[derive(Args, Debug, Clone)]
+pub struct NodeArgs {
+    /// Path to the config file
+    #[arg(short, long)]
+    pub config_file: Option<String>,
+
+    /// DDS domain, overrides the config file
+    #[arg(long)]
+    pub domain: Option<i32>,
+
+    /// Vehicle id used as the key of published samples, overrides the config file
+    #[arg(long)]
+    pub vehicle_id: Option<String>,
+
+    /// Log level (error, warn, info, debug or trace), overrides the config file. RUST_LOG takes precedence.
+    #[arg(long)]
+    pub log_level: Option<String>,
+}
+
+/// Which vehicles a node handles the samples of, from the `vehicles` list of the config file. An empty list
+/// accepts every vehicle.
+#[derive(Debug, Clone, Default)]
+pub struct VehicleFilter {
+    vehicles: Vec<String>,
+}
+
+impl VehicleFilter {
+    pub fn new(vehicles: Vec<String>) -> Self {
+        VehicleFilter { vehicles }
+    }
+
+    pub fn accepts(&self, vehicle_id: &str) -> bool {
+        self.vehicles.is_empty() || self.vehicles.iter().any(|v| v == vehicle_id)
+    }
+}
+
+/// What the heartbeat reports about the node.
+struct Health {
+    state: NodeState,
+    last_error: String,
+}
+
+/// A running node: its settings, identity and shutdown state.
+pub struct Node {
+    name: &'static str,
+    domain: i32,
+    vehicle_id: String,
+    settings: Config,
+    config_file: String,
+    /// False when the config file is the default one, which may be missing
+    config_required: bool,
+    shutdown: Arc<AtomicBool>,
+    health: Arc<Mutex<Health>>,
+    /// Set once the node is done, stops the heartbeat
+    finished: Arc<AtomicBool>,
+    heartbeat: Mutex<Option<JoinHandle<()>>>,
+    watchdog: Watchdog,
+}
+
+impl Node {
+    /// Read the config, set up logging, install the shutdown signal handlers and start the heartbeat.
+    /// `version` is normally `env!("CARGO_PKG_VERSION")`. `default_config` is used when no config file is given on
+    /// the command line, and may be missing. Exits the process if the config is unusable.
+    pub fn init(name: &'static str, version: &'static str, args: &NodeArgs, default_config: &str) -> Node {
+        let (config_file, required) = match &args.config_file {
+            Some(val) => (val.as_str(), true),
+            None => (default_config, false),
+        };
+
+        let settings = match load_settings(config_file, required) {
+            Ok(val) => val,
+            Err(e) => {
+                init_logging(args.log_level.as_deref().unwrap_or("info"));
+                log::error!("Failed to load config {}: {}", config_file, e);
+                std::process::exit(1);
+            }
+        };
+
+        let log_level = match &args.log_level {
+            Some(val) => val.clone(),
+            None => settings.get_string("log_level").unwrap_or("info".to_string()),
+        };
+        init_logging(&log_level);
+
+        let domain = match args.domain {
+            Some(val) => val,
+            None => settings.get_int("domain").map(|d| d as i32).unwrap_or(kingfisher_data_types::DEFAULT_DOMAIN),
+        };
+        let vehicle_id = match &args.vehicle_id {
+            Some(val) => val.clone(),
+            None => settings.get_string("vehicle_id").unwrap_or(kingfisher_data_types::DEFAULT_ID.to_string()),
+        };
+
+        // Applies to every reader and writer the node creates, so this has to happen before the heartbeat starts.
+        match settings.get::<QosOverrides>("qos") {
+            Ok(val) => qos_profiles::set_overrides(val),
+            Err(ConfigError::NotFound(_)) => (),
+            Err(e) => {
+                log::error!("Invalid QoS overrides in {}: {}", config_file, e);
+                std::process::exit(1);
+            }
+        }
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        for signal in [SIGINT, SIGTERM] {
+            // A second signal kills the node if the clean shutdown gets stuck.
+            signal_hook::flag::register_conditional_shutdown(signal, 1, shutdown.clone()).unwrap();
+            signal_hook::flag::register(signal, shutdown.clone()).unwrap();
+        }
+
+        log::info!("Starting {} {} for vehicle {} on domain {}.", name, version, vehicle_id, domain);
+        let health = Arc::new(Mutex::new(Health {
+            state: NodeState::Starting,
+            last_error: String::new(),
+        }));
+        let finished = Arc::new(AtomicBool::new(false));
+        let watchdog = Watchdog::from_env();
+        watchdog.status("Starting");
+        let heartbeat = Heartbeat {
+            template: NodeHeartbeat {
+                id: vehicle_id.clone(),
+                node: name.to_string(),
+                pid: std::process::id(),
+                version: version.to_string(),
+                uptime: 0.0,
+                state: NodeState::Starting,
+                last_error: String::new(),
+            },
+            domain,
+            started: Instant::now(),
+            shutdown: shutdown.clone(),
+            finished: finished.clone(),
+            health: health.clone(),
+            watchdog: watchdog.clone(),
+        };
+        let heartbeat = std::thread::spawn(move || heartbeat.run());
+
+        Node {
+            name,
+            domain,
+            vehicle_id,
+            settings,
+            config_file: config_file.to_string(),
+            config_required: required,
+            shutdown,
+            health,
+            finished,
+            heartbeat: Mutex::new(Some(heartbeat)),
+            watchdog,
+        }
+    }
+
+    /// Report that the node is set up and doing its job, call once the participant and devices are up.
+    pub fn ready(&self) {
+        let mut health = self.health.lock().unwrap();
+        if health.state == NodeState::Starting {
+            health.state = NodeState::Running;
+        }
+        self.watchdog.ready("Running");
+        self.watchdog.ping();
+        log::info!("{} is running.", self.name);
+    }
+
+    /// Report a problem that keeps the node from doing its job properly. Also logged as an error.
+    pub fn report_error(&self, error: &str) {
+        log::error!("{}", error);
+        let mut health = self.health.lock().unwrap();
+        health.state = NodeState::Degraded;
+        health.last_error = error.to_string();
+        self.watchdog.status(&format!("Degraded: {}", error));
+    }
+
+    /// Report that the node recovered from the last error.
+    pub fn clear_error(&self) {
+        let mut health = self.health.lock().unwrap();
+        if health.state == NodeState::Degraded {
+            health.state = NodeState::Running;
+            self.watchdog.status("Running");
+        }
+    }
+
+    /// Report real progress, e.g. a sample published, to the systemd watchdog.
+    pub fn progress(&self) {
+        self.watchdog.pet();
+    }
+
+    /// Handle to report progress from tasks that don't have the node.
+    pub fn watchdog(&self) -> Watchdog {
+        self.watchdog.clone()
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn domain(&self) -> i32 {
+        self.domain
+    }
+
+    /// Id of the vehicle this node runs on, used as the key of the published samples.
+    pub fn vehicle_id(&self) -> &str {
+        &self.vehicle_id
+    }
+
+    /// Node specific settings from the config file.
+    pub fn settings(&self) -> &Config {
+        &self.settings
+    }
+
+    /// Config file the settings were read from, may not exist when it is the default one.
+    pub fn config_file(&self) -> &str {
+        &self.config_file
+    }
+
+    /// Read the config file and the environment again, for nodes that pick up config changes without a restart.
+    /// The domain, vehicle id, log level and QoS overrides keep the values the node started with.
+    pub fn reload_settings(&self) -> Result<Config, ConfigError> {
+        load_settings(&self.config_file, self.config_required)
+    }
+
+    /// Filter for the nodes that handle samples from several vehicles.
+    pub fn vehicle_filter(&self) -> VehicleFilter {
+        let vehicles = self.settings.get_array("vehicles").unwrap_or_default()
+            .into_iter()
+            .filter_map(|v| v.into_string().ok())
+            .collect();
+        VehicleFilter::new(vehicles)
+    }
+
+    /// Create a participant on the node's domain with the blocking API.
+    pub fn participant(&self) -> DomainParticipant {
+        DomainParticipantFactory::get_instance()
+            .create_participant(self.domain, QosKind::Default, None, NO_STATUS)
+            .unwrap()
+    }
+
+    /// Create a participant on the node's domain with the async API.
+    pub async fn participant_async(&self) -> DomainParticipantAsync {
+        factory_async()
+            .create_participant(self.domain, QosKind::Default, None, NO_STATUS)
+            .await
+            .unwrap()
+    }
+
+    /// False once a shutdown has been requested.
+    pub fn running(&self) -> bool {
+        !self.shutdown.load(Ordering::Relaxed)
+    }
+
+    /// Ask the node to shut down, as if it had received SIGTERM.
+    pub fn request_shutdown(&self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+    }
+
+    /// Sleep for the given time, waking up early on shutdown. Returns `running()`.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let start = Instant::now();
+        while self.running() {
+            let elapsed = start.elapsed();
+            if elapsed >= duration {
+                break;
+            }
+            std::thread::sleep(SHUTDOWN_POLL_PERIOD.min(duration - elapsed));
+        }
+        self.running()
+    }
+
+    /// Wait until a shutdown has been requested.
+    pub async fn wait_for_shutdown(&self) {
+        while self.running() {
+            tokio::time::sleep(SHUTDOWN_POLL_PERIOD).await;
+        }
+        log::info!("Shutting down {}.", self.name);
+    }
+
+    /// Stop the heartbeat, for nodes without a participant of their own. `close` does this too.
+    pub fn finish(&self) {
+        self.watchdog.stopping();
+        self.finished.store(true, Ordering::Relaxed);
+        if let Some(heartbeat) = self.heartbeat.lock().unwrap().take() {
+            let _ = heartbeat.join();
+        }
+    }
+
+    /// Delete the participant's readers and writers, so the readers on other nodes see this node's instances go
+    /// away straight away instead of waiting for the liveliness to lapse, and stop the heartbeat.
+    pub fn close(&self, participant: DomainParticipant) {
+        if let Err(e) = participant.delete_contained_entities() {
+            log::error!("Failed to delete the DDS entities: {:?}", e);
+        }
+        if let Err(e) = DomainParticipantFactory::get_instance().delete_participant(&participant) {
+            log::error!("Failed to delete the DDS participant: {:?}", e);
+        }
+        self.finish();
+        log::info!("Stopped {}.", self.name);
+    }
+
+    /// Async version of `close`.
+    pub async fn close_async(&self, participant: DomainParticipantAsync) {
+        if let Err(e) = participant.delete_contained_entities().await {
+            log::error!("Failed to delete the DDS entities: {:?}", e);
+        }
+        if let Err(e) = factory_async().delete_participant(&participant).await {
+            log::error!("Failed to delete the DDS participant: {:?}", e);
+        }
+        self.finish();
+        log::info!("Stopped {}.", self.name);
+    }
+}
+
+/// The factory of the async participants, shared like `DomainParticipantFactory::get_instance` so `close_async` can
+/// delete the participants `participant_async` created.
+fn factory_async() -> &'static DomainParticipantFactoryAsync {
+    static FACTORY: OnceLock<DomainParticipantFactoryAsync> = OnceLock::new();
+    FACTORY.get_or_init(DomainParticipantFactoryAsync::new)
+}
+
+/// Publishes the node heartbeat from its own thread and participant, so the heartbeat keeps going while the node
+/// shuts down. A node stuck in its main loop is caught by `PROGRESS_TIMEOUT`.
+struct Heartbeat {
+    template: NodeHeartbeat,
+    domain: i32,
+    started: Instant,
+    shutdown: Arc<AtomicBool>,
+    finished: Arc<AtomicBool>,
+    health: Arc<Mutex<Health>>,
+    watchdog: Watchdog,
+}
+
+impl Heartbeat {
+    fn run(self) {
+        let factory = DomainParticipantFactory::get_instance();
+        let participant = match factory.create_participant(self.domain, QosKind::Default, None, NO_STATUS) {
+            Ok(val) => val,
+            Err(e) => {
+                log::error!("Failed to create the heartbeat participant: {:?}", e);
+                return;
+            }
+        };
+        let writer = participant
+            .create_publisher(QosKind::Default, None, NO_STATUS)
+            .and_then(|publisher| publish_blocking::<NodeHeartbeatTopic>(&participant, &publisher));
+        let writer = match writer {
+            Ok(val) => val,
+            Err(e) => {
+                log::error!("Failed to create the heartbeat writer: {:?}", e);
+                return;
+            }
+        };
+
+        let period = Duration::from_secs(HEARTBEAT_PERIOD);
+        let mut next = Instant::now();
+        let mut stalled = false;
+        while !self.finished.load(Ordering::Relaxed) {
+            if Instant::now() >= next {
+                next += period;
+                let stopping = self.shutdown.load(Ordering::Relaxed);
+                let since_progress = self.watchdog.since_progress();
+                if !stopping && since_progress.is_some_and(|age| age > PROGRESS_TIMEOUT) {
+                    // Leave it to the supervisor to notice the missing heartbeats.
+                    if !stalled {
+                        log::warn!("No progress for {} s, holding the heartbeat.", PROGRESS_TIMEOUT.as_secs());
+                        stalled = true;
+                    }
+                    std::thread::sleep(SHUTDOWN_POLL_PERIOD);
+                    continue;
+                }
+                if stalled {
+                    log::info!("Making progress again, resuming the heartbeat.");
+                    stalled = false;
+                }
+
+                let mut heartbeat = self.template.clone();
+                heartbeat.uptime = self.started.elapsed().as_secs_f64();
+                {
+                    let health = self.health.lock().unwrap();
+                    heartbeat.state = if stopping {
+                        // The node may take a while to stop, let systemd know it's on its way.
+                        self.watchdog.stopping();
+                        NodeState::Stopping
+                    } else {
+                        health.state
+                    };
+                    heartbeat.last_error = health.last_error.clone();
+                }
+                if let Err(e) = NodeHeartbeatTopic::write_blocking(&writer, &heartbeat) {
+                    log::error!("Failed to publish the heartbeat: {:?}", e);
+                }
+            }
+            std::thread::sleep(SHUTDOWN_POLL_PERIOD);
+        }
+
+        let _ = participant.delete_contained_entities();
+        let _ = factory.delete_participant(&participant);
+    }
+}
+
+fn load_settings(config_file: &str, required: bool) -> Result<Config, ConfigError> {
+    Config::builder()
+        .add_source(config::File::with_name(config_file).required(required))
+        .add_source(config::Environment::with_prefix(ENV_PREFIX))
+        .build()
+}
+
+/// Log to stderr with millisecond timestamps at the given level, unless RUST_LOG says otherwise.
+//switch this to syslog later: https://rust-lang-nursery.github.io/rust-cookbook/development_tools/debugging/log.html#log-to-the-unix-syslog
+fn init_logging(level: &str) {
+    let _ = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level))
+        .format_timestamp_millis()
+        .try_init();
+}
@@ -0,0 +1,141 @@
+//! sd_notify support, for nodes started by systemd with `Type=notify`.
+//!
+//! Everything here is a no-op unless systemd passed a `NOTIFY_SOCKET`. To try it without systemd, listen on a
+//! datagram socket, e.g. `socat -u UNIX-RECV:/tmp/notify.sock STDOUT`, and start the node with
+//! `NOTIFY_SOCKET=/tmp/notify.sock WATCHDOG_USEC=5000000`.
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct Notifier {
+    socket: UnixDatagram,
+    /// Watchdog pings are sent at most this often, half the systemd watchdog timeout
+    watchdog_interval: Option<Duration>,
+    started: Instant,
+    /// Time of the last watchdog ping (ms since started)
+    last_ping: AtomicU64,
+    stopping: AtomicBool,
+}
+
+/// Handle to the systemd notify socket. Cheap to clone, so tasks that publish samples can hold one to pet the
+/// watchdog.
+#[derive(Clone, Default)]
+pub struct Watchdog {
+    notifier: Option<Arc<Notifier>>,
+    /// When the node last made progress, also without systemd, for the heartbeat
+    last_progress: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Watchdog {
+    /// Connect to the socket systemd passed in the environment, if any.
+    pub(crate) fn from_env() -> Watchdog {
+        let path = match std::env::var_os("NOTIFY_SOCKET") {
+            Some(val) => val,
+            None => return Watchdog::default(),
+        };
+        let socket = match connect(&path) {
+            Ok(val) => val,
+            Err(e) => {
+                log::error!("Failed to connect to the systemd notify socket {:?}: {}", path, e);
+                return Watchdog::default();
+            }
+        };
+
+        // WATCHDOG_PID is set when the watchdog is meant for another process of the service.
+        let for_us = match std::env::var("WATCHDOG_PID") {
+            Ok(pid) => pid.parse::<u32>().ok() == Some(std::process::id()),
+            Err(_) => true,
+        };
+        let watchdog_interval = std::env::var("WATCHDOG_USEC").ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|_| for_us)
+            .map(|usec| Duration::from_micros(usec / 2));
+        if let Some(interval) = watchdog_interval {
+            log::info!("systemd watchdog enabled, pinging at least every {:?}.", interval);
+        }
+
+        Watchdog {
+            last_progress: Arc::default(),
+            notifier: Some(Arc::new(Notifier {
+                socket,
+                watchdog_interval,
+                started: Instant::now(),
+                last_ping: AtomicU64::new(0),
+                stopping: AtomicBool::new(false),
+            })),
+        }
+    }
+
+    /// Tell systemd the node made real progress, e.g. published a sample. Systemd restarts a node that stops doing
+    /// this for longer than `WatchdogSec`, and the heartbeat stops once it did.
+    pub fn pet(&self) {
+        *self.last_progress.lock().unwrap() = Some(Instant::now());
+        self.ping();
+    }
+
+    /// Time since the last progress, None if the node never reported any.
+    pub(crate) fn since_progress(&self) -> Option<Duration> {
+        self.last_progress.lock().unwrap().map(|time| time.elapsed())
+    }
+
+    /// Ping the systemd watchdog, at most every half of its timeout.
+    pub(crate) fn ping(&self) {
+        let notifier = match &self.notifier {
+            Some(val) => val,
+            None => return,
+        };
+        let interval = match notifier.watchdog_interval {
+            Some(val) => val,
+            None => return,
+        };
+        let now = notifier.started.elapsed().as_millis() as u64;
+        let last = notifier.last_ping.load(Ordering::Relaxed);
+        if last != 0 && now.saturating_sub(last) < interval.as_millis() as u64 {
+            return;
+        }
+        if notifier.last_ping.compare_exchange(last, now.max(1), Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            self.notify("WATCHDOG=1");
+        }
+    }
+
+    pub(crate) fn ready(&self, status: &str) {
+        self.notify(&format!("READY=1\nSTATUS={}", status));
+    }
+
+    pub(crate) fn status(&self, status: &str) {
+        self.notify(&format!("STATUS={}", status));
+    }
+
+    /// Tell systemd the node is shutting down, only the first call does anything.
+    pub(crate) fn stopping(&self) {
+        if let Some(notifier) = &self.notifier {
+            if !notifier.stopping.swap(true, Ordering::Relaxed) {
+                self.notify("STOPPING=1\nSTATUS=Shutting down");
+            }
+        }
+    }
+
+    fn notify(&self, message: &str) {
+        if let Some(notifier) = &self.notifier {
+            if let Err(e) = notifier.socket.send(message.as_bytes()) {
+                log::warn!("Failed to notify systemd: {}", e);
+            }
+        }
+    }
+}
+
+/// Paths starting with @ are abstract socket names.
+fn connect(path: &std::ffi::OsStr) -> std::io::Result<UnixDatagram> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::SocketAddr;
+
+    let socket = UnixDatagram::unbound()?;
+    let bytes = path.as_bytes();
+    match bytes.strip_prefix(b"@") {
+        Some(name) => socket.connect_addr(&SocketAddr::from_abstract_name(name)?)?,
+        None => socket.connect(path)?,
+    }
+    Ok(socket)
+}
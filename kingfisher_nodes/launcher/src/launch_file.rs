@@ -0,0 +1,104 @@
+//! Launch file format.
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// When to restart a node that exited.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Restart {
+    Always,
+    /// Restart unless the node exited with status 0
+    #[default]
+    OnFailure,
+    Never,
+}
+
+/// A node to run.
+#[derive(Deserialize, Debug, Clone)]
+pub struct NodeSpec {
+    /// Name used for the log prefix and the dependencies
+    pub name: String,
+    /// Program to run, looked up in PATH if not a path
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Extra environment variables
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub working_dir: Option<String>,
+    /// Nodes that have to be up before this one starts
+    #[serde(default)]
+    pub after: Vec<String>,
+    #[serde(default)]
+    pub restart: Restart,
+    /// A node counts as up once it ran this long (s)
+    #[serde(default = "default_ready_after")]
+    pub ready_after: f64,
+}
+
+fn default_ready_after() -> f64 {
+    1.0
+}
+
+fn default_stop_timeout() -> f64 {
+    5.0
+}
+
+/// The launch file, which is also the config of kf_launch itself.
+#[derive(Deserialize, Debug)]
+pub struct LaunchFile {
+    /// Time a node gets to stop after SIGTERM before it is killed (s)
+    #[serde(default = "default_stop_timeout")]
+    pub stop_timeout: f64,
+    #[serde(default, rename = "node")]
+    pub nodes: Vec<NodeSpec>,
+}
+
+impl LaunchFile {
+    /// Check the times, names and dependencies, and return the nodes in start order.
+    pub fn start_order(&self) -> Result<Vec<NodeSpec>, String> {
+        if !is_duration(self.stop_timeout) {
+            return Err("stop_timeout must be a positive number of seconds or zero".to_string());
+        }
+        let mut pending: BTreeMap<&str, &NodeSpec> = BTreeMap::new();
+        for node in &self.nodes {
+            if pending.insert(&node.name, node).is_some() {
+                return Err(format!("Node {} is defined twice", node.name));
+            }
+            if !is_duration(node.ready_after) {
+                return Err(format!("ready_after of node {} must be a positive number of seconds or zero", node.name));
+            }
+        }
+        for node in &self.nodes {
+            for dep in &node.after {
+                if !pending.contains_key(dep.as_str()) {
+                    return Err(format!("Node {} depends on unknown node {}", node.name, dep));
+                }
+            }
+        }
+
+        // Keep the launch file order where the dependencies allow it.
+        let mut order: Vec<NodeSpec> = Vec::new();
+        while order.len() < self.nodes.len() {
+            let next = self.nodes.iter().find(|n| {
+                pending.contains_key(n.name.as_str()) && n.after.iter().all(|d| !pending.contains_key(d.as_str()))
+            });
+            match next {
+                Some(node) => {
+                    pending.remove(node.name.as_str());
+                    order.push(node.clone());
+                }
+                None => {
+                    let names: Vec<&str> = pending.keys().copied().collect();
+                    return Err(format!("Dependency cycle between {}", names.join(", ")));
+                }
+            }
+        }
+        Ok(order)
+    }
+}
+
+/// Whether seconds from the launch file make a Duration.
+fn is_duration(seconds: f64) -> bool {
+    seconds.is_finite() && seconds >= 0.0
+}
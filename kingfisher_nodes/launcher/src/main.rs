@@ -0,0 +1,98 @@
+//! Starts the nodes of the system from a launch file and keeps them running.
+//!
+//! The launch file is the config of kf_launch, so it can also hold the shared node settings. The domain and vehicle
+//! id are passed on to every node through the environment.
+mod launch_file;
+mod process;
+
+use clap::Parser;
+use kingfisher_node::{Node, NodeArgs};
+use launch_file::LaunchFile;
+use process::{Launch, Process};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Parser for command line parameters
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct CommandLineParameters {
+    #[command(flatten)]
+    node: NodeArgs,
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = CommandLineParameters::parse();
+    let node = Arc::new(Node::init("kf_launch", env!("CARGO_PKG_VERSION"), &cli.node, "./launch.toml"));
+
+    let launch_file: LaunchFile = match node.settings().clone().try_deserialize() {
+        Ok(val) => val,
+        Err(e) => {
+            log::error!("Invalid launch file: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let order = match launch_file.start_order() {
+        Ok(val) => val,
+        Err(e) => {
+            log::error!("Invalid launch file: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let names: Vec<&str> = order.iter().map(|n| n.name.as_str()).collect();
+    log::info!("Launching {}.", names.join(", "));
+
+    let launch = Arc::new(Launch {
+        node: node.clone(),
+        env: vec![
+            ("KINGFISHER_DOMAIN".to_string(), node.domain().to_string()),
+            ("KINGFISHER_VEHICLE_ID".to_string(), node.vehicle_id().to_string()),
+        ],
+        stop_timeout: Duration::from_secs_f64(launch_file.stop_timeout),
+        failing: Mutex::new(BTreeSet::new()),
+    });
+
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let mut senders = Vec::new();
+    let mut up = HashMap::new();
+    let mut exited = HashMap::new();
+    for spec in &order {
+        let (up_tx, up_rx) = watch::channel(false);
+        let (exited_tx, exited_rx) = watch::channel(false);
+        senders.push((up_tx, exited_tx));
+        up.insert(spec.name.clone(), up_rx);
+        exited.insert(spec.name.clone(), exited_rx);
+    }
+
+    let mut tasks = Vec::new();
+    for (spec, (up_tx, exited_tx)) in order.iter().cloned().zip(senders) {
+        let dependencies = spec.after.iter().map(|d| up[d].clone()).collect();
+        let dependents = order.iter()
+            .filter(|n| n.after.contains(&spec.name))
+            .map(|n| exited[&n.name].clone())
+            .collect();
+        let process = Process {
+            up: up_tx,
+            exited: exited_tx,
+            spec,
+            launch: launch.clone(),
+            dependencies,
+            dependents,
+            stop: stop_rx.clone(),
+        };
+        tasks.push(tokio::spawn(process.run()));
+    }
+
+    node.ready();
+    node.wait_for_shutdown().await;
+
+    // Every node waits for the ones depending on it, so they stop in reverse start order.
+    let _ = stop_tx.send(true);
+    for task in tasks {
+        let _ = task.await;
+    }
+    node.finish();
+    log::info!("Stopped all nodes.");
+}
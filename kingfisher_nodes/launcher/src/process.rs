@@ -0,0 +1,225 @@
+//! Runs one node: starts it once its dependencies are up, forwards its output, restarts it with a backoff and stops
+//! it once the nodes depending on it have stopped.
+use crate::launch_file::{NodeSpec, Restart};
+use kingfisher_node::Node;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::collections::BTreeSet;
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::watch;
+
+/// First restart delay, doubled on every crash up to MAX_BACKOFF.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// A node that ran this long before crashing restarts after MIN_BACKOFF again.
+const STABLE_AFTER: Duration = Duration::from_secs(30);
+
+/// State shared by the processes of one launch.
+pub struct Launch {
+    pub node: Arc<Node>,
+    /// Environment given to every node, before the node's own
+    pub env: Vec<(String, String)>,
+    pub stop_timeout: Duration,
+    /// Nodes that crashed and aren't back up yet
+    pub failing: Mutex<BTreeSet<String>>,
+}
+
+impl Launch {
+    fn failed(&self, name: &str, error: &str) {
+        self.failing.lock().unwrap().insert(name.to_string());
+        self.node.report_error(error);
+    }
+
+    fn recovered(&self, name: &str) {
+        let mut failing = self.failing.lock().unwrap();
+        if failing.remove(name) && failing.is_empty() {
+            self.node.clear_error();
+        }
+    }
+}
+
+pub struct Process {
+    pub spec: NodeSpec,
+    pub launch: Arc<Launch>,
+    /// Up states of the nodes this one starts after
+    pub dependencies: Vec<watch::Receiver<bool>>,
+    /// Exited states of the nodes that start after this one
+    pub dependents: Vec<watch::Receiver<bool>>,
+    pub up: watch::Sender<bool>,
+    pub exited: watch::Sender<bool>,
+    pub stop: watch::Receiver<bool>,
+}
+
+impl Process {
+    pub async fn run(mut self) {
+        if self.wait_for_dependencies().await {
+            self.supervise().await;
+        }
+        // The dependencies stop once every node depending on them did.
+        let _ = self.up.send(false);
+        let _ = self.exited.send(true);
+    }
+
+    /// Returns false if the launch stopped first.
+    async fn wait_for_dependencies(&mut self) -> bool {
+        for dependency in &mut self.dependencies {
+            tokio::select! {
+                _ = dependency.wait_for(|up| *up) => (),
+                _ = self.stop.wait_for(|stop| *stop) => return false,
+            }
+        }
+        true
+    }
+
+    async fn supervise(&mut self) {
+        let name = self.spec.name.clone();
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            let started = Instant::now();
+            match self.spawn() {
+                Ok(mut child) => {
+                    log::info!("Started {} (pid {}).", name, child.id().unwrap_or(0));
+                    let status = match self.watch(&mut child).await {
+                        Some(val) => val,
+                        None => {
+                            self.terminate(&mut child).await;
+                            return;
+                        }
+                    };
+                    let _ = self.up.send(false);
+
+                    let restart = match self.spec.restart {
+                        Restart::Always => true,
+                        Restart::OnFailure => !status.success(),
+                        Restart::Never => false,
+                    };
+                    if status.success() {
+                        log::info!("{} exited.", name);
+                    } else {
+                        self.launch.failed(&name, &format!("{} exited with {}", name, status));
+                    }
+                    if !restart {
+                        self.wait_for_stop().await;
+                        return;
+                    }
+                    if started.elapsed() >= STABLE_AFTER {
+                        backoff = MIN_BACKOFF;
+                    }
+                }
+                Err(e) => {
+                    self.launch.failed(&name, &format!("Failed to start {}: {}", name, e));
+                    if self.spec.restart == Restart::Never {
+                        self.wait_for_stop().await;
+                        return;
+                    }
+                }
+            }
+
+            log::info!("Restarting {} in {} s.", name, backoff.as_secs());
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => (),
+                _ = self.stop.wait_for(|stop| *stop) => return,
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    fn spawn(&self) -> std::io::Result<Child> {
+        let mut command = Command::new(&self.spec.command);
+        command
+            .args(&self.spec.args)
+            .envs(self.launch.env.iter().cloned())
+            .envs(&self.spec.env)
+            // The notify socket belongs to kf_launch when it runs under systemd.
+            .env_remove("NOTIFY_SOCKET")
+            .env_remove("WATCHDOG_USEC")
+            .env_remove("WATCHDOG_PID")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            // Keep a Ctrl-C in the terminal from reaching the nodes directly, the launcher stops them in order.
+            .process_group(0);
+        if let Some(dir) = &self.spec.working_dir {
+            command.current_dir(dir);
+        }
+
+        let mut child = command.spawn()?;
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(forward(self.spec.name.clone(), stdout, false));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(forward(self.spec.name.clone(), stderr, true));
+        }
+        Ok(child)
+    }
+
+    /// Wait for the child to exit, marking the node up once it ran for a while. Returns None if the launch stopped
+    /// first.
+    async fn watch(&mut self, child: &mut Child) -> Option<ExitStatus> {
+        let ready = tokio::time::sleep(Duration::from_secs_f64(self.spec.ready_after));
+        tokio::pin!(ready);
+        let mut is_up = false;
+        loop {
+            tokio::select! {
+                status = child.wait() => {
+                    return match status {
+                        Ok(val) => Some(val),
+                        Err(e) => {
+                            log::error!("Failed to wait for {}: {}", self.spec.name, e);
+                            let _ = child.kill().await;
+                            child.wait().await.ok()
+                        }
+                    };
+                },
+                _ = &mut ready, if !is_up => {
+                    is_up = true;
+                    let _ = self.up.send(true);
+                    self.launch.recovered(&self.spec.name);
+                },
+                _ = self.stop.wait_for(|stop| *stop) => return None,
+            }
+        }
+    }
+
+    async fn wait_for_stop(&mut self) {
+        let _ = self.stop.wait_for(|stop| *stop).await;
+    }
+
+    /// Stop the child with SIGTERM once the nodes depending on it are gone, and kill it if it takes too long.
+    async fn terminate(&mut self, child: &mut Child) {
+        for dependent in &mut self.dependents {
+            let _ = dependent.wait_for(|exited| *exited).await;
+        }
+
+        log::info!("Stopping {}.", self.spec.name);
+        if let Some(pid) = child.id() {
+            if let Err(e) = kill(Pid::from_raw(pid as i32), Signal::SIGTERM) {
+                log::error!("Failed to signal {}: {}", self.spec.name, e);
+            }
+        }
+        match tokio::time::timeout(self.launch.stop_timeout, child.wait()).await {
+            Ok(Ok(status)) => log::info!("{} stopped with {}.", self.spec.name, status),
+            Ok(Err(e)) => log::error!("Failed to wait for {}: {}", self.spec.name, e),
+            Err(_) => {
+                log::warn!("{} didn't stop in time, killing it.", self.spec.name);
+                let _ = child.kill().await;
+            }
+        }
+    }
+}
+
+/// Copy the output of a node to ours, one line at a time with the node name in front.
+async fn forward(name: String, output: impl AsyncRead + Unpin, stderr: bool) {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if stderr {
+            eprintln!("[{}] {}", name, line);
+        } else {
+            println!("[{}] {}", name, line);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Just enough HTTP to answer Prometheus scrapes of /metrics.
+use crate::store::MetricStore;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Time a client gets to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest request line or header accepted
+const MAX_LINE: usize = 8192;
+
+pub async fn serve(listener: TcpListener, store: Arc<Mutex<MetricStore>>, expire_after: Duration) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle(stream, store.clone(), expire_after));
+            }
+            Err(e) => {
+                log::error!("Failed to accept a connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+async fn handle(mut stream: TcpStream, store: Arc<Mutex<MetricStore>>, expire_after: Duration) {
+    let request = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Some(val)) => val,
+        _ => return,
+    };
+    let (method, path) = match request.split_once(' ') {
+        Some((method, rest)) => (method, rest.split(' ').next().unwrap_or("")),
+        None => return,
+    };
+    let path = path.split('?').next().unwrap_or("");
+
+    let response = if method != "GET" && method != "HEAD" {
+        http_response("405 Method Not Allowed", "text/plain", "Only GET is supported\n".to_string(), false)
+    } else if path == "/metrics" {
+        let body = store.lock().unwrap().render(expire_after);
+        http_response("200 OK", "text/plain; version=0.0.4; charset=utf-8", body, method == "HEAD")
+    } else {
+        http_response("404 Not Found", "text/plain", "The metrics are at /metrics\n".to_string(), method == "HEAD")
+    };
+    if let Err(e) = stream.write_all(response.as_bytes()).await {
+        log::debug!("Failed to send the response: {}", e);
+    }
+    let _ = stream.shutdown().await;
+}
+
+/// Read the request line and skip the headers.
+async fn read_request(stream: &mut TcpStream) -> Option<String> {
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = (&mut reader).take(MAX_LINE as u64).read_line(&mut line).await.ok()?;
+        if read == 0 || !line.ends_with('\n') {
+            return None;
+        }
+        if line.trim().is_empty() {
+            break;
+        }
+        if request.is_empty() {
+            request = line.trim().to_string();
+        }
+    }
+    Some(request)
+}
+
+fn http_response(status: &str, content_type: &str, body: String, head: bool) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        if head { "" } else { &body }
+    )
+}
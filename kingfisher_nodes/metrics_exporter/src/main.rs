@@ -0,0 +1,166 @@
+//! Exports the telemetry on DDS as Prometheus metrics on a local /metrics endpoint.
+//!
+//! Which topic fields become which metrics is set by the `[[metric]]` tables of the config, see metrics.toml.
+mod http;
+mod mapping;
+mod store;
+
+use clap::Parser;
+use dust_dds::{
+    dds_async::{data_reader::DataReaderAsync, domain_participant::DomainParticipantAsync, subscriber::SubscriberAsync},
+    infrastructure::{error::DdsResult, qos::QosKind, status::NO_STATUS},
+};
+use kingfisher_data_types::for_each_topic;
+use kingfisher_data_types::topic_registry::{subscribe, take_samples, Topic};
+use kingfisher_node::{Node, NodeArgs, VehicleFilter};
+use mapping::MetricMapping;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use store::MetricStore;
+use tokio::net::TcpListener;
+
+/// Most samples taken from a reader at once
+const MAX_SAMPLES: i32 = 100;
+
+/// Parser for command line parameters
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct CommandLineParameters {
+    #[command(flatten)]
+    node: NodeArgs,
+}
+
+#[derive(Deserialize)]
+struct Settings {
+    /// Address and port of the HTTP endpoint
+    #[serde(default = "default_listen")]
+    listen: String,
+    /// Series not updated for this long are dropped (s)
+    #[serde(default = "default_expire_after")]
+    expire_after: f64,
+    #[serde(default, rename = "metric")]
+    metrics: Vec<MetricMapping>,
+}
+
+fn default_listen() -> String {
+    "0.0.0.0:9530".to_string()
+}
+
+fn default_expire_after() -> f64 {
+    60.0
+}
+
+/// Update the metrics of a topic from every sample of the vehicles we export.
+async fn follow<T: Topic>(reader: DataReaderAsync<T::Data>, mappings: Vec<MetricMapping>, store: Arc<Mutex<MetricStore>>,
+                          vehicles: VehicleFilter) {
+    loop {
+        let samples = take_samples::<T>(&reader, MAX_SAMPLES).await;
+        let mut metrics = store.lock().unwrap();
+        for sample in samples {
+            let vehicle = T::vehicle_id(&sample.data);
+            if !vehicles.accepts(vehicle) {
+                continue;
+            }
+            let value = match serde_json::to_value(&sample.data) {
+                Ok(val) => val,
+                Err(e) => {
+                    log::error!("Failed to convert sample on {}: {}", T::NAME, e);
+                    continue;
+                }
+            };
+            for mapping in &mappings {
+                for (labels, number) in mapping.extract(&value, vec![("vehicle".to_string(), vehicle.to_string())]) {
+                    metrics.set(&mapping.name, labels, number);
+                }
+            }
+        }
+    }
+}
+
+/// Declares a function that starts exporting a topic by name.
+macro_rules! export_topic {
+    ($($topic:ty),*) => {
+        /// Returns None if there is no topic with the name.
+        async fn export_topic(name: &str, participant: &DomainParticipantAsync, subscriber: &SubscriberAsync,
+                              mappings: Vec<MetricMapping>, store: Arc<Mutex<MetricStore>>,
+                              vehicles: VehicleFilter) -> Option<DdsResult<()>> {
+            $(
+                if name == <$topic as Topic>::NAME {
+                    return Some(subscribe::<$topic>(participant, subscriber).await.map(|reader| {
+                        tokio::spawn(follow::<$topic>(reader, mappings, store, vehicles));
+                    }));
+                }
+            )*
+            None
+        }
+    };
+}
+
+for_each_topic!(export_topic);
+
+#[tokio::main]
+async fn main() {
+    let cli = CommandLineParameters::parse();
+    let node = Node::init("kf_metrics", env!("CARGO_PKG_VERSION"), &cli.node, "./metrics.toml");
+
+    let settings: Settings = match node.settings().clone().try_deserialize() {
+        Ok(val) => val,
+        Err(e) => {
+            log::error!("Invalid settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if settings.expire_after.is_nan() || settings.expire_after <= 0.0 {
+        log::error!("expire_after must be a positive number of seconds.");
+        std::process::exit(1);
+    }
+    let mut by_topic: BTreeMap<String, Vec<MetricMapping>> = BTreeMap::new();
+    for mapping in settings.metrics {
+        if let Err(e) = mapping.check() {
+            log::error!("Invalid metric: {}", e);
+            std::process::exit(1);
+        }
+        by_topic.entry(mapping.topic.clone()).or_default().push(mapping);
+    }
+    let all: Vec<MetricMapping> = by_topic.values().flatten().cloned().collect();
+    let store = match MetricStore::new(&all) {
+        Ok(val) => Arc::new(Mutex::new(val)),
+        Err(e) => {
+            log::error!("Invalid metric: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let participant = node.participant_async().await;
+    let subscriber = participant
+        .create_subscriber(QosKind::Default, None, NO_STATUS)
+        .await
+        .unwrap();
+    for (topic, mappings) in by_topic {
+        let count = mappings.len();
+        match export_topic(&topic, &participant, &subscriber, mappings, store.clone(), node.vehicle_filter()).await {
+            Some(Ok(())) => log::info!("Exporting {} metrics from {}.", count, topic),
+            Some(Err(e)) => log::error!("Failed to subscribe to {}, it won't be exported: {:?}", topic, e),
+            None => log::error!("Unknown topic {}, it won't be exported.", topic),
+        }
+    }
+
+    let listener = match TcpListener::bind(&settings.listen).await {
+        Ok(val) => val,
+        Err(e) => {
+            log::error!("Failed to listen on {}: {}", settings.listen, e);
+            std::process::exit(1);
+        }
+    };
+    log::info!("Serving metrics at http://{}/metrics.", settings.listen);
+    let expire_after = Duration::from_secs_f64(settings.expire_after);
+    let server = tokio::spawn(http::serve(listener, store, expire_after));
+
+    node.ready();
+    node.wait_for_shutdown().await;
+
+    server.abort();
+    node.close_async(participant).await;
+}
@@ -0,0 +1,140 @@
+//! Which fields of which topics become metrics, from the `[[metric]]` tables of the config.
+//!
+//! Samples are turned into JSON and the field is looked up by its dotted path. Arrays on the way are expanded, one
+//! series per element. Labels are read from the array element the value is in, or from the sample itself.
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Labels of one series, the vehicle first.
+pub type Labels = Vec<(String, String)>;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricKind {
+    #[default]
+    Gauge,
+    Counter,
+}
+
+impl MetricKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MetricKind::Gauge => "gauge",
+            MetricKind::Counter => "counter",
+        }
+    }
+}
+
+/// A mapping as written in the config.
+#[derive(Deserialize, Debug, Clone)]
+pub struct MetricMapping {
+    /// DDS topic name
+    pub topic: String,
+    /// Dotted path of the value in the sample, e.g. "cpus.usage"
+    pub field: String,
+    /// Prometheus metric name
+    pub name: String,
+    #[serde(default)]
+    pub help: String,
+    #[serde(default, rename = "type")]
+    pub kind: MetricKind,
+    /// Label names and the fields they are read from
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// Label for the position in an array of plain values, e.g. the axis of the IMU vectors
+    pub index_label: Option<String>,
+    /// Label values for the positions, the position number if not given
+    #[serde(default)]
+    pub index_names: Vec<String>,
+    /// Numbers for text values, e.g. the GPS fix
+    #[serde(default)]
+    pub values: BTreeMap<String, f64>,
+}
+
+fn valid_name(name: &str, colons: bool) -> bool {
+    let mut chars = name.chars();
+    let valid_char = |c: char, first: bool| c.is_ascii_alphabetic() || c == '_' || (colons && c == ':') || (!first && c.is_ascii_digit());
+    match chars.next() {
+        Some(c) if valid_char(c, true) => chars.all(|c| valid_char(c, false)),
+        _ => false,
+    }
+}
+
+impl MetricMapping {
+    pub fn check(&self) -> Result<(), String> {
+        if !valid_name(&self.name, true) {
+            return Err(format!("{} isn't a valid metric name", self.name));
+        }
+        for label in self.labels.keys().chain(self.index_label.iter()) {
+            if !valid_name(label, false) || label.starts_with("__") {
+                return Err(format!("{} of {} isn't a valid label name", label, self.name));
+            }
+            if label == "vehicle" {
+                return Err(format!("{} sets the vehicle label, which every series gets", self.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Values of the metric in a sample, with their labels after the given ones.
+    pub fn extract(&self, sample: &Value, labels: Labels) -> Vec<(Labels, f64)> {
+        let path: Vec<&str> = self.field.split('.').collect();
+        let mut labels = labels;
+        self.add_labels(sample, &mut labels);
+        let mut values = Vec::new();
+        self.walk(sample, &path, labels, &mut values);
+        values
+    }
+
+    fn walk(&self, value: &Value, path: &[&str], labels: Labels, values: &mut Vec<(Labels, f64)>) {
+        match value {
+            Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    let mut labels = labels.clone();
+                    if item.is_object() {
+                        self.add_labels(item, &mut labels);
+                    } else if let Some(label) = &self.index_label {
+                        let index = self.index_names.get(i).cloned().unwrap_or(i.to_string());
+                        labels.push((label.clone(), index));
+                    }
+                    self.walk(item, path, labels, values);
+                }
+            }
+            _ if !path.is_empty() => {
+                if let Some(next) = value.get(path[0]) {
+                    self.walk(next, &path[1..], labels, values);
+                }
+            }
+            // NaNs turn into nulls and are left out, as a missing reading.
+            Value::Number(number) => {
+                if let Some(number) = number.as_f64() {
+                    values.push((labels, number));
+                }
+            }
+            Value::Bool(flag) => values.push((labels, if *flag { 1.0 } else { 0.0 })),
+            Value::String(text) => {
+                if let Some(number) = self.values.get(text) {
+                    values.push((labels, *number));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Set the labels whose fields the object has.
+    fn add_labels(&self, object: &Value, labels: &mut Labels) {
+        for (label, field) in &self.labels {
+            let value = match object.get(field) {
+                Some(Value::String(text)) => text.clone(),
+                Some(Value::Number(number)) => number.to_string(),
+                Some(Value::Bool(flag)) => flag.to_string(),
+                _ => continue,
+            };
+            match labels.iter_mut().find(|(name, _)| name == label) {
+                Some(existing) => existing.1 = value,
+                None => labels.push((label.clone(), value)),
+            }
+        }
+    }
+}
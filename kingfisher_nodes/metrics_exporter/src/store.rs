@@ -0,0 +1,88 @@
+//! Latest value of every series, rendered in the Prometheus text format.
+use crate::mapping::{Labels, MetricKind, MetricMapping};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+struct Series {
+    value: f64,
+    updated: Instant,
+}
+
+struct Family {
+    help: String,
+    kind: MetricKind,
+    series: BTreeMap<Labels, Series>,
+}
+
+pub struct MetricStore {
+    families: BTreeMap<String, Family>,
+}
+
+impl MetricStore {
+    /// Mappings sharing a name must have the same type, the first help text is used.
+    pub fn new(mappings: &[MetricMapping]) -> Result<MetricStore, String> {
+        let mut families: BTreeMap<String, Family> = BTreeMap::new();
+        for mapping in mappings {
+            match families.get(&mapping.name) {
+                Some(family) if family.kind != mapping.kind => {
+                    return Err(format!("{} is both a {} and a {}", mapping.name, family.kind.name(), mapping.kind.name()));
+                }
+                Some(_) => (),
+                None => {
+                    families.insert(mapping.name.clone(), Family {
+                        help: mapping.help.clone(),
+                        kind: mapping.kind,
+                        series: BTreeMap::new(),
+                    });
+                }
+            }
+        }
+        Ok(MetricStore { families })
+    }
+
+    pub fn set(&mut self, name: &str, labels: Labels, value: f64) {
+        if let Some(family) = self.families.get_mut(name) {
+            family.series.insert(labels, Series { value, updated: Instant::now() });
+        }
+    }
+
+    /// The current values, dropping the series that weren't updated for expire_after, e.g. of a disk that was
+    /// unmounted or a vehicle that left.
+    pub fn render(&mut self, expire_after: Duration) -> String {
+        let mut text = String::new();
+        for (name, family) in &mut self.families {
+            family.series.retain(|_, series| series.updated.elapsed() < expire_after);
+            if family.series.is_empty() {
+                continue;
+            }
+            if !family.help.is_empty() {
+                let _ = writeln!(text, "# HELP {} {}", name, family.help.replace('\\', "\\\\").replace('\n', "\\n"));
+            }
+            let _ = writeln!(text, "# TYPE {} {}", name, family.kind.name());
+            for (labels, series) in &family.series {
+                let labels: Vec<String> = labels.iter()
+                    .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+                    .collect();
+                let _ = writeln!(text, "{}{{{}}} {}", name, labels.join(","), number(series.value));
+            }
+        }
+        text
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn number(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
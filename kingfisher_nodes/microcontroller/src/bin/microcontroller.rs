@@ -1,7 +1,7 @@
 use tokio::sync::mpsc;
 use tokio_serial::SerialPortBuilderExt;
-use tokio::signal;
 use clap::Parser;
+use kingfisher_node::{Node, NodeArgs};
 
 use microcontroller::serial_task::SerialTask;
 use microcontroller::dds_task::DDSTask;
@@ -10,6 +10,9 @@ use microcontroller::dds_task::DDSTask;
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    #[command(flatten)]
+    node: NodeArgs,
+
     ///The device path to a serial port
     #[arg(short, long, default_value_t = String::from("/dev/boat_control") )]
     port: String,
@@ -22,10 +25,8 @@ struct Cli {
 
 #[tokio::main]
 async fn main() {
-    pretty_env_logger::init();
-
-    log::info!("Starting the microcontroller interface.");
     let cli = Cli::parse();
+    let node = Node::init("microcontroller", env!("CARGO_PKG_VERSION"), &cli.node, "./microcontroller.toml");
 
     let port_name = &cli.port;
     let baud_rate = cli.baudrate;
@@ -51,18 +52,13 @@ async fn main() {
         serial_task.run().await;
     });
 
-    let mut dds_task = DDSTask::new(serial_tx, dds_rx);
+    let participant = node.participant_async().await;
+    let mut dds_task = DDSTask::new(participant.clone(), node.vehicle_id(), serial_tx, dds_rx);
     tokio::spawn(async move {
         dds_task.run().await;
     });
 
-    match signal::ctrl_c().await {
-        Ok(()) => {},
-        Err(err) => {
-            eprintln!("Unable to listen for shutdown signal: {}", err);
-            // we also shut down in case of error
-        },
-    }
-
+    node.ready();
+    node.wait_for_shutdown().await;
+    node.close_async(participant).await;
 }
-
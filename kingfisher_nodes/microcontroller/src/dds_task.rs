@@ -1,139 +1,267 @@
-//! This task handles DDS communications and relays them to the handler serial task.
-use kingfisher_data_types::microcontroller_types::{MicroControlMessages, MicroStatusMessages};
+//! This task handles DDS communications: it relays the control commands to the serial task and publishes the
+//! state the microcontroller reports.
+use dust_dds::{
+    dds_async::{data_reader::DataReaderAsync, data_writer::DataWriterAsync, domain_participant::DomainParticipantAsync},
+    infrastructure::{error::DdsError, qos::QosKind, status::NO_STATUS},
+};
+use kingfisher_data_types::dds_topics::{MicroControl, MicroStatus};
+use kingfisher_data_types::microcontroller_types::{
+    ControllerState, MicroControlMessages, MicroStatusMessages, Output, State
+};
+use kingfisher_data_types::qos_profiles::CONTROL_DEADLINE;
+use kingfisher_data_types::topic_registry::{publish, subscribe, MicroControlTopic, MicroStatusTopic, Topic};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
+/// How often the control reader is checked for commands
+const CONTROL_POLL_PERIOD: Duration = Duration::from_millis(20);
+/// Most commands taken at once
+const MAX_COMMANDS: i32 = 10;
+/// How often the state of the microcontroller is requested and published
+const STATUS_PERIOD: Duration = Duration::from_millis(200);
+/// Warn when the microcontroller hasn't answered for this long
+const STATUS_TIMEOUT: Duration = Duration::from_secs(2);
 
-// const CONTROL_READER_READY: Token = Token(0);
-// const CONTROL_READER_STATUS_READY: Token = Token(1);
-// const STATUS_WRITER_STATUS_READY: Token = Token(2);
-// const SERIAL_STATUS_READY: Token = Token(3);
+/// The sender currently driving, and when its last command arrived.
+struct Driver {
+    source: String,
+    last_command: Instant,
+}
 
 pub struct DDSTask {
+    participant: DomainParticipantAsync,
+    vehicle_id: String,
     to_serial: mpsc::Sender<MicroControlMessages>,
-    from_serial: mpsc::Receiver<MicroStatusMessages>
+    from_serial: mpsc::Receiver<MicroStatusMessages>,
+    driver: Option<Driver>,
+    /// Outputs from the last state report, the status is published when the controller state that follows arrives
+    state: Option<State>,
+    /// When the microcontroller last answered, None once we warned about it going quiet
+    last_report: Option<Instant>,
 }
 
 impl DDSTask {
 
     /// Create a new DDS Task
-    pub fn new (to_serial: mpsc::Sender<MicroControlMessages>, from_serial: mpsc::Receiver<MicroStatusMessages>) -> Self {
+    pub fn new (participant: DomainParticipantAsync, vehicle_id: &str, to_serial: mpsc::Sender<MicroControlMessages>,
+                from_serial: mpsc::Receiver<MicroStatusMessages>) -> Self {
         DDSTask {
+            participant,
+            vehicle_id: vehicle_id.to_string(),
             to_serial,
-            from_serial
+            from_serial,
+            driver: None,
+            state: None,
+            last_report: Some(Instant::now()),
         }
     }
 
-    /// Create a new DDS task.
+    /// Pass the control commands for this vehicle on to the serial task, and publish the state of the
+    /// microcontroller.
     pub async fn run(&mut self) {
+        let reader = match self.control_reader().await {
+            Ok(val) => val,
+            Err(e) => {
+                log::error!("Failed to subscribe to {}, tele-operation won't work: {:?}", MicroControlTopic::NAME, e);
+                return;
+            }
+        };
+        let writer = match self.status_writer().await {
+            Ok(val) => val,
+            Err(e) => {
+                log::error!("Failed to create the {} writer: {:?}", MicroStatusTopic::NAME, e);
+                return;
+            }
+        };
+
+        let mut poll = tokio::time::interval(CONTROL_POLL_PERIOD);
+        let mut status = tokio::time::interval(STATUS_PERIOD);
+        loop {
+            tokio::select! {
+                _ = poll.tick() => {
+                    self.take_commands(&reader).await;
+                    self.check_deadline().await;
+                }
+                _ = status.tick() => {
+                    self.request_status().await;
+                }
+                val = self.from_serial.recv() => {
+                    match val {
+                        Some(message) => self.handle_status(message, &writer).await,
+                        None => {
+                            log::error!("The serial task has stopped.");
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    async fn control_reader(&self) -> Result<DataReaderAsync<MicroControl>, DdsError> {
+        let subscriber = self.participant
+            .create_subscriber(QosKind::Default, None, NO_STATUS)
+            .await?;
+        subscribe::<MicroControlTopic>(&self.participant, &subscriber).await
+    }
 
+    async fn status_writer(&self) -> Result<DataWriterAsync<MicroStatus>, DdsError> {
+        let publisher = self.participant
+            .create_publisher(QosKind::Default, None, NO_STATUS)
+            .await?;
+        publish::<MicroStatusTopic>(&self.participant, &publisher).await
+    }
 
+    /// The microcontroller only reports its state when asked. It answers in order, so the controller state
+    /// completes the report.
+    async fn request_status(&mut self) {
+        if let Some(last_report) = self.last_report {
+            if last_report.elapsed() > STATUS_TIMEOUT {
+                log::warn!("The microcontroller hasn't reported its state for {} s.", STATUS_TIMEOUT.as_secs());
+                self.last_report = None;
+            }
+        }
+        for request in [MicroControlMessages::RequestState, MicroControlMessages::RequestControllerState] {
+            if let Err(e) = self.to_serial.send(request).await {
+                log::error!("Failed to request the state from the serial task: {}", e);
+            }
+        }
     }
 
-    // Task that runs in separate thread and handles DDS communication.
-    fn dds_loop(status_receiver: mpsc::Receiver<MicroStatusMessages>, control_to_serial: mpsc::Sender<MicroControlMessages>) {
-        // let poll = Poll::new().unwrap();
-        // let mut events = Events::with_capacity(4);
-
-        // //create domain participant
-        // let domain_participant = DomainParticipant::new(kingfisher_data_types::DEFAULT_DOMAIN)
-        //     .unwrap_or_else(|e| panic!("DomainParticipant construction failed: {:?}", e));
-
-        // //create quality of service
-        // let mut qos = QosPolicyBuilder::new()
-        //     .reliability( Reliability::Reliable {max_blocking_time: rustdds::Duration::DURATION_ZERO}).build();
-
-        // //create control listener topic
-        // let uc_control_topic = domain_participant
-        //     .create_topic(
-        //         kingfisher_data_types::dds_topics::MICROCONTROLLER_CONTROL_TOPIC.to_string(),
-        //         "microcontroller_control".to_string(),
-        //         &qos,
-        //         TopicKind::NoKey
-        //     ).unwrap_or_else(|e| panic!("create microcontroller control topic failed: {:?}", e));
-        
-        // let control_subscriber = domain_participant.create_subscriber(&qos).unwrap_or_else(|e| panic!("Creating the control subscriber failed: {:?}", e));
-        // let mut control_reader = control_subscriber
-        //   .create_datareader_no_key::<MicroControlMessages, CDRDeserializerAdapter<MicroControlMessages>>(&uc_control_topic, Some(qos))
-        //   .unwrap_or_else(|e| panic!("Creating the control reader failed: {:?}", e));
-        // //register reader with mio
-        // poll.registry().register(&mut control_reader, CONTROL_READER_READY, Interest::READABLE)
-        //   .unwrap_or_else(|e| panic!("Failed to register the reader ready event: {}", e));
-        // poll.registry().register(
-        //     control_reader.as_status_evented(),
-        //     CONTROL_READER_STATUS_READY,
-        //     Interest::READABLE
-        //   ).unwrap_or_else(|e| panic!("Failed to register the control reader status ready event: {:?}", e));
-
-        // //create status publisher
-        // let uc_status_topic = domain_participant
-        //     .create_topic(
-        //         kingfisher_data_types::dds_topics::MICROCONTROLLER_STATUS_TOPIC.to_string(),
-        //         "microcontroller_status".to_string(),
-        //     &qos,
-        //     TopicKind::NoKey,
-        // ).unwrap_or_else(|e| panic!("Create uc status topic failed: {:?}", e));
-        
-        // let status_publisher = domain_participant.create_publisher(&qos).unwrap_or_else(|e| panic!("Create status publisher failed: {:?}", e));
-        // let mut status_writer = status_publisher
-        //     .create_datawriter_no_key::<MicroStatusMessages, CDRDeserializerAdapter<MicroStatusMessages>>(&uc_status_topic, None) // None = get qos policy from publisher
-        //     .unwrap_or_else(|e| panic!("Create status writer failed: {:?}", e));
-        // //register publisher with mio
-        // poll.registry().register(
-        //     status_writer.as_status_evented(),
-        //     STATUS_WRITER_STATUS_READY,
-        //     Interest::READABLE
-        // ).unwrap_or_else(|e| panic!("Failed to register writer status even with mio: {:?}", e));
-
-        // //register communication from serial port
-        // poll.registry().register(
-        //     &mut status_receiver,
-        //     SERIAL_STATUS_READY,
-        //     Interest::READABLE
-        // ).unwrap_or_else(|e| panic!("Failed to register serial status channel with mio: {:?}", e));
-
-        // let loop_delay = std::time::Duration::from_millis(10);
-        // loop {
-        //     poll.poll(&mut events, Some(loop_delay)).unwrap();
-        //     for event in events {
-        //         match event.token() {
-        //             CONTROL_READER_READY => {
-        //                 loop {
-        //                     log::trace!("ControlReader triggered");
-        //                     match control_reader.take_next_sample() {
-        //                     Ok(Some(sample)) => match sample.into_value() {
-        //                         Ok(sample) => {
-        //                             log::info!("Received Control UC message: {:?}", sample);
-        //                             control_to_serial.try_send(sample).unwrap_or_else(|e| log::error!("Failed to send control message to serial port: {:?}", e))
-        //                         },
-        //                         Err(key) => println!("Disposed key {:?}", key),
-        //                     },
-        //                     Ok(None) => break, // no more data
-        //                     Err(e) => println!("DataReader error {:?}", e),
-        //                     } 
-        //                 }
-        //             },
-        //             CONTROL_READER_STATUS_READY => {
-        //                 while let Some(status) = control_reader.try_recv_status() {
-        //                     log::info!("ControlReader status: {:?}", status);
-        //                 }
-        //             },
-        //             STATUS_WRITER_STATUS_READY => {
-        //                 while let Some(status) = status_writer.try_recv_status() {
-        //                     log::info!("StatusWriter status: {:?}", status);
-        //                 }
-        //             },
-        //             SERIAL_STATUS_READY => {
-        //                 match status_receiver.try_recv() {
-        //                     Ok(status) => {
-        //                         log::trace!("Status message received from serial and being sent over DDS.");
-        //                         status_writer.write(status).unwrap_or_else(|e| log::error!("Failed to write uC status message to DDS bus: {:?}", e))
-        //                     },
-        //                     Err(e) => log::error!("Failed to get a status message from the serial port into the DDS thread: {:?}", e)
-        //                 }
-        //             },
-        //             _ => ()
-        //         }
-        //     }
-        // }
+    async fn handle_status(&mut self, message: MicroStatusMessages, writer: &DataWriterAsync<MicroStatus>) {
+        if self.last_report.is_none() {
+            log::info!("The microcontroller is reporting its state again.");
+        }
+        self.last_report = Some(Instant::now());
+        match message {
+            MicroStatusMessages::State(state) => self.state = Some(state),
+            MicroStatusMessages::ControllerState(controller) => {
+                if let Some(state) = self.state.take() {
+                    let status = self.status(state, controller);
+                    if let Err(e) = MicroStatusTopic::write(writer, &status).await {
+                        log::error!("Failed to send {} update: {:?}", MicroStatusTopic::NAME, e);
+                    }
+                }
+            }
+            MicroStatusMessages::Debug(text) => {
+                log::debug!("Microcontroller: {}", String::from_utf8_lossy(&text));
+            }
+        }
+    }
+
+    fn status(&self, state: State, controller: ControllerState) -> MicroStatus {
+        MicroStatus {
+            id: self.vehicle_id.clone(),
+            port_light: state.port_lights,
+            starboard_light: state.starboard_light,
+            port_power: state.port_power,
+            starboard_power: state.starboard_power,
+            port_throttle: throttle_from_duty(state.port_throttle),
+            starboard_throttle: throttle_from_duty(state.starboard_throttle),
+            rc_override: controller.overridden,
+            rc_throttle: controller.throttle,
+            rc_turn: controller.turn,
+            rc_switch: controller.switch,
+            driver: match &self.driver {
+                Some(driver) => driver.source.clone(),
+                None => String::new(),
+            },
+            time: match std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH) {
+                Ok(val) => val.as_secs_f64(),
+                Err(_) => 0.0
+            },
+        }
+    }
+
+    async fn take_commands(&mut self, reader: &DataReaderAsync<MicroControl>) {
+        let samples = match MicroControlTopic::take(reader, MAX_COMMANDS).await {
+            Ok(val) => val,
+            Err(DdsError::NoData) => return,
+            Err(e) => {
+                log::error!("Failed to take control commands: {:?}", e);
+                return;
+            }
+        };
+
+        let mut outputs = None;
+        for sample in samples {
+            if sample.data.id == self.vehicle_id {
+                if let Some(val) = self.handle_command(sample.data) {
+                    outputs = Some(val);
+                }
+            }
+        }
+        // Only the newest command matters.
+        if let Some(outputs) = outputs {
+            self.send(outputs).await;
+        }
     }
-}
\ No newline at end of file
+
+    /// The outputs for a command, None if it comes from someone other than the driver.
+    fn handle_command(&mut self, command: MicroControl) -> Option<Vec<Output>> {
+        if let Some(driver) = &self.driver {
+            if driver.source != command.source {
+                log::debug!("Ignoring the command from {}, {} is driving.", command.source, driver.source);
+                return None;
+            }
+        }
+
+        if command.armed {
+            if self.driver.is_none() {
+                log::info!("{} took control.", command.source);
+            }
+            self.driver = Some(Driver {
+                source: command.source.clone(),
+                last_command: Instant::now(),
+            });
+        } else if self.driver.take().is_some() {
+            log::info!("{} released control.", command.source);
+        }
+
+        let (port, starboard) = if command.armed {
+            (throttle(command.port_throttle), throttle(command.starboard_throttle))
+        } else {
+            (0, 0)
+        };
+        Some(vec![
+            Output::PortLight(command.port_light),
+            Output::StarboardLight(command.starboard_light),
+            Output::PortPower(command.port_power),
+            Output::StarboardPower(command.starboard_power),
+            Output::PortThrottle(port),
+            Output::StarboardThrottle(starboard),
+        ])
+    }
+
+    /// Stop the motors if the driver went quiet, e.g. the link dropped or the dashboard hung.
+    async fn check_deadline(&mut self) {
+        let expired = match &self.driver {
+            Some(driver) => driver.last_command.elapsed() > Duration::from_secs_f64(CONTROL_DEADLINE),
+            None => false,
+        };
+        if expired {
+            let driver = self.driver.take().unwrap();
+            log::warn!("No command from {} for {} s, stopping the motors.", driver.source, CONTROL_DEADLINE);
+            self.send(vec![Output::PortThrottle(0), Output::StarboardThrottle(0)]).await;
+        }
+    }
+
+    async fn send(&self, outputs: Vec<Output>) {
+        if let Err(e) = self.to_serial.send(MicroControlMessages::SetOutput(outputs)).await {
+            log::error!("Failed to send the outputs to the serial task: {}", e);
+        }
+    }
+}
+
+/// Scale a throttle from -1..1 to the microcontroller's -127..127.
+fn throttle(value: f32) -> i8 {
+    if value.is_nan() {
+        return 0;
+    }
+    (value.clamp(-1.0, 1.0) * 127.0).round() as i8
+}
+
+/// Scale a duty cycle reported by the microcontroller, 127 at rest, back to -1..1.
+fn throttle_from_duty(duty: u8) -> f32 {
+    ((duty as f32 - 127.0) / 127.0).clamp(-1.0, 1.0)
+}
@@ -32,7 +32,7 @@ impl SerialTask {
         loop {
             tokio::select! {
                 val = self.read_into_serial.recv() => {
-                    log::info!("Received message from DDS task.");
+                    log::debug!("Received message from DDS task.");
                     if let Some(packet) = val {
                         match self.serial_sink.send(packet).await {
                             Ok(_) => (),
@@ -43,11 +43,11 @@ impl SerialTask {
                     }
                 }
                 val = self.serial_source.next() => {
-                    log::info!("Received serial data in serial task.");
+                    log::debug!("Received serial data in serial task.");
                     if let Some(packet) = val {
                         match packet {
                             Ok(packet) => {
-                                log::info!("Sending parsed packet to DDS");
+                                log::debug!("Sending parsed packet to DDS");
                                 match self.send_to_dds.send(packet).await {
                                     Ok(_) => (),
                                     Err(e) => {
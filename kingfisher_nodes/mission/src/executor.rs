@@ -0,0 +1,259 @@
+//! The mission state machine: which waypoint to head for, when it counts as reached and how long to loiter there.
+use kingfisher_data_types::dds_topics::{GeoPoint, GeofenceZone, Mission, MissionState};
+use std::time::{Duration, Instant};
+
+/// Largest acceptance radius of a waypoint (m)
+pub const MAX_ACCEPTANCE_RADIUS: f32 = 1000.0;
+/// Fastest a waypoint may be approached at (m/s), well past what the vehicle does
+pub const MAX_SPEED: f32 = 10.0;
+/// Longest loiter at a waypoint (s)
+pub const MAX_LOITER_TIME: f32 = 86_400.0;
+
+/// How the speed is shaped around the waypoints.
+#[derive(Debug, Clone, Copy)]
+pub struct Tuning {
+    /// The speed ramps down within this distance of a waypoint (m)
+    pub slowdown_radius: f64,
+    /// Slowest speed asked for while heading for a waypoint (m/s)
+    pub min_speed: f64,
+    /// Speed to get back to a waypoint at after drifting off it while loitering (m/s)
+    pub station_keeping_speed: f64,
+}
+
+/// Where to go, for the navigation setpoint.
+#[derive(Debug, Clone, Copy)]
+pub struct Guidance {
+    /// Degrees from true north
+    pub heading: f64,
+    /// Speed over ground (m/s)
+    pub speed: f64,
+}
+
+pub struct Executor {
+    mission: Mission,
+    state: MissionState,
+    current: usize,
+    tuning: Tuning,
+    /// When the loiter at the current waypoint ends
+    loiter_end: Option<Instant>,
+    /// State and loiter time left when paused, to pick up from on resume
+    paused_from: MissionState,
+    loiter_left: Duration,
+}
+
+impl Executor {
+    pub fn new(vehicle_id: &str, tuning: Tuning) -> Self {
+        Executor {
+            mission: Mission {
+                id: vehicle_id.to_string(),
+                name: String::new(),
+                waypoints: Vec::new(),
+            },
+            state: MissionState::Idle,
+            current: 0,
+            tuning,
+            loiter_end: None,
+            paused_from: MissionState::Running,
+            loiter_left: Duration::ZERO,
+        }
+    }
+
+    pub fn mission(&self) -> &Mission {
+        &self.mission
+    }
+
+    pub fn state(&self) -> MissionState {
+        self.state
+    }
+
+    /// Index of the waypoint being headed for, the waypoint count once completed.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Whether the vehicle is meant to be under way or holding a waypoint.
+    pub fn active(&self) -> bool {
+        matches!(self.state, MissionState::Running | MissionState::Loitering)
+    }
+
+    /// Replace the mission. It waits for a start, even if the previous one was running.
+    pub fn load(&mut self, mission: Mission) {
+        if self.active() || self.state == MissionState::Paused {
+            log::warn!("Mission {} replaced by {} while under way.", self.mission.name, mission.name);
+        }
+        log::info!("Loaded mission {} with {} waypoints.", mission.name, mission.waypoints.len());
+        self.mission = mission;
+        self.state = MissionState::Idle;
+        self.current = 0;
+        self.loiter_end = None;
+    }
+
+    pub fn start(&mut self) -> Result<(), String> {
+        if self.mission.waypoints.is_empty() {
+            return Err("No mission loaded".to_string());
+        }
+        log::info!("Starting mission {}.", self.mission.name);
+        self.state = MissionState::Running;
+        self.current = 0;
+        self.loiter_end = None;
+        Ok(())
+    }
+
+    pub fn pause(&mut self) -> Result<(), String> {
+        if !self.active() {
+            return Err(format!("Can't pause a mission that is {:?}", self.state));
+        }
+        self.paused_from = self.state;
+        self.loiter_left = match self.loiter_end {
+            Some(end) => end.saturating_duration_since(Instant::now()),
+            None => Duration::ZERO,
+        };
+        self.state = MissionState::Paused;
+        log::info!("Paused mission {} at waypoint {}.", self.mission.name, self.current + 1);
+        Ok(())
+    }
+
+    pub fn resume(&mut self) -> Result<(), String> {
+        if self.state != MissionState::Paused {
+            return Err(format!("Can't resume a mission that is {:?}", self.state));
+        }
+        self.state = self.paused_from;
+        if self.state == MissionState::Loitering {
+            self.loiter_end = Some(Instant::now() + self.loiter_left);
+        }
+        log::info!("Resumed mission {} at waypoint {}.", self.mission.name, self.current + 1);
+        Ok(())
+    }
+
+    pub fn abort(&mut self) -> Result<(), String> {
+        if !self.active() && self.state != MissionState::Paused {
+            return Err(format!("Can't abort a mission that is {:?}", self.state));
+        }
+        log::warn!("Aborted mission {} at waypoint {}.", self.mission.name, self.current + 1);
+        self.state = MissionState::Aborted;
+        self.loiter_end = None;
+        Ok(())
+    }
+
+    /// Head for the next waypoint. A paused mission stays paused.
+    pub fn skip(&mut self) -> Result<(), String> {
+        if !self.active() && self.state != MissionState::Paused {
+            return Err(format!("Can't skip a waypoint of a mission that is {:?}", self.state));
+        }
+        log::info!("Skipping waypoint {} of mission {}.", self.current + 1, self.mission.name);
+        if self.state == MissionState::Paused {
+            self.paused_from = MissionState::Running;
+            self.current += 1;
+            if self.current >= self.mission.waypoints.len() {
+                self.complete();
+            }
+        } else {
+            self.advance();
+        }
+        Ok(())
+    }
+
+    /// Loiter time left at the current waypoint (s).
+    pub fn loiter_remaining(&self) -> f64 {
+        match (self.state, self.loiter_end) {
+            (MissionState::Loitering, Some(end)) => end.saturating_duration_since(Instant::now()).as_secs_f64(),
+            (MissionState::Paused, _) => self.loiter_left.as_secs_f64(),
+            _ => 0.0,
+        }
+    }
+
+    /// Distance (m) and bearing (degrees) to the current waypoint.
+    pub fn to_waypoint(&self, position: &GeoPoint) -> Option<(f64, f64)> {
+        let waypoint = GeoPoint::from(self.mission.waypoints.get(self.current)?);
+        Some((position.distance_to(&waypoint), position.bearing_to(&waypoint)))
+    }
+
+    /// Move the mission along from the vehicle's position. Returns where to go, None to stop the vehicle.
+    pub fn update(&mut self, position: &GeoPoint) -> Option<Guidance> {
+        // Each pass either returns or moves on to the next waypoint, so this ends.
+        loop {
+            let waypoint = self.mission.waypoints.get(self.current)?.clone();
+            let (distance, bearing) = self.to_waypoint(position)?;
+            let reached = distance <= waypoint.acceptance_radius as f64;
+            match self.state {
+                MissionState::Running if reached => {
+                    log::info!("Reached waypoint {} of mission {}.", self.current + 1, self.mission.name);
+                    if waypoint.loiter_time > 0.0 {
+                        self.state = MissionState::Loitering;
+                        self.loiter_end = Some(Instant::now() + Duration::from_secs_f32(waypoint.loiter_time));
+                        return None;
+                    }
+                    self.advance();
+                }
+                MissionState::Running => {
+                    // Ramp the speed down on the final approach, so the waypoint isn't overshot.
+                    let ramp = waypoint.speed as f64 * distance / self.tuning.slowdown_radius;
+                    let speed = ramp.max(self.tuning.min_speed).min(waypoint.speed as f64);
+                    return Some(Guidance { heading: bearing, speed });
+                }
+                MissionState::Loitering => {
+                    if self.loiter_end.is_some_and(|end| Instant::now() >= end) {
+                        self.advance();
+                        continue;
+                    }
+                    if reached {
+                        return None;
+                    }
+                    return Some(Guidance { heading: bearing, speed: self.tuning.station_keeping_speed });
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    fn advance(&mut self) {
+        self.current += 1;
+        self.loiter_end = None;
+        if self.current >= self.mission.waypoints.len() {
+            self.complete();
+        } else {
+            self.state = MissionState::Running;
+        }
+    }
+
+    fn complete(&mut self) {
+        log::info!("Completed mission {}.", self.mission.name);
+        self.state = MissionState::Completed;
+        self.current = self.mission.waypoints.len();
+    }
+}
+
+/// Check a mission can be run, and that its waypoints and the legs between them are all allowed by the geofence.
+pub fn check_mission(mission: &Mission, geofence: &[GeofenceZone]) -> Result<(), String> {
+    if mission.waypoints.is_empty() {
+        return Err(format!("Mission {} has no waypoints", mission.name));
+    }
+    for (i, waypoint) in mission.waypoints.iter().enumerate() {
+        let number = i + 1;
+        if !(-90.0..=90.0).contains(&waypoint.latitude) || !(-180.0..=180.0).contains(&waypoint.longitude) {
+            return Err(format!("Waypoint {} is not a valid position", number));
+        }
+        // The comparisons are false for NaN, which is refused with the rest.
+        if !(waypoint.acceptance_radius > 0.0 && waypoint.acceptance_radius <= MAX_ACCEPTANCE_RADIUS) {
+            return Err(format!("Waypoint {} needs an acceptance radius above 0 and up to {} m", number,
+                               MAX_ACCEPTANCE_RADIUS));
+        }
+        if !(waypoint.speed > 0.0 && waypoint.speed <= MAX_SPEED) {
+            return Err(format!("Waypoint {} needs a speed above 0 and up to {} m/s", number, MAX_SPEED));
+        }
+        if !(waypoint.loiter_time >= 0.0 && waypoint.loiter_time <= MAX_LOITER_TIME) {
+            return Err(format!("Waypoint {} needs a loiter time from 0 to {} s", number, MAX_LOITER_TIME));
+        }
+        if let Some(zone) = geofence.iter().find(|zone| !zone.allows(&GeoPoint::from(waypoint))) {
+            return Err(format!("Waypoint {} is {} geofence zone {}", number,
+                               if zone.keep_in { "outside" } else { "inside" }, zone.name));
+        }
+    }
+    for (i, leg) in mission.waypoints.windows(2).enumerate() {
+        let (from, to) = (GeoPoint::from(&leg[0]), GeoPoint::from(&leg[1]));
+        if let Some(zone) = geofence.iter().find(|zone| zone.crosses(&from, &to)) {
+            return Err(format!("The leg from waypoint {} to {} crosses geofence zone {}", i + 1, i + 2, zone.name));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,194 @@
+//! Mission files: JSON in the layout of the Mission topic, or GPX routes to swap with other planning tools.
+//!
+//! GPX has no acceptance radius, speed or loiter time, they are kept in `kf:` extensions of each route point. Points
+//! without them, e.g. from another tool, get the defaults from the config.
+use kingfisher_data_types::dds_topics::{Mission, Waypoint};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::reader::Reader;
+use std::path::{Path, PathBuf};
+
+/// Namespace of the GPX extensions
+const GPX_NAMESPACE: &str = "urn:kingfisher:mission";
+
+/// Waypoint settings for the points of a GPX file that don't have them.
+#[derive(Debug, Clone, Copy)]
+pub struct Defaults {
+    pub acceptance_radius: f32,
+    pub speed: f32,
+    pub loiter_time: f32,
+}
+
+#[derive(PartialEq)]
+enum Format {
+    Json,
+    Gpx,
+}
+
+fn format(path: &Path) -> Result<Format, String> {
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref() {
+        Some("json") => Ok(Format::Json),
+        Some("gpx") => Ok(Format::Gpx),
+        _ => Err(format!("{} isn't a .json or .gpx file", path.display())),
+    }
+}
+
+/// Path of a file in the mission directory. Names that would lead out of it are refused, as they come over DDS.
+pub fn mission_path(dir: &str, file: &str) -> Result<PathBuf, String> {
+    if file.is_empty() || file.starts_with('.') || file.contains(['/', '\\']) {
+        return Err(format!("Invalid mission file name \"{}\"", file));
+    }
+    Ok(Path::new(dir).join(file))
+}
+
+pub fn load(path: &Path, vehicle_id: &str, defaults: &Defaults) -> Result<Mission, String> {
+    let format = format(path)?;
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut mission = match format {
+        Format::Json => serde_json::from_str::<Mission>(&text)
+            .map_err(|e| format!("Invalid mission in {}: {}", path.display(), e))?,
+        Format::Gpx => {
+            let (name, waypoints) = from_gpx(&text, defaults)
+                .map_err(|e| format!("Invalid GPX in {}: {}", path.display(), e))?;
+            Mission {
+                id: String::new(),
+                name,
+                waypoints,
+            }
+        }
+    };
+    // The file may come from another vehicle.
+    mission.id = vehicle_id.to_string();
+    if mission.name.is_empty() {
+        mission.name = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    }
+    Ok(mission)
+}
+
+pub fn save(path: &Path, mission: &Mission) -> Result<(), String> {
+    let text = match format(path)? {
+        Format::Json => serde_json::to_string_pretty(mission).map_err(|e| format!("Failed to convert the mission: {}", e))?,
+        Format::Gpx => to_gpx(mission),
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    std::fs::write(path, text).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Name and waypoints of a GPX file. The points of the first route are used, the waypoints if there is no route.
+fn from_gpx(text: &str, defaults: &Defaults) -> Result<(String, Vec<Waypoint>), String> {
+    let mut reader = Reader::from_str(text);
+    reader.config_mut().trim_text(true);
+    let mut name = String::new();
+    let mut route_points = Vec::new();
+    let mut waypoints = Vec::new();
+    let mut routes = 0;
+    // Point being read, and whether it's a route point
+    let mut point: Option<(Waypoint, bool)> = None;
+    // Innermost element the text belongs to
+    let mut element: Vec<u8> = Vec::new();
+
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Start(e) => {
+                let local = e.local_name().as_ref().to_vec();
+                match local.as_slice() {
+                    b"rte" => routes += 1,
+                    b"rtept" | b"wpt" => point = Some((read_point(&e, defaults)?, local == b"rtept")),
+                    _ => (),
+                }
+                element = local;
+            }
+            Event::Empty(e) => {
+                let local = e.local_name().as_ref().to_vec();
+                if local == b"rtept" && routes == 1 {
+                    route_points.push(read_point(&e, defaults)?);
+                } else if local == b"wpt" {
+                    waypoints.push(read_point(&e, defaults)?);
+                }
+            }
+            Event::Text(e) => {
+                let text = e.unescape().map_err(|e| e.to_string())?;
+                match (&mut point, element.as_slice()) {
+                    (Some((waypoint, _)), b"acceptance_radius") => waypoint.acceptance_radius = number(&text)?,
+                    (Some((waypoint, _)), b"speed") => waypoint.speed = number(&text)?,
+                    (Some((waypoint, _)), b"loiter_time") => waypoint.loiter_time = number(&text)?,
+                    (None, b"name") if name.is_empty() => name = text.to_string(),
+                    _ => (),
+                }
+            }
+            Event::End(e) => {
+                let local = e.local_name();
+                if local.as_ref() == b"rtept" || local.as_ref() == b"wpt" {
+                    match point.take() {
+                        Some((waypoint, true)) if routes == 1 => route_points.push(waypoint),
+                        Some((waypoint, false)) => waypoints.push(waypoint),
+                        _ => (),
+                    }
+                }
+                element.clear();
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+    }
+    Ok((name, if route_points.is_empty() { waypoints } else { route_points }))
+}
+
+fn read_point(element: &BytesStart, defaults: &Defaults) -> Result<Waypoint, String> {
+    let mut latitude = None;
+    let mut longitude = None;
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| e.to_string())?;
+        let value = attribute.unescape_value().map_err(|e| e.to_string())?;
+        match attribute.key.as_ref() {
+            b"lat" => latitude = Some(number::<f64>(&value)?),
+            b"lon" => longitude = Some(number::<f64>(&value)?),
+            _ => (),
+        }
+    }
+    match (latitude, longitude) {
+        (Some(latitude), Some(longitude)) => Ok(Waypoint {
+            latitude,
+            longitude,
+            acceptance_radius: defaults.acceptance_radius,
+            speed: defaults.speed,
+            loiter_time: defaults.loiter_time,
+        }),
+        _ => Err("A point is missing its lat or lon".to_string()),
+    }
+}
+
+fn number<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.trim().parse().map_err(|_| format!("\"{}\" is not a number", text))
+}
+
+fn to_gpx(mission: &Mission) -> String {
+    let mut gpx = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <gpx version=\"1.1\" creator=\"kingfisher mission\" xmlns=\"http://www.topografix.com/GPX/1/1\" xmlns:kf=\"{}\">\n\
+         \x20 <rte>\n\
+         \x20   <name>{}</name>\n",
+        GPX_NAMESPACE,
+        escape(&mission.name)
+    );
+    for (i, waypoint) in mission.waypoints.iter().enumerate() {
+        gpx += &format!(
+            "    <rtept lat=\"{}\" lon=\"{}\">\n\
+             \x20     <name>{}</name>\n\
+             \x20     <extensions>\n\
+             \x20       <kf:acceptance_radius>{}</kf:acceptance_radius>\n\
+             \x20       <kf:speed>{}</kf:speed>\n\
+             \x20       <kf:loiter_time>{}</kf:loiter_time>\n\
+             \x20     </extensions>\n\
+             \x20   </rtept>\n",
+            waypoint.latitude, waypoint.longitude, i + 1, waypoint.acceptance_radius, waypoint.speed, waypoint.loiter_time
+        );
+    }
+    gpx += "  </rte>\n</gpx>\n";
+    gpx
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,485 @@
+//! Runs waypoint missions: follows the vehicle along the waypoints with the GPS and publishes the heading and speed
+//! to steer for, for the controller node.
+//!
+//! Missions come from the Mission topic, from files in the mission directory or a go to request from the dashboard
+//! map. They are checked against the geofence of the config before they are accepted, and a running mission is
+//! paused when the vehicle finds itself outside the fence.
+mod executor;
+mod files;
+
+use clap::Parser;
+use dust_dds::{
+    dds_async::{data_reader::DataReaderAsync, data_writer::DataWriterAsync},
+    infrastructure::{error::DdsError, qos::QosKind, status::NO_STATUS},
+};
+use executor::{check_mission, Executor, Guidance, Tuning, MAX_ACCEPTANCE_RADIUS, MAX_LOITER_TIME, MAX_SPEED};
+use files::Defaults;
+use kingfisher_data_types::dds_topics::{
+    GeoPoint, Geofence, GeofenceZone, GoTo, GpsData, GpsFix, Mission, MissionCommand, MissionCommandKind,
+    MissionState, MissionStatus, NavigationSetpoint, Waypoint
+};
+use kingfisher_data_types::qos_profiles::CONTROL_DEADLINE;
+use kingfisher_data_types::topic_registry::{
+    publish, subscribe, GeofenceTopic, GoToTopic, GpsTopic, MissionCommandTopic, MissionStatusTopic, MissionTopic,
+    NavigationSetpointTopic, Topic
+};
+use kingfisher_node::{Node, NodeArgs};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+/// Most samples taken from a reader at once
+const MAX_SAMPLES: i32 = 10;
+/// The status is published at least this often, and straight away when the state changes
+const STATUS_PERIOD: Duration = Duration::from_secs(1);
+
+/// Parser for command line parameters
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct CommandLineParameters {
+    #[command(flatten)]
+    node: NodeArgs,
+
+    /// Mission file (.json or .gpx) to load on start, it still has to be started
+    #[arg(short, long)]
+    mission: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ZoneSettings {
+    name: String,
+    #[serde(default = "default_keep_in")]
+    keep_in: bool,
+    /// Corners as [latitude, longitude]
+    points: Vec<[f64; 2]>,
+}
+
+fn default_keep_in() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+struct Settings {
+    /// How often the setpoint is published (Hz)
+    #[serde(default = "default_update_rate")]
+    update_rate: f64,
+    /// The vehicle stops when the last fix is older than this (s)
+    #[serde(default = "default_gps_timeout")]
+    gps_timeout: f64,
+    /// Where the Load and Save commands read and write mission files
+    #[serde(default = "default_mission_dir")]
+    mission_dir: String,
+    /// Waypoint settings for GPX points and go to requests, which don't have their own
+    #[serde(default = "default_acceptance_radius")]
+    acceptance_radius: f32,
+    #[serde(default = "default_speed")]
+    speed: f32,
+    #[serde(default)]
+    loiter_time: f32,
+    #[serde(default = "default_slowdown_radius")]
+    slowdown_radius: f64,
+    #[serde(default = "default_min_speed")]
+    min_speed: f64,
+    #[serde(default = "default_station_keeping_speed")]
+    station_keeping_speed: f64,
+    #[serde(default)]
+    geofence: Vec<ZoneSettings>,
+}
+
+fn default_update_rate() -> f64 {
+    5.0
+}
+
+fn default_gps_timeout() -> f64 {
+    3.0
+}
+
+fn default_mission_dir() -> String {
+    "/var/lib/kingfisher/missions".to_string()
+}
+
+fn default_acceptance_radius() -> f32 {
+    5.0
+}
+
+fn default_speed() -> f32 {
+    1.0
+}
+
+fn default_slowdown_radius() -> f64 {
+    10.0
+}
+
+fn default_min_speed() -> f64 {
+    0.3
+}
+
+fn default_station_keeping_speed() -> f64 {
+    0.5
+}
+
+impl Settings {
+    fn check(&self) -> Result<(), String> {
+        let positive = [
+            ("update_rate", self.update_rate),
+            ("gps_timeout", self.gps_timeout),
+            ("acceptance_radius", self.acceptance_radius as f64),
+            ("speed", self.speed as f64),
+            ("slowdown_radius", self.slowdown_radius),
+            ("station_keeping_speed", self.station_keeping_speed),
+        ];
+        for (name, value) in positive {
+            if value.is_nan() || value <= 0.0 {
+                return Err(format!("{} must be positive", name));
+            }
+        }
+        // The setpoint is a control topic, slower than its deadline and the controller would stop between setpoints.
+        if !(self.update_rate > 1.0 / CONTROL_DEADLINE && self.update_rate.is_finite()) {
+            return Err(format!("update_rate must be above {} Hz", 1.0 / CONTROL_DEADLINE));
+        }
+        // The waypoint defaults are held to the limits of the missions they go into.
+        if self.acceptance_radius > MAX_ACCEPTANCE_RADIUS {
+            return Err(format!("acceptance_radius can't be over {} m", MAX_ACCEPTANCE_RADIUS));
+        }
+        if self.speed > MAX_SPEED {
+            return Err(format!("speed can't be over {} m/s", MAX_SPEED));
+        }
+        if !(self.loiter_time >= 0.0 && self.loiter_time <= MAX_LOITER_TIME) {
+            return Err(format!("loiter_time must be from 0 to {} s", MAX_LOITER_TIME));
+        }
+        if self.min_speed.is_nan() || self.min_speed < 0.0 {
+            return Err("min_speed can't be negative".to_string());
+        }
+        for zone in &self.geofence {
+            if zone.points.len() < 3 {
+                return Err(format!("Geofence zone {} needs at least 3 points", zone.name));
+            }
+        }
+        Ok(())
+    }
+
+    fn defaults(&self) -> Defaults {
+        Defaults {
+            acceptance_radius: self.acceptance_radius,
+            speed: self.speed,
+            loiter_time: self.loiter_time,
+        }
+    }
+
+    fn tuning(&self) -> Tuning {
+        Tuning {
+            slowdown_radius: self.slowdown_radius,
+            min_speed: self.min_speed,
+            station_keeping_speed: self.station_keeping_speed,
+        }
+    }
+
+    fn geofence(&self) -> Vec<GeofenceZone> {
+        self.geofence.iter().map(|zone| GeofenceZone {
+            name: zone.name.clone(),
+            keep_in: zone.keep_in,
+            points: zone.points.iter().map(|p| GeoPoint::new(p[0], p[1])).collect(),
+        }).collect()
+    }
+}
+
+/// The executor and everything it's fed with.
+struct MissionNode {
+    vehicle_id: String,
+    settings: Settings,
+    geofence: Vec<GeofenceZone>,
+    executor: Executor,
+    /// Last usable fix and when it arrived
+    fix: Option<(GeoPoint, Instant)>,
+    /// Why the last request was refused, for the status
+    message: String,
+    mission_writer: DataWriterAsync<Mission>,
+    status_writer: DataWriterAsync<MissionStatus>,
+    setpoint_writer: DataWriterAsync<NavigationSetpoint>,
+    /// State and waypoint of the last status sent, and when
+    last_status: Option<(MissionState, usize, String, Instant)>,
+}
+
+impl MissionNode {
+    async fn handle_commands(&mut self, reader: &DataReaderAsync<MissionCommand>) {
+        for command in take::<MissionCommandTopic>(reader).await {
+            if command.id != self.vehicle_id {
+                continue;
+            }
+            log::info!("Received {:?} command.", command.command);
+            let result = match command.command {
+                MissionCommandKind::Start => self.executor.start(),
+                MissionCommandKind::Pause => self.executor.pause(),
+                MissionCommandKind::Resume => self.executor.resume(),
+                MissionCommandKind::Abort => self.executor.abort(),
+                MissionCommandKind::Skip => self.executor.skip(),
+                MissionCommandKind::Load => {
+                    match files::mission_path(&self.settings.mission_dir, &command.file) {
+                        Ok(path) => self.load_file(&path.to_string_lossy()).await,
+                        Err(e) => Err(e),
+                    }
+                }
+                MissionCommandKind::Save => {
+                    files::mission_path(&self.settings.mission_dir, &command.file)
+                        .and_then(|path| files::save(&path, self.executor.mission()).map(|_| path))
+                        .map(|path| log::info!("Saved mission {} to {}.", self.executor.mission().name, path.display()))
+                }
+            };
+            self.result(result);
+        }
+    }
+
+    async fn handle_missions(&mut self, reader: &DataReaderAsync<Mission>) {
+        for mission in take::<MissionTopic>(reader).await {
+            // Our own missions come back too, from files and go to requests.
+            if mission.id != self.vehicle_id || mission == *self.executor.mission() {
+                continue;
+            }
+            let result = self.accept(mission, false).await;
+            self.result(result);
+        }
+    }
+
+    async fn handle_go_to(&mut self, reader: &DataReaderAsync<GoTo>) {
+        for request in take::<GoToTopic>(reader).await {
+            if request.id != self.vehicle_id {
+                continue;
+            }
+            log::info!("{} sent us to ({:.6}, {:.6}).", request.source, request.latitude, request.longitude);
+            let mission = Mission {
+                id: self.vehicle_id.clone(),
+                name: "Go to".to_string(),
+                waypoints: vec![Waypoint {
+                    latitude: request.latitude,
+                    longitude: request.longitude,
+                    acceptance_radius: self.settings.acceptance_radius,
+                    speed: self.settings.speed,
+                    loiter_time: 0.0,
+                }],
+            };
+            let result = match self.accept(mission, true).await {
+                Ok(()) => self.executor.start(),
+                Err(e) => Err(e),
+            };
+            self.result(result);
+        }
+    }
+
+    async fn handle_gps(&mut self, reader: &DataReaderAsync<GpsData>) {
+        for fix in take::<GpsTopic>(reader).await {
+            if fix.id != self.vehicle_id {
+                continue;
+            }
+            let usable = !matches!(fix.fix, GpsFix::None) && fix.latitude.is_finite() && fix.longitude.is_finite();
+            if usable {
+                self.fix = Some((GeoPoint::new(fix.latitude, fix.longitude), Instant::now()));
+            }
+        }
+    }
+
+    async fn load_file(&mut self, path: &str) -> Result<(), String> {
+        let mission = files::load(std::path::Path::new(path), &self.vehicle_id, &self.settings.defaults())?;
+        self.accept(mission, true).await
+    }
+
+    /// Check a mission and load it. Missions that didn't come from the topic are published on it, for the map.
+    async fn accept(&mut self, mission: Mission, publish: bool) -> Result<(), String> {
+        check_mission(&mission, &self.geofence)?;
+        if publish {
+            if let Err(e) = MissionTopic::write(&self.mission_writer, &mission).await {
+                log::error!("Failed to send the mission: {:?}", e);
+            }
+        }
+        self.executor.load(mission);
+        Ok(())
+    }
+
+    fn result(&mut self, result: Result<(), String>) {
+        match result {
+            Ok(()) => self.message.clear(),
+            Err(e) => {
+                log::warn!("{}", e);
+                self.message = e;
+            }
+        }
+    }
+
+    /// Move the mission along and publish the setpoint and status.
+    async fn update(&mut self) {
+        let gps_timeout = Duration::from_secs_f64(self.settings.gps_timeout);
+        let position = match self.fix {
+            Some((position, received)) if received.elapsed() < gps_timeout => Some(position),
+            _ => None,
+        };
+        if let Some(position) = &position {
+            self.check_geofence(position);
+        }
+        let guidance = match &position {
+            Some(position) => self.executor.update(position),
+            None => None,
+        };
+        let no_fix = position.is_none() && self.executor.active();
+        self.publish_setpoint(guidance).await;
+        self.publish_status(position.as_ref(), no_fix).await;
+    }
+
+    /// Pause the mission when the vehicle is where the geofence doesn't allow it, e.g. blown off course. The
+    /// operator gets it back inside and resumes.
+    fn check_geofence(&mut self, position: &GeoPoint) {
+        if !self.executor.active() {
+            return;
+        }
+        if let Some(zone) = self.geofence.iter().find(|zone| !zone.allows(position)) {
+            let message = format!("Vehicle {} geofence zone {}, paused the mission",
+                                  if zone.keep_in { "left" } else { "entered" }, zone.name);
+            // Can't fail, the mission is active.
+            let _ = self.executor.pause();
+            self.result(Err(message));
+        }
+    }
+
+    async fn publish_setpoint(&self, guidance: Option<Guidance>) {
+        let setpoint = NavigationSetpoint {
+            id: self.vehicle_id.clone(),
+            active: guidance.is_some(),
+            heading: guidance.map(|g| g.heading as f32).unwrap_or(f32::NAN),
+            speed: guidance.map(|g| g.speed as f32).unwrap_or(0.0),
+            time: now(),
+        };
+        if let Err(e) = NavigationSetpointTopic::write(&self.setpoint_writer, &setpoint).await {
+            log::error!("Failed to send the setpoint: {:?}", e);
+        }
+    }
+
+    async fn publish_status(&mut self, position: Option<&GeoPoint>, no_fix: bool) {
+        let message = if no_fix { "No GPS fix, stopped".to_string() } else { self.message.clone() };
+        let state = self.executor.state();
+        let current = self.executor.current();
+        if let Some((last_state, last_current, last_message, time)) = &self.last_status {
+            let changed = *last_state != state || *last_current != current || *last_message != message;
+            if !changed && time.elapsed() < STATUS_PERIOD {
+                return;
+            }
+        }
+        let (distance, bearing) = match position.and_then(|p| self.executor.to_waypoint(p)) {
+            Some((distance, bearing)) => (distance as f32, bearing as f32),
+            None => (f32::NAN, f32::NAN),
+        };
+        let status = MissionStatus {
+            id: self.vehicle_id.clone(),
+            mission_name: self.executor.mission().name.clone(),
+            state,
+            current_waypoint: current as u32,
+            waypoint_count: self.executor.mission().waypoints.len() as u32,
+            distance_to_waypoint: distance,
+            bearing_to_waypoint: bearing,
+            loiter_remaining: self.executor.loiter_remaining() as f32,
+            message: message.clone(),
+            time: now(),
+        };
+        match MissionStatusTopic::write(&self.status_writer, &status).await {
+            Ok(_) => self.last_status = Some((state, current, message, Instant::now())),
+            Err(e) => log::error!("Failed to send the mission status: {:?}", e),
+        }
+    }
+}
+
+/// Samples waiting on a reader, empty if there are none.
+async fn take<T: Topic>(reader: &DataReaderAsync<T::Data>) -> Vec<T::Data> {
+    match T::take(reader, MAX_SAMPLES).await {
+        Ok(samples) => samples.into_iter().map(|s| s.data).collect(),
+        Err(DdsError::NoData) => Vec::new(),
+        Err(e) => {
+            log::error!("Failed to take {} samples: {:?}", T::NAME, e);
+            Vec::new()
+        }
+    }
+}
+
+fn now() -> f64 {
+    match std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH) {
+        Ok(val) => val.as_secs_f64(),
+        Err(_) => 0.0
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = CommandLineParameters::parse();
+    let node = Node::init("mission", env!("CARGO_PKG_VERSION"), &cli.node, "./mission.toml");
+
+    let settings: Settings = match node.settings().clone().try_deserialize() {
+        Ok(val) => val,
+        Err(e) => {
+            log::error!("Invalid settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = settings.check() {
+        log::error!("Invalid settings: {}", e);
+        std::process::exit(1);
+    }
+
+    let participant = node.participant_async().await;
+    let publisher = participant
+        .create_publisher(QosKind::Default, None, NO_STATUS)
+        .await
+        .unwrap();
+    let subscriber = participant
+        .create_subscriber(QosKind::Default, None, NO_STATUS)
+        .await
+        .unwrap();
+    let command_reader = subscribe::<MissionCommandTopic>(&participant, &subscriber).await.unwrap();
+    let mission_reader = subscribe::<MissionTopic>(&participant, &subscriber).await.unwrap();
+    let go_to_reader = subscribe::<GoToTopic>(&participant, &subscriber).await.unwrap();
+    let gps_reader = subscribe::<GpsTopic>(&participant, &subscriber).await.unwrap();
+    let geofence_writer = publish::<GeofenceTopic>(&participant, &publisher).await.unwrap();
+
+    let geofence = settings.geofence();
+    // Published even without zones, so a fence from an earlier config is cleared off the map.
+    let fence = Geofence {
+        id: node.vehicle_id().to_string(),
+        zones: geofence.clone(),
+    };
+    if let Err(e) = GeofenceTopic::write(&geofence_writer, &fence).await {
+        log::error!("Failed to send the geofence: {:?}", e);
+    }
+
+    let mut mission_node = MissionNode {
+        vehicle_id: node.vehicle_id().to_string(),
+        executor: Executor::new(node.vehicle_id(), settings.tuning()),
+        geofence,
+        settings,
+        fix: None,
+        message: String::new(),
+        mission_writer: publish::<MissionTopic>(&participant, &publisher).await.unwrap(),
+        status_writer: publish::<MissionStatusTopic>(&participant, &publisher).await.unwrap(),
+        setpoint_writer: publish::<NavigationSetpointTopic>(&participant, &publisher).await.unwrap(),
+        last_status: None,
+    };
+    if let Some(path) = &cli.mission {
+        if let Err(e) = mission_node.load_file(path).await {
+            log::error!("{}", e);
+            node.report_error(&e);
+        }
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / mission_node.settings.update_rate));
+    node.ready();
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                mission_node.handle_commands(&command_reader).await;
+                mission_node.handle_missions(&mission_reader).await;
+                mission_node.handle_go_to(&go_to_reader).await;
+                mission_node.handle_gps(&gps_reader).await;
+                mission_node.update().await;
+                node.progress();
+            }
+            _ = node.wait_for_shutdown() => break,
+        }
+    }
+
+    // Leave the controller with a stop rather than a stale setpoint.
+    mission_node.publish_setpoint(None).await;
+    node.close_async(participant).await;
+}
@@ -0,0 +1,309 @@
+//! Alarm rules from the `[[alarm]]` tables of the config, evaluated against the numbers state_monitor collects.
+//!
+//! Every rule compares one metric against a threshold and raises an alarm when it is crossed. The alarm clears once
+//! the value is back past the `clear` threshold, which defaults to the raise threshold. Rules without a target apply
+//! to every disk, interface, sensor, process, ping host or topic the metric has a value for, each with its own alarm.
+use dust_dds::{
+    dds_async::{
+        data_reader::DataReaderAsync, data_writer::DataWriterAsync, domain_participant::DomainParticipantAsync,
+        publisher::PublisherAsync, subscriber::SubscriberAsync,
+    },
+    infrastructure::error::DdsResult,
+};
+use kingfisher_data_types::dds_topics::{Alarm, AlarmSeverity};
+use kingfisher_data_types::for_each_topic;
+use kingfisher_data_types::topic_registry::{publish, subscribe, take_samples, AlarmTopic, Topic};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+use tokio::task::JoinHandle;
+
+/// Metrics the rules can use and what they measure.
+const METRICS: &[(&str, &str)] = &[
+    ("cpu_usage", "average over all cores (%)"),
+    ("load", "1 minute load average"),
+    ("memory_usage", "used memory (%)"),
+    ("swap_usage", "used swap (%)"),
+    ("disk_usage", "used space (%) of a disk"),
+    ("interface_up", "1 if an interface is up with an address, 0 if not"),
+    ("signal", "wireless signal level (dBm) of an interface"),
+    ("ping_rtt", "round trip time (ms) to a ping host, infinite if the last probe was lost"),
+    ("ping_loss", "lost probes (%) to a ping host"),
+    ("temperature", "temperature (C) of a sensor"),
+    ("process_running", "1 if a process is running, 0 if not"),
+    ("process_restarts", "restarts of a process"),
+    ("topic_age", "seconds since the last sample of a topic for this vehicle"),
+];
+
+/// A rule as written in the config.
+#[derive(Deserialize, Debug, Clone)]
+pub struct AlarmRule {
+    pub name: String,
+    pub metric: String,
+    /// Disk, interface, sensor, process, ping host or topic name, all of them if not set
+    pub target: Option<String>,
+    /// Raise when the value goes above this
+    pub above: Option<f64>,
+    /// Raise when the value goes below this
+    pub below: Option<f64>,
+    /// Clear once the value is back past this, the raise threshold if not set
+    pub clear: Option<f64>,
+    /// info, warning or critical
+    #[serde(default = "default_severity")]
+    pub severity: String,
+    /// Text in front of the value in the alarm message, the rule name if not set
+    pub message: Option<String>,
+}
+
+fn default_severity() -> String {
+    "warning".to_string()
+}
+
+/// Values collected in one update, by metric and target.
+#[derive(Default)]
+pub struct Metrics {
+    values: Vec<(&'static str, String, f64)>,
+}
+
+impl Metrics {
+    pub fn push(&mut self, metric: &'static str, target: &str, value: f64) {
+        self.values.push((metric, target.to_string(), value));
+    }
+}
+
+/// Latest values of every metric group, the groups are refreshed at their own intervals.
+#[derive(Default)]
+pub struct MetricStore {
+    groups: BTreeMap<&'static str, Metrics>,
+}
+
+impl MetricStore {
+    /// Replace the values of a group, dropping those of disks, interfaces and so on that went away.
+    pub fn set(&mut self, group: &'static str, metrics: Metrics) {
+        self.groups.insert(group, metrics);
+    }
+
+    fn values(&self) -> impl Iterator<Item = &(&'static str, String, f64)> {
+        self.groups.values().flat_map(|metrics| metrics.values.iter())
+    }
+}
+
+/// Topic watched by a topic_age rule, followed by its own task.
+struct WatchedTopic {
+    last_sample: Arc<Mutex<Instant>>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for WatchedTopic {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Note the time of every sample from the given vehicle.
+async fn follow<T: Topic>(reader: DataReaderAsync<T::Data>, vehicle_id: String, last_sample: Arc<Mutex<Instant>>) {
+    loop {
+        let samples = take_samples::<T>(&reader, 100).await;
+        if samples.iter().any(|s| T::vehicle_id(&s.data) == vehicle_id) {
+            *last_sample.lock().unwrap() = Instant::now();
+        }
+    }
+}
+
+/// Declares a function that starts watching a topic by name.
+macro_rules! watch_topic {
+    ($($topic:ty),*) => {
+        async fn watch_topic(name: &str, participant: &DomainParticipantAsync, subscriber: &SubscriberAsync,
+                             vehicle_id: &str) -> Option<DdsResult<WatchedTopic>> {
+            $(
+                if name == <$topic as Topic>::NAME {
+                    let reader = match subscribe::<$topic>(participant, subscriber).await {
+                        Ok(val) => val,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    let last_sample = Arc::new(Mutex::new(Instant::now()));
+                    let task = tokio::spawn(follow::<$topic>(reader, vehicle_id.to_string(), last_sample.clone()));
+                    return Some(Ok(WatchedTopic { last_sample, task }));
+                }
+            )*
+            None
+        }
+    };
+}
+
+for_each_topic!(watch_topic);
+
+/// State of one alarm.
+struct AlarmState {
+    /// Rule the alarm comes from
+    rule: String,
+    severity: AlarmSeverity,
+    raised: bool,
+}
+
+pub struct Alarms {
+    participant: DomainParticipantAsync,
+    subscriber: SubscriberAsync,
+    vehicle_id: String,
+    rules: Vec<AlarmRule>,
+    topics: BTreeMap<String, WatchedTopic>,
+    /// Alarms by name
+    alarms: BTreeMap<String, AlarmState>,
+    writer: DataWriterAsync<Alarm>,
+}
+
+fn severity(name: &str) -> Option<AlarmSeverity> {
+    match name {
+        "info" => Some(AlarmSeverity::Info),
+        "warning" => Some(AlarmSeverity::Warning),
+        "critical" => Some(AlarmSeverity::Critical),
+        _ => None,
+    }
+}
+
+impl Alarms {
+    pub async fn new(participant: &DomainParticipantAsync, subscriber: &SubscriberAsync, publisher: &PublisherAsync,
+                     vehicle_id: &str, rules: Vec<AlarmRule>) -> DdsResult<Alarms> {
+        let mut alarms = Alarms {
+            participant: participant.clone(),
+            subscriber: subscriber.clone(),
+            vehicle_id: vehicle_id.to_string(),
+            rules: Vec::new(),
+            topics: BTreeMap::new(),
+            alarms: BTreeMap::new(),
+            writer: publish::<AlarmTopic>(participant, publisher).await?,
+        };
+        alarms.set_rules(rules).await?;
+        Ok(alarms)
+    }
+
+    /// Check the rules, dropping the invalid ones, and watch the topics the topic_age rules use. Alarms of rules
+    /// that are gone are cleared, the others keep their state.
+    pub async fn set_rules(&mut self, rules: Vec<AlarmRule>) -> DdsResult<()> {
+        let mut valid = Vec::new();
+        let mut watched = HashSet::new();
+        for rule in rules {
+            if !METRICS.iter().any(|(metric, _)| *metric == rule.metric) {
+                let names: Vec<&str> = METRICS.iter().map(|(metric, _)| *metric).collect();
+                log::error!("Alarm {} uses unknown metric {}, use one of {}.", rule.name, rule.metric, names.join(", "));
+                continue;
+            }
+            if rule.above.is_some() == rule.below.is_some() {
+                log::error!("Alarm {} needs either above or below.", rule.name);
+                continue;
+            }
+            if severity(&rule.severity).is_none() {
+                log::error!("Alarm {} has unknown severity {}, use info, warning or critical.", rule.name, rule.severity);
+                continue;
+            }
+            if rule.metric == "topic_age" {
+                let topic = match &rule.target {
+                    Some(val) => val.clone(),
+                    None => {
+                        log::error!("Alarm {} needs the topic to watch as target.", rule.name);
+                        continue;
+                    }
+                };
+                if !self.topics.contains_key(&topic) {
+                    match watch_topic(&topic, &self.participant, &self.subscriber, &self.vehicle_id).await {
+                        Some(val) => {
+                            self.topics.insert(topic.clone(), val?);
+                        }
+                        None => {
+                            log::error!("Alarm {} watches unknown topic {}.", rule.name, topic);
+                            continue;
+                        }
+                    }
+                }
+                watched.insert(topic);
+            }
+            valid.push(rule);
+        }
+        log::info!("Loaded {} alarm rules.", valid.len());
+        self.topics.retain(|name, _| watched.contains(name));
+
+        let removed: Vec<String> = self.alarms.iter()
+            .filter(|(_, alarm)| !valid.iter().any(|rule| rule.name == alarm.rule))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in removed {
+            let alarm = self.alarms.remove(&name).unwrap();
+            if alarm.raised {
+                self.publish(&name, alarm.severity, format!("{}: rule removed", name), false).await;
+            }
+        }
+        self.rules = valid;
+        Ok(())
+    }
+
+    /// Evaluate the rules against the latest metrics and publish the alarms that changed.
+    pub async fn update(&mut self, metrics: &MetricStore) {
+        let mut topic_ages = Metrics::default();
+        for (name, topic) in &self.topics {
+            let last_sample = *topic.last_sample.lock().unwrap();
+            topic_ages.push("topic_age", name, last_sample.elapsed().as_secs_f64());
+        }
+
+        let mut evaluated = HashSet::new();
+        let mut changes = Vec::new();
+        for rule in &self.rules {
+            let rule_severity = severity(&rule.severity).unwrap();
+            for (metric, target, value) in metrics.values().chain(topic_ages.values.iter()) {
+                if *metric != rule.metric || rule.target.as_ref().is_some_and(|t| t != target) {
+                    continue;
+                }
+                let name = if target.is_empty() { rule.name.clone() } else { format!("{}/{}", rule.name, target) };
+                if !evaluated.insert(name.clone()) {
+                    continue;
+                }
+
+                let was_raised = self.alarms.get(&name).is_some_and(|a| a.raised);
+                let raised = match (rule.above, rule.below) {
+                    (Some(above), _) => if was_raised { *value > rule.clear.unwrap_or(above) } else { *value > above },
+                    (_, Some(below)) => if was_raised { *value < rule.clear.unwrap_or(below) } else { *value < below },
+                    _ => false,
+                };
+                self.alarms.insert(name.clone(), AlarmState { rule: rule.name.clone(), severity: rule_severity, raised });
+                if raised != was_raised {
+                    let text = rule.message.as_deref().unwrap_or(&rule.name);
+                    let message = if target.is_empty() {
+                        format!("{}: {} = {:.1}", text, rule.metric, value)
+                    } else {
+                        format!("{}: {} of {} = {:.1}", text, rule.metric, target, value)
+                    };
+                    changes.push((name, rule_severity, message, raised));
+                }
+            }
+        }
+        for (name, severity, message, raised) in changes {
+            self.publish(&name, severity, message, raised).await;
+        }
+    }
+
+    async fn publish(&self, name: &str, severity: AlarmSeverity, message: String, raised: bool) {
+        if raised {
+            log::warn!("Alarm raised: {}", message);
+        } else {
+            log::info!("Alarm cleared: {}", message);
+        }
+        let time = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(val) => val.as_secs_f64(),
+            Err(e) => {
+                log::error!("Failed to get the system time: {:?}", e);
+                0.0
+            }
+        };
+        let alarm = Alarm {
+            id: self.vehicle_id.clone(),
+            name: name.to_string(),
+            severity,
+            message,
+            raised,
+            time
+        };
+        if let Err(e) = AlarmTopic::write(&self.writer, &alarm).await {
+            log::error!("Failed to send alarm {}: {:?}", name, e);
+        }
+    }
+}
@@ -1,214 +1,144 @@
 //! Program that publishes system statistic to DDS.
-use sysinfo::{Disks, Networks, System};
-use config::Config;
+mod alarms;
+mod monitor;
+mod network;
+mod ping;
+mod processes;
+mod settings;
+mod supervisor;
+
+use std::time::Duration;
 use clap::Parser;
-use kingfisher_data_types::dds_topics::{
-    SYSTEM_STATUS_CPU_TOPIC, SYSTEM_STATUS_DISK_TOPIC, SYSTEM_STATUS_MEMORY_TOPIC, 
-    SYSTEM_STATUS_NETWORK_TOPIC, SystemStatusMemory,
-    SystemStatusDisk, SystemStatusNetwork, DiskInfo,
-    NetworkInfo, CpuInfo, SystemStatusCpu
-};
-use dust_dds::{
-    domain::domain_participant_factory::DomainParticipantFactory,
-    infrastructure::{qos::QosKind, status::NO_STATUS},
-};
-
+use kingfisher_node::{Node, NodeArgs};
+use dust_dds::infrastructure::{qos::QosKind, status::NO_STATUS};
+use tokio::time::{Interval, MissedTickBehavior};
+use alarms::Alarms;
+use monitor::Monitor;
+use ping::PingProbes;
+use settings::{ConfigWatcher, Intervals, Settings};
+use supervisor::Supervisor;
+
+/// How often the config file is checked for changes
+const CONFIG_CHECK_PERIOD: Duration = Duration::from_secs(2);
 
 /// Parser for command line parameters
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct CommandLineParameters {
-    /// Path to the config file
-    #[arg(short, long)]
-    config_file: Option<String>,
+    #[command(flatten)]
+    node: NodeArgs,
 }
 
-fn main() {
-    //switch this to syslog later: https://rust-lang-nursery.github.io/rust-cookbook/development_tools/debugging/log.html#log-to-the-unix-syslog
-    env_logger::init();
-    log::info!("Starting system status publisher.");
-    
-    let cli = CommandLineParameters::parse();
-    let config_file = match cli.config_file {
-        Some(val) => val,
-        None => String::from("./system_status.toml")
-    };
-
-    let settings = Config::builder()
-        .add_source(config::File::with_name(&config_file))
-        .build()
-        .unwrap();
-
-    let network_interfaces: Vec<String> = settings.get_array("network_interface").unwrap_or(Vec::new()).iter().map(|x| {
-        x.clone().into_string().unwrap()
-    }).collect();
-    let hard_drives: Vec<String> = settings.get_array("hard_drive").unwrap_or(Vec::new()).iter().map(|x| {
-        x.clone().into_string().unwrap()
-    }).collect();
-    let update_rate = settings.get_int("update_rate").unwrap_or(1) as u64;
-
-    // Please note that we use "new_all" to ensure that all lists of
-    // CPUs and processes are filled!
-    let mut sys = System::new_all();
+/// One timer per metric group, so the slow ones don't hold up the others.
+struct Timers {
+    cpu: Interval,
+    memory: Interval,
+    network: Interval,
+    thermal: Interval,
+    processes: Interval,
+    disk: Interval,
+    health: Interval,
+}
 
+impl Timers {
+    fn new(intervals: &Intervals) -> Timers {
+        Timers {
+            cpu: timer(intervals.cpu),
+            memory: timer(intervals.memory),
+            network: timer(intervals.network),
+            thermal: timer(intervals.thermal),
+            processes: timer(intervals.processes),
+            disk: timer(intervals.disk),
+            health: timer(intervals.health),
+        }
+    }
+}
 
-    let domain_id = kingfisher_data_types::DEFAULT_DOMAIN;
-    let participant_factory = DomainParticipantFactory::get_instance();
+fn timer(period: Duration) -> Interval {
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    interval
+}
 
-    let participant = participant_factory
-        .create_participant(domain_id, QosKind::Default, None, NO_STATUS)
-        .unwrap();
+#[tokio::main]
+async fn main() {
+    let cli = CommandLineParameters::parse();
+    let node = Node::init("state_monitor", env!("CARGO_PKG_VERSION"), &cli.node, "./system_status.toml");
+    let mut settings = match Settings::from_config(node.settings()) {
+        Ok(val) => val,
+        Err(e) => {
+            log::error!("Invalid settings in {}: {}", node.config_file(), e);
+            std::process::exit(1);
+        }
+    };
+    let mut config_watcher = ConfigWatcher::new(node.config_file());
 
-    let topic_memory = participant
-        .create_topic::<SystemStatusMemory>(SYSTEM_STATUS_MEMORY_TOPIC, "SystemStatusMemory", QosKind::Default, None, NO_STATUS)
-        .unwrap();
-    let topic_disk = participant
-        .create_topic::<SystemStatusDisk>(SYSTEM_STATUS_DISK_TOPIC, "SystemStatusDisk", QosKind::Default, None, NO_STATUS)
-        .unwrap();
-    let topic_network = participant.create_topic::<SystemStatusNetwork>(SYSTEM_STATUS_NETWORK_TOPIC, "SystemStatusNetwork", QosKind::Default, None, NO_STATUS).unwrap();
-    let topic_cpu = participant.create_topic::<SystemStatusCpu>(SYSTEM_STATUS_CPU_TOPIC, "SystemStatusCpu", QosKind::Default, None, NO_STATUS).unwrap();
+    let participant = node.participant_async().await;
 
     let publisher = participant
         .create_publisher(QosKind::Default, None, NO_STATUS)
+        .await
         .unwrap();
-
-    let memory_writer = publisher
-        .create_datawriter::<SystemStatusMemory>(&topic_memory, QosKind::Default, None, NO_STATUS)
+    let subscriber = participant
+        .create_subscriber(QosKind::Default, None, NO_STATUS)
+        .await
         .unwrap();
-    let disk_writer = publisher
-    .create_datawriter::<SystemStatusDisk>(&topic_disk, QosKind::Default, None, NO_STATUS)
-    .unwrap();
-    let network_writer = publisher
-    .create_datawriter::<SystemStatusNetwork>(&topic_network, QosKind::Default, None, NO_STATUS)
-    .unwrap();
-    let cpu_writer = publisher
-    .create_datawriter::<SystemStatusCpu>(&topic_cpu, QosKind::Default, None, NO_STATUS)
-    .unwrap();
-    
-    loop {
-        
-        //Memory update
-        sys.refresh_memory();
-        let mem = SystemStatusMemory {
-            id: kingfisher_data_types::DEFAULT_ID.into(),
-            total_memory: sys.total_memory(),
-            used_memory: sys.used_memory(),
-            total_swap: sys.total_swap(),
-            used_swap: sys.used_swap()
-        };
 
-        match memory_writer.write(&mem, None) {
-            Ok(_)=> {
-                log::info!("Sent memory update message.");
-            },
-            Err(e) => {
-                log::error!("Failed to send memory message: {:?}", e);
-            }
-        }
+    let mut monitor = Monitor::new(&participant, &publisher, node.vehicle_id(), &settings).await.unwrap();
+    let mut supervisor = Supervisor::new(&participant, &subscriber, &publisher, node.vehicle_id(), settings.supervisor.clone()).await.unwrap();
+    let mut alarms = Alarms::new(&participant, &subscriber, &publisher, node.vehicle_id(), settings.alarm_rules.clone()).await.unwrap();
+    let mut ping_probes = PingProbes::start(settings.ping_hosts.clone(), settings.ping_interval);
+    let mut timers = Timers::new(&settings.intervals);
+    let mut config_check = tokio::time::interval(CONFIG_CHECK_PERIOD);
+    node.ready();
 
-        //CPU update
-        sys.refresh_cpu_all();
-        let mut cpus_info = Vec::new();
-        for cpu in sys.cpus() {
-            cpus_info.push(CpuInfo {
-                name: cpu.name().into(),
-                usage: cpu.cpu_usage()
-            })
-        }
-        let cpu_msg = SystemStatusCpu {
-            id: kingfisher_data_types::DEFAULT_ID.into(),
-            cpus: cpus_info
-        };
-        match cpu_writer.write(&cpu_msg, None) {
-            Ok(_)=> {
-                log::info!("Sent CPU update message.");
+    loop {
+        tokio::select! {
+            _ = timers.cpu.tick() => monitor.update_cpu().await,
+            _ = timers.memory.tick() => monitor.update_memory().await,
+            _ = timers.network.tick() => monitor.update_network(ping_probes.results()).await,
+            _ = timers.thermal.tick() => monitor.update_thermal().await,
+            _ = timers.processes.tick() => monitor.update_processes().await,
+            _ = timers.disk.tick() => monitor.update_disks().await,
+            _ = timers.health.tick() => {
+                // Unlike the status topics, which skip unchanged samples, the health goes out on every tick.
+                if supervisor.update().await {
+                    node.progress();
+                }
             },
-            Err(e) => {
-                log::error!("Failed to send CPU message: {:?}", e);
-            }
-        }
-
-        //Disk Update
-        let mut disk_infos = Vec::new();
-        let disks = Disks::new_with_refreshed_list();
-        for disk in &disks {
-            for name in &hard_drives {
-                if disk.name().to_str().unwrap().contains(name) {
-                    let disk_info = DiskInfo {
-                        name: name.clone(),
-                        bytes_used: disk.total_space() - disk.available_space(),
-                        bytes_available: disk.available_space()
-                    };
-
-                    disk_infos.push(disk_info);
+            _ = config_check.tick() => {
+                if !config_watcher.changed() {
+                    continue;
                 }
-            }
-        }
-        disk_infos.sort_by_key(|d| d.name.clone());
-        let disk_status = SystemStatusDisk {
-            id: kingfisher_data_types::DEFAULT_ID.into(),
-            disk_info: disk_infos
-        };
-
-        match disk_writer.write(&disk_status, None) {
-            Ok(_) => {
-                log::info!("Sent disk status update.");
-            }
-            Err(e) => {
-                log::error!("Failed to send disk status update: {:?}", e);
-            }
-        };
-        
-        //Network Update
-        let mut net_infos = Vec::new();
-        let networks = Networks::new_with_refreshed_list();
-        for (interface_name, data) in &networks {
-            for name in &network_interfaces {
-                if interface_name.contains(name) {
-                    let mut ip_addresses = Vec::new();
-                    for ip in data.ip_networks() {
-                        ip_addresses.push(format!("{}/{}", ip.addr, ip.prefix));
+                let new_settings = match node.reload_settings().and_then(|config| Settings::from_config(&config)) {
+                    Ok(val) => val,
+                    Err(e) => {
+                        // Often just an editor half way through saving, the next change loads it.
+                        log::error!("Keeping the current settings, failed to reload {}: {}", node.config_file(), e);
+                        continue;
                     }
-                    ip_addresses.sort();
-                    let net_info = NetworkInfo {
-                        name: interface_name.clone(),
-                        ip_address: ip_addresses,
-                        bytes_sent: data.total_transmitted(),
-                        bytes_received: data.total_received(),
-                        transmit_errors: data.total_errors_on_transmitted(),
-                        receive_errors: data.total_errors_on_received()
-                    };
-
-                    net_infos.push(net_info);
+                };
+                log::info!("Reloading {}, the vehicle id, domain, log level and QoS need a restart to change.", node.config_file());
+                monitor.configure(&new_settings);
+                supervisor.set_settings(new_settings.supervisor.clone());
+                if let Err(e) = alarms.set_rules(new_settings.alarm_rules.clone()).await {
+                    log::error!("Failed to apply the alarm rules: {:?}", e);
                 }
-            }
-        }
-        net_infos.sort_by_key(|d| d.name.clone());
-
-        let net_info = SystemStatusNetwork {
-            id: kingfisher_data_types::DEFAULT_ID.into(),
-            network_info: net_infos
-        };
-
-        match network_writer.write(&net_info, None) {
-            Ok(_) => {
-                log::info!("Sent network status update.");
+                if new_settings.ping_hosts != settings.ping_hosts || new_settings.ping_interval != settings.ping_interval {
+                    let old = std::mem::replace(&mut ping_probes, PingProbes::start(new_settings.ping_hosts.clone(), new_settings.ping_interval));
+                    // Waits for the probes in flight, which may take up to a ping timeout.
+                    tokio::task::spawn_blocking(move || old.stop());
+                }
+                if new_settings.intervals != settings.intervals {
+                    timers = Timers::new(&new_settings.intervals);
+                }
+                settings = new_settings;
             },
-            Err(e) => {
-                log::error!("Failed to send network status update: {:?}", e);
-            }
+            _ = node.wait_for_shutdown() => break,
         }
-
-
-        // // Components temperature:
-        // let components = Components::new_with_refreshed_list();
-        // println!("=> components:");
-        // for component in &components {
-        //     println!("{component:?}");
-        // }
-
-        std::thread::sleep(std::time::Duration::from_secs(update_rate));
-        
+        alarms.update(&monitor.metrics).await;
     }
-    
+
+    ping_probes.stop();
+    node.close_async(participant).await;
 }
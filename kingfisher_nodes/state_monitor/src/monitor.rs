@@ -0,0 +1,308 @@
+//! Collects the system statistics, one group of metrics at a time, and publishes them when they change.
+use crate::alarms::{MetricStore, Metrics};
+use crate::network::{self, RateTracker};
+use crate::processes::ProcessTracker;
+use crate::settings::Settings;
+use dust_dds::{
+    dds_async::{data_writer::DataWriterAsync, domain_participant::DomainParticipantAsync, publisher::PublisherAsync},
+    infrastructure::error::DdsResult,
+};
+use kingfisher_data_types::dds_topics::{
+    CpuInfo, DiskInfo, NetworkInfo, PingInfo, ProcessInfo, SystemStatusCpu, SystemStatusDisk, SystemStatusMemory,
+    SystemStatusNetwork, SystemStatusProcesses, SystemStatusThermal, ThermalInfo
+};
+use kingfisher_data_types::topic_registry::{
+    publish, SystemStatusCpuTopic, SystemStatusDiskTopic, SystemStatusMemoryTopic, SystemStatusNetworkTopic,
+    SystemStatusProcessesTopic, SystemStatusThermalTopic, Topic
+};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use sysinfo::{Components, Disks, Networks, System};
+
+/// Writer that skips samples equal to the last one sent, until the max publish interval has passed.
+struct ChangePublisher<T: Topic> {
+    writer: DataWriterAsync<T::Data>,
+    /// Debug text of the last sample sent, and when
+    last: Option<(String, Instant)>,
+}
+
+impl<T: Topic> ChangePublisher<T> {
+    async fn new(participant: &DomainParticipantAsync, publisher: &PublisherAsync) -> DdsResult<Self> {
+        Ok(ChangePublisher {
+            writer: publish::<T>(participant, publisher).await?,
+            last: None,
+        })
+    }
+
+    async fn publish(&mut self, data: &T::Data, max_interval: Duration) {
+        // Compared as text, as the NaNs of sensors without a reading would never compare equal.
+        let text = format!("{:?}", data);
+        if let Some((last, time)) = &self.last {
+            if *last == text && time.elapsed() < max_interval {
+                log::debug!("{} unchanged.", T::NAME);
+                return;
+            }
+        }
+        match T::write(&self.writer, data).await {
+            Ok(_) => {
+                log::info!("Sent {} update.", T::NAME);
+                self.last = Some((text, Instant::now()));
+            },
+            Err(e) => {
+                log::error!("Failed to send {} update: {:?}", T::NAME, e);
+            }
+        }
+    }
+}
+
+pub struct Monitor {
+    vehicle_id: String,
+    network_interfaces: Vec<String>,
+    hard_drives: Vec<String>,
+    max_publish_interval: Duration,
+    // The sysinfo lists are refreshed in place, building them scans /proc and /sys from scratch.
+    sys: System,
+    disks: Disks,
+    networks: Networks,
+    components: Components,
+    process_tracker: ProcessTracker,
+    network_rates: RateTracker,
+    /// Highest temperature of each sensor since we started
+    max_temperatures: HashMap<String, f32>,
+    cpu_writer: ChangePublisher<SystemStatusCpuTopic>,
+    memory_writer: ChangePublisher<SystemStatusMemoryTopic>,
+    disk_writer: ChangePublisher<SystemStatusDiskTopic>,
+    network_writer: ChangePublisher<SystemStatusNetworkTopic>,
+    thermal_writer: ChangePublisher<SystemStatusThermalTopic>,
+    processes_writer: ChangePublisher<SystemStatusProcessesTopic>,
+    /// Latest values for the alarm rules
+    pub metrics: MetricStore,
+}
+
+impl Monitor {
+    pub async fn new(participant: &DomainParticipantAsync, publisher: &PublisherAsync, vehicle_id: &str,
+                     settings: &Settings) -> DdsResult<Monitor> {
+        Ok(Monitor {
+            vehicle_id: vehicle_id.to_string(),
+            network_interfaces: settings.network_interfaces.clone(),
+            hard_drives: settings.hard_drives.clone(),
+            max_publish_interval: settings.max_publish_interval,
+            // Please note that we use "new_all" to ensure that all lists of
+            // CPUs and processes are filled!
+            sys: System::new_all(),
+            disks: Disks::new_with_refreshed_list(),
+            networks: Networks::new_with_refreshed_list(),
+            components: Components::new_with_refreshed_list(),
+            process_tracker: ProcessTracker::new(settings.processes.clone()),
+            network_rates: RateTracker::default(),
+            max_temperatures: HashMap::new(),
+            cpu_writer: ChangePublisher::new(participant, publisher).await?,
+            memory_writer: ChangePublisher::new(participant, publisher).await?,
+            disk_writer: ChangePublisher::new(participant, publisher).await?,
+            network_writer: ChangePublisher::new(participant, publisher).await?,
+            thermal_writer: ChangePublisher::new(participant, publisher).await?,
+            processes_writer: ChangePublisher::new(participant, publisher).await?,
+            metrics: MetricStore::default(),
+        })
+    }
+
+    /// Apply reloaded settings, the next update of each group uses them.
+    pub fn configure(&mut self, settings: &Settings) {
+        self.network_interfaces = settings.network_interfaces.clone();
+        self.hard_drives = settings.hard_drives.clone();
+        self.max_publish_interval = settings.max_publish_interval;
+        self.process_tracker.set_names(settings.processes.clone());
+    }
+
+    pub async fn update_cpu(&mut self) {
+        self.sys.refresh_cpu_all();
+        let mut cpus_info = Vec::new();
+        for cpu in self.sys.cpus() {
+            cpus_info.push(CpuInfo {
+                name: cpu.name().into(),
+                usage: cpu.cpu_usage(),
+                frequency: cpu.frequency()
+            })
+        }
+        let load = System::load_average();
+        let mut metrics = Metrics::default();
+        if !cpus_info.is_empty() {
+            let usage: f32 = cpus_info.iter().map(|c| c.usage).sum();
+            metrics.push("cpu_usage", "", (usage / cpus_info.len() as f32) as f64);
+        }
+        metrics.push("load", "", load.one);
+        self.metrics.set("cpu", metrics);
+
+        let cpu_msg = SystemStatusCpu {
+            id: self.vehicle_id.clone(),
+            cpus: cpus_info,
+            load_one: load.one,
+            load_five: load.five,
+            load_fifteen: load.fifteen,
+            uptime: System::uptime()
+        };
+        self.cpu_writer.publish(&cpu_msg, self.max_publish_interval).await;
+    }
+
+    pub async fn update_memory(&mut self) {
+        self.sys.refresh_memory();
+        let mem = SystemStatusMemory {
+            id: self.vehicle_id.clone(),
+            total_memory: self.sys.total_memory(),
+            used_memory: self.sys.used_memory(),
+            total_swap: self.sys.total_swap(),
+            used_swap: self.sys.used_swap()
+        };
+        let mut metrics = Metrics::default();
+        if mem.total_memory > 0 {
+            metrics.push("memory_usage", "", mem.used_memory as f64 / mem.total_memory as f64 * 100.0);
+        }
+        if mem.total_swap > 0 {
+            metrics.push("swap_usage", "", mem.used_swap as f64 / mem.total_swap as f64 * 100.0);
+        }
+        self.metrics.set("memory", metrics);
+
+        self.memory_writer.publish(&mem, self.max_publish_interval).await;
+    }
+
+    pub async fn update_disks(&mut self) {
+        // Also picks up disks mounted since the last update and drops the unmounted ones.
+        self.disks.refresh(true);
+        let mut disk_infos = Vec::new();
+        for disk in &self.disks {
+            for name in &self.hard_drives {
+                if disk.name().to_string_lossy().contains(name.as_str()) {
+                    disk_infos.push(DiskInfo {
+                        name: name.clone(),
+                        bytes_used: disk.total_space().saturating_sub(disk.available_space()),
+                        bytes_available: disk.available_space()
+                    });
+                }
+            }
+        }
+        disk_infos.sort_by_key(|d| d.name.clone());
+        let mut metrics = Metrics::default();
+        for disk in &disk_infos {
+            let total = disk.bytes_used + disk.bytes_available;
+            if total > 0 {
+                metrics.push("disk_usage", &disk.name, disk.bytes_used as f64 / total as f64 * 100.0);
+            }
+        }
+        self.metrics.set("disk", metrics);
+
+        let disk_status = SystemStatusDisk {
+            id: self.vehicle_id.clone(),
+            disk_info: disk_infos
+        };
+        self.disk_writer.publish(&disk_status, self.max_publish_interval).await;
+    }
+
+    /// Refresh the interfaces and publish them with the latest ping results.
+    pub async fn update_network(&mut self, pings: Vec<PingInfo>) {
+        self.networks.refresh(true);
+        let wireless = network::read_wireless();
+        let mut net_infos = Vec::new();
+        for (interface_name, data) in &self.networks {
+            if !self.network_interfaces.iter().any(|name| interface_name.contains(name.as_str())) {
+                continue;
+            }
+            let mut ip_addresses = Vec::new();
+            for ip in data.ip_networks() {
+                ip_addresses.push(format!("{}/{}", ip.addr, ip.prefix));
+            }
+            ip_addresses.sort();
+            let (send_rate, receive_rate) = self.network_rates.update(interface_name, data.total_transmitted(), data.total_received());
+            let link = wireless.get(interface_name);
+            net_infos.push(NetworkInfo {
+                name: interface_name.clone(),
+                ip_address: ip_addresses,
+                bytes_sent: data.total_transmitted(),
+                bytes_received: data.total_received(),
+                transmit_errors: data.total_errors_on_transmitted(),
+                receive_errors: data.total_errors_on_received(),
+                send_rate,
+                receive_rate,
+                link_quality: link.map(|l| l.link_quality).unwrap_or(f32::NAN),
+                signal: link.map(|l| l.signal).unwrap_or(f32::NAN),
+                noise: link.map(|l| l.noise).unwrap_or(f32::NAN),
+                bitrate: link.and_then(|_| network::tx_bitrate(interface_name)).unwrap_or(f32::NAN)
+            });
+        }
+        net_infos.sort_by_key(|d| d.name.clone());
+
+        let mut metrics = Metrics::default();
+        // A configured interface that is missing or has no address counts as down.
+        for name in &self.network_interfaces {
+            let up = net_infos.iter().any(|n| n.name.contains(name.as_str()) && !n.ip_address.is_empty());
+            metrics.push("interface_up", name, if up { 1.0 } else { 0.0 });
+        }
+        for net in &net_infos {
+            if !net.signal.is_nan() {
+                metrics.push("signal", &net.name, net.signal as f64);
+            }
+        }
+        for ping in &pings {
+            // A lost probe counts as an infinite round trip.
+            metrics.push("ping_rtt", &ping.host, if ping.rtt.is_nan() { f64::INFINITY } else { ping.rtt as f64 });
+            if !ping.loss.is_nan() {
+                metrics.push("ping_loss", &ping.host, ping.loss as f64);
+            }
+        }
+        self.metrics.set("network", metrics);
+
+        let net_info = SystemStatusNetwork {
+            id: self.vehicle_id.clone(),
+            network_info: net_infos,
+            pings
+        };
+        self.network_writer.publish(&net_info, self.max_publish_interval).await;
+    }
+
+    pub async fn update_thermal(&mut self) {
+        self.components.refresh(true);
+        let mut sensors = Vec::new();
+        for component in &self.components {
+            let temperature = component.temperature().unwrap_or(f32::NAN);
+            let max = self.max_temperatures.entry(component.label().to_string()).or_insert(temperature);
+            if temperature > *max || max.is_nan() {
+                *max = temperature;
+            }
+            sensors.push(ThermalInfo {
+                name: component.label().to_string(),
+                temperature,
+                max: *max,
+                critical: component.critical().unwrap_or(f32::NAN)
+            });
+        }
+        sensors.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut metrics = Metrics::default();
+        for sensor in &sensors {
+            if !sensor.temperature.is_nan() {
+                metrics.push("temperature", &sensor.name, sensor.temperature as f64);
+            }
+        }
+        self.metrics.set("thermal", metrics);
+
+        let thermal = SystemStatusThermal {
+            id: self.vehicle_id.clone(),
+            sensors
+        };
+        self.thermal_writer.publish(&thermal, self.max_publish_interval).await;
+    }
+
+    pub async fn update_processes(&mut self) {
+        let processes: Vec<ProcessInfo> = self.process_tracker.update(&mut self.sys);
+        let mut metrics = Metrics::default();
+        for process in &processes {
+            metrics.push("process_running", &process.name, if process.running { 1.0 } else { 0.0 });
+            metrics.push("process_restarts", &process.name, process.restarts as f64);
+        }
+        self.metrics.set("processes", metrics);
+
+        let status = SystemStatusProcesses {
+            id: self.vehicle_id.clone(),
+            processes
+        };
+        self.processes_writer.publish(&status, self.max_publish_interval).await;
+    }
+}
@@ -0,0 +1,90 @@
+//! Throughput rates and wireless link quality of the network interfaces.
+use std::collections::HashMap;
+use std::process::Command;
+use std::time::Instant;
+
+/// Signal numbers of a wireless interface, NaN when the driver doesn't report them.
+pub struct WirelessInfo {
+    /// Link quality in the driver's own units, usually out of 70
+    pub link_quality: f32,
+    /// Signal level (dBm)
+    pub signal: f32,
+    /// Noise level (dBm)
+    pub noise: f32,
+}
+
+/// Read the wireless interfaces from /proc/net/wireless.
+pub fn read_wireless() -> HashMap<String, WirelessInfo> {
+    match std::fs::read_to_string("/proc/net/wireless") {
+        Ok(val) => parse_wireless(&val),
+        Err(_) => HashMap::new(),
+    }
+}
+
+fn parse_wireless(text: &str) -> HashMap<String, WirelessInfo> {
+    let mut interfaces = HashMap::new();
+    // The first two lines are headers.
+    for line in text.lines().skip(2) {
+        let (name, rest) = match line.split_once(':') {
+            Some(val) => val,
+            None => continue,
+        };
+        let fields: Vec<f32> = rest.split_whitespace()
+            .skip(1)
+            .take(3)
+            .map(|f| f.trim_end_matches('.').parse().unwrap_or(f32::NAN))
+            .collect();
+        if fields.len() < 3 {
+            continue;
+        }
+        // Drivers that don't measure the noise report -256.
+        let noise = if fields[2] <= -256.0 { f32::NAN } else { fields[2] };
+        interfaces.insert(name.trim().to_string(), WirelessInfo {
+            link_quality: fields[0],
+            signal: fields[1],
+            noise,
+        });
+    }
+    interfaces
+}
+
+/// Transmit bitrate (Mbit/s) of a connected wireless interface, from `iw` if it is installed.
+pub fn tx_bitrate(interface: &str) -> Option<f32> {
+    let output = Command::new("iw").args(["dev", interface, "link"]).output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    // e.g. "	tx bitrate: 72.2 MBit/s MCS 7 short GI"
+    text.lines()
+        .find_map(|line| line.trim().strip_prefix("tx bitrate:"))
+        .and_then(|rate| rate.split_whitespace().next())
+        .and_then(|rate| rate.parse().ok())
+}
+
+/// Turns the byte counters of the interfaces into rates.
+#[derive(Default)]
+pub struct RateTracker {
+    /// Bytes sent and received, and when, at the last update of each interface
+    last: HashMap<String, (u64, u64, Instant)>,
+}
+
+impl RateTracker {
+    /// Bytes per second sent and received since the last update, 0 on the first one or after a counter reset.
+    pub fn update(&mut self, interface: &str, sent: u64, received: u64) -> (f64, f64) {
+        let now = Instant::now();
+        let rates = match self.last.get(interface) {
+            Some((last_sent, last_received, time)) => {
+                let elapsed = now.duration_since(*time).as_secs_f64();
+                if elapsed > 0.0 {
+                    (
+                        sent.saturating_sub(*last_sent) as f64 / elapsed,
+                        received.saturating_sub(*last_received) as f64 / elapsed,
+                    )
+                } else {
+                    (0.0, 0.0)
+                }
+            }
+            None => (0.0, 0.0),
+        };
+        self.last.insert(interface.to_string(), (sent, received, now));
+        rates
+    }
+}
@@ -0,0 +1,105 @@
+//! Round trip time probes to the shore hosts, run from background threads so a lost link doesn't hold up the other
+//! status updates.
+use kingfisher_data_types::dds_topics::PingInfo;
+use std::collections::VecDeque;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Number of probes the loss is computed over
+const LOSS_WINDOW: usize = 20;
+
+/// Results of the last probes of one host.
+#[derive(Default)]
+struct ProbeResults {
+    /// Round trip times (ms), None for lost probes, most recent last
+    results: VecDeque<Option<f32>>,
+}
+
+struct Probe {
+    host: String,
+    results: Arc<Mutex<ProbeResults>>,
+    thread: JoinHandle<()>,
+}
+
+pub struct PingProbes {
+    probes: Vec<Probe>,
+    stop: Arc<AtomicBool>,
+}
+
+impl PingProbes {
+    /// Start probing every host at the given interval.
+    pub fn start(hosts: Vec<String>, interval: Duration) -> PingProbes {
+        let stop = Arc::new(AtomicBool::new(false));
+        let probes = hosts.into_iter().map(|host| {
+            let results = Arc::new(Mutex::new(ProbeResults::default()));
+            let thread = {
+                let host = host.clone();
+                let results = results.clone();
+                let stop = stop.clone();
+                std::thread::spawn(move || probe_loop(&host, interval, &results, &stop))
+            };
+            Probe { host, results, thread }
+        }).collect();
+        PingProbes { probes, stop }
+    }
+
+    /// Latest round trip time and the loss over the last probes of every host.
+    pub fn results(&self) -> Vec<PingInfo> {
+        self.probes.iter().map(|probe| {
+            let results = probe.results.lock().unwrap();
+            let lost = results.results.iter().filter(|r| r.is_none()).count();
+            PingInfo {
+                host: probe.host.clone(),
+                rtt: results.results.back().copied().flatten().unwrap_or(f32::NAN),
+                loss: if results.results.is_empty() { f32::NAN } else { lost as f32 / results.results.len() as f32 * 100.0 },
+            }
+        }).collect()
+    }
+
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for probe in self.probes {
+            let _ = probe.thread.join();
+        }
+    }
+}
+
+fn probe_loop(host: &str, interval: Duration, results: &Mutex<ProbeResults>, stop: &AtomicBool) {
+    while !stop.load(Ordering::Relaxed) {
+        let started = Instant::now();
+        let rtt = ping(host, interval);
+        {
+            let mut results = results.lock().unwrap();
+            if results.results.len() >= LOSS_WINDOW {
+                results.results.pop_front();
+            }
+            results.results.push_back(rtt);
+        }
+        while !stop.load(Ordering::Relaxed) && started.elapsed() < interval {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+/// Send one ping with the system ping command, which doesn't need extra privileges. Returns the round trip time (ms).
+fn ping(host: &str, timeout: Duration) -> Option<f32> {
+    let timeout = timeout.as_secs().max(1).to_string();
+    let output = match Command::new("ping").args(["-n", "-c", "1", "-W", &timeout, host]).output() {
+        Ok(val) => val,
+        Err(e) => {
+            log::error!("Failed to run ping: {}", e);
+            return None;
+        }
+    };
+    if !output.status.success() {
+        return None;
+    }
+    // e.g. "64 bytes from 192.168.1.1: icmp_seq=1 ttl=64 time=2.41 ms"
+    let text = String::from_utf8_lossy(&output.stdout);
+    text.split_whitespace()
+        .find_map(|word| word.strip_prefix("time="))
+        .and_then(|time| time.parse().ok())
+}
@@ -0,0 +1,84 @@
+//! Keeps track of the kingfisher node processes, and counts their restarts.
+use kingfisher_data_types::dds_topics::ProcessInfo;
+use sysinfo::{ProcessesToUpdate, System};
+
+pub struct ProcessTracker {
+    /// Last state of every process listed in the config, in config order
+    processes: Vec<ProcessInfo>,
+    /// Whether each process was ever seen, the first sighting isn't a restart
+    seen: Vec<bool>,
+}
+
+impl ProcessTracker {
+    pub fn new(names: Vec<String>) -> Self {
+        ProcessTracker {
+            seen: vec![false; names.len()],
+            processes: names.into_iter().map(|name| ProcessInfo {
+                name,
+                running: false,
+                pid: 0,
+                cpu_usage: 0.0,
+                memory: 0,
+                run_time: 0,
+                restarts: 0
+            }).collect(),
+        }
+    }
+
+    /// Change the processes to report on, keeping the state and restart counts of those already tracked.
+    pub fn set_names(&mut self, names: Vec<String>) {
+        let mut tracker = ProcessTracker::new(names);
+        for (info, seen) in tracker.processes.iter_mut().zip(tracker.seen.iter_mut()) {
+            if let Some(i) = self.processes.iter().position(|p| p.name == info.name) {
+                *info = self.processes[i].clone();
+                *seen = self.seen[i];
+            }
+        }
+        *self = tracker;
+    }
+
+    /// Refresh the processes and return their current state.
+    pub fn update(&mut self, sys: &mut System) -> Vec<ProcessInfo> {
+        if self.processes.is_empty() {
+            return Vec::new();
+        }
+        sys.refresh_processes(ProcessesToUpdate::All, true);
+
+        for (info, seen) in self.processes.iter_mut().zip(self.seen.iter_mut()) {
+            // Linux truncates process names to 15 characters. With several matches keep following the one already
+            // tracked, so starting a second copy by hand doesn't count as a restart.
+            let mut matches: Vec<_> = sys.processes().values()
+                .filter(|p| p.thread_kind().is_none() && name_matches(&p.name().to_string_lossy(), &info.name))
+                .collect();
+            matches.sort_by_key(|p| (p.pid().as_u32() != info.pid, p.pid()));
+
+            match matches.first() {
+                Some(process) => {
+                    let pid = process.pid().as_u32();
+                    if *seen && pid != info.pid {
+                        info.restarts += 1;
+                        log::warn!("{} restarted, pid {} -> {}.", info.name, info.pid, pid);
+                    }
+                    *seen = true;
+                    info.running = true;
+                    info.pid = pid;
+                    info.cpu_usage = process.cpu_usage();
+                    info.memory = process.memory();
+                    info.run_time = process.run_time();
+                },
+                None => {
+                    if info.running {
+                        log::warn!("{} (pid {}) is not running.", info.name, info.pid);
+                    }
+                    info.running = false;
+                    info.cpu_usage = 0.0;
+                }
+            }
+        }
+        self.processes.clone()
+    }
+}
+
+fn name_matches(process_name: &str, name: &str) -> bool {
+    process_name == name || (process_name.len() == 15 && name.starts_with(process_name))
+}
@@ -0,0 +1,128 @@
+//! The state_monitor part of system_status.toml, read at start up and again whenever the file changes.
+use crate::alarms::AlarmRule;
+use crate::supervisor::SupervisorSettings;
+use config::{Config, ConfigError};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often each group of metrics is refreshed.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Intervals {
+    pub cpu: Duration,
+    pub memory: Duration,
+    pub network: Duration,
+    pub thermal: Duration,
+    pub processes: Duration,
+    pub disk: Duration,
+    /// Node heartbeats and the SystemHealth summary
+    pub health: Duration,
+}
+
+pub struct Settings {
+    pub network_interfaces: Vec<String>,
+    pub hard_drives: Vec<String>,
+    pub intervals: Intervals,
+    /// Longest time a status topic goes without a sample when nothing changes
+    pub max_publish_interval: Duration,
+    /// Processes to report on
+    pub processes: Vec<String>,
+    pub ping_hosts: Vec<String>,
+    pub ping_interval: Duration,
+    pub supervisor: SupervisorSettings,
+    pub alarm_rules: Vec<AlarmRule>,
+}
+
+impl Settings {
+    pub fn from_config(settings: &Config) -> Result<Settings, ConfigError> {
+        if settings.get_float("update_rate").is_ok() {
+            log::warn!("update_rate is no longer used, set the [intervals] of the metric groups instead.");
+        }
+        let expected_nodes = strings(settings, "expected_nodes")?;
+        let processes = match settings.get_array("processes") {
+            Ok(_) => strings(settings, "processes")?,
+            // The nodes the supervisor expects by default
+            Err(ConfigError::NotFound(_)) => expected_nodes.clone(),
+            Err(e) => return Err(e),
+        };
+        let alarm_rules = match settings.get("alarm") {
+            Ok(val) => val,
+            Err(ConfigError::NotFound(_)) => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Settings {
+            network_interfaces: strings(settings, "network_interface")?,
+            hard_drives: strings(settings, "hard_drive")?,
+            intervals: Intervals {
+                cpu: seconds(settings, "intervals.cpu", 1.0)?,
+                memory: seconds(settings, "intervals.memory", 2.0)?,
+                network: seconds(settings, "intervals.network", 2.0)?,
+                thermal: seconds(settings, "intervals.thermal", 5.0)?,
+                processes: seconds(settings, "intervals.processes", 5.0)?,
+                disk: seconds(settings, "intervals.disk", 30.0)?,
+                health: seconds(settings, "intervals.health", 1.0)?,
+            },
+            max_publish_interval: seconds(settings, "max_publish_interval", 10.0)?,
+            processes,
+            ping_hosts: strings(settings, "ping_hosts")?,
+            ping_interval: seconds(settings, "ping_interval", 1.0)?,
+            supervisor: SupervisorSettings {
+                expected_nodes,
+                stale_after: settings.get_float("heartbeat_stale_after").unwrap_or(3.0),
+                missing_after: settings.get_float("heartbeat_missing_after").unwrap_or(10.0),
+            },
+            alarm_rules,
+        })
+    }
+}
+
+/// A list of strings, empty if not set.
+fn strings(settings: &Config, key: &str) -> Result<Vec<String>, ConfigError> {
+    match settings.get_array(key) {
+        Ok(val) => val.into_iter().map(|x| x.into_string()).collect(),
+        Err(ConfigError::NotFound(_)) => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// A positive time in seconds.
+fn seconds(settings: &Config, key: &str, default: f64) -> Result<Duration, ConfigError> {
+    let value = match settings.get_float(key) {
+        Ok(val) => val,
+        Err(ConfigError::NotFound(_)) => default,
+        Err(e) => return Err(e),
+    };
+    if value.is_finite() && value > 0.0 {
+        Ok(Duration::from_secs_f64(value))
+    } else {
+        Err(ConfigError::Message(format!("{} must be a positive number of seconds", key)))
+    }
+}
+
+/// Notices changes to the config file by its modification time.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: &str) -> ConfigWatcher {
+        let path = PathBuf::from(path);
+        let modified = modified(&path);
+        ConfigWatcher { path, modified }
+    }
+
+    /// True if the file was written, created or removed since the last call.
+    pub fn changed(&mut self) -> bool {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,203 @@
+//! Watches the heartbeats of the nodes on this vehicle and publishes a SystemHealth summary.
+use std::collections::BTreeMap;
+use std::time::Instant;
+use dust_dds::{
+    dds_async::{
+        data_reader::DataReaderAsync, data_writer::DataWriterAsync, domain_participant::DomainParticipantAsync,
+        publisher::PublisherAsync, subscriber::SubscriberAsync,
+    },
+    infrastructure::{error::DdsResult, instance::InstanceHandle},
+    subscription::sample_info::{InstanceStateKind, ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE},
+};
+use kingfisher_data_types::dds_topics::{NodeHealth, NodeHealthStatus, NodeHeartbeat, NodeState, SystemHealth};
+use kingfisher_data_types::topic_registry::{
+    publish, subscribe, NodeHeartbeatTopic, SystemHealthTopic, Topic
+};
+
+/// Maximum number of heartbeats taken per update
+const MAX_HEARTBEATS: i32 = 100;
+
+/// When a node is considered stale or missing.
+#[derive(Clone)]
+pub struct SupervisorSettings {
+    /// Nodes that should be running, reported as missing until their first heartbeat
+    pub expected_nodes: Vec<String>,
+    /// Heartbeat age after which a node is stale (s)
+    pub stale_after: f64,
+    /// Heartbeat age after which a node is missing (s)
+    pub missing_after: f64,
+}
+
+/// Last heartbeat of a node and when it arrived.
+struct Tracked {
+    heartbeat: NodeHeartbeat,
+    received: Instant,
+    /// DDS instance of the node's heartbeats, to match the statuses of the reader to the node
+    handle: InstanceHandle,
+    /// The heartbeat missed its deadline since the last one arrived
+    late: bool,
+    /// The node's heartbeat writer went away or lost its liveliness
+    gone: bool,
+}
+
+pub struct Supervisor {
+    vehicle_id: String,
+    settings: SupervisorSettings,
+    heartbeat_reader: DataReaderAsync<NodeHeartbeat>,
+    health_writer: DataWriterAsync<SystemHealth>,
+    nodes: BTreeMap<String, Tracked>,
+}
+
+impl Supervisor {
+    pub async fn new(participant: &DomainParticipantAsync, subscriber: &SubscriberAsync, publisher: &PublisherAsync,
+                     vehicle_id: &str, settings: SupervisorSettings) -> DdsResult<Supervisor> {
+        Ok(Supervisor {
+            vehicle_id: vehicle_id.to_string(),
+            settings,
+            heartbeat_reader: subscribe::<NodeHeartbeatTopic>(participant, subscriber).await?,
+            health_writer: publish::<SystemHealthTopic>(participant, publisher).await?,
+            nodes: BTreeMap::new(),
+        })
+    }
+
+    pub fn set_settings(&mut self, settings: SupervisorSettings) {
+        self.settings = settings;
+    }
+
+    /// Take the new heartbeats and publish the current health of the nodes. Returns whether it was published.
+    pub async fn update(&mut self) -> bool {
+        self.check_statuses().await;
+
+        // Taken without the topic helper, which drops the samples that only signal an instance state change.
+        match self.heartbeat_reader.take(MAX_HEARTBEATS, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE).await {
+            Ok(samples) => {
+                let received = Instant::now();
+                for sample in samples {
+                    let info = sample.sample_info();
+                    if !info.valid_data {
+                        if info.instance_state != InstanceStateKind::Alive {
+                            self.mark(info.instance_handle, |tracked| tracked.gone = true);
+                        }
+                        continue;
+                    }
+                    let heartbeat = match sample.data() {
+                        Ok(heartbeat) => heartbeat,
+                        Err(e) => {
+                            log::error!("Failed to read a heartbeat: {:?}", e);
+                            continue;
+                        }
+                    };
+                    if heartbeat.id != self.vehicle_id {
+                        continue;
+                    }
+                    if !self.nodes.contains_key(&heartbeat.node) {
+                        log::info!("Found node {} (pid {}, version {}).", heartbeat.node, heartbeat.pid, heartbeat.version);
+                    }
+                    self.nodes.insert(heartbeat.node.clone(), Tracked {
+                        heartbeat,
+                        received,
+                        handle: info.instance_handle,
+                        late: false,
+                        gone: false,
+                    });
+                }
+            },
+            Err(dust_dds::infrastructure::error::DdsError::NoData) => (),
+            Err(e) => log::error!("Failed to take heartbeats: {:?}", e)
+        }
+
+        let health = self.health();
+        match SystemHealthTopic::write(&self.health_writer, &health).await {
+            Ok(_) => {
+                log::info!("Sent system health update.");
+                true
+            },
+            Err(e) => {
+                log::error!("Failed to send system health update: {:?}", e);
+                false
+            }
+        }
+    }
+
+    /// A missed deadline marks the node of the last late instance until its next heartbeat. A lost liveliness
+    /// turns the node's instance not alive, which shows up as a sample without data.
+    async fn check_statuses(&mut self) {
+        if let Ok(status) = self.heartbeat_reader.get_liveliness_changed_status().await {
+            if status.not_alive_count_change > 0 {
+                log::warn!("{} heartbeat writers lost their liveliness.", status.not_alive_count_change);
+            }
+        }
+        if let Ok(status) = self.heartbeat_reader.get_requested_deadline_missed_status().await {
+            if status.total_count_change > 0 {
+                log::warn!("Missed {} heartbeat deadlines.", status.total_count_change);
+                self.mark(status.last_instance_handle, |tracked| tracked.late = true);
+            }
+        }
+    }
+
+    /// Update the node whose heartbeats are the instance, if it's one of this vehicle's.
+    fn mark(&mut self, handle: InstanceHandle, update: impl FnOnce(&mut Tracked)) {
+        if let Some((name, tracked)) = self.nodes.iter_mut().find(|(_, tracked)| tracked.handle == handle) {
+            if !tracked.late && !tracked.gone {
+                log::warn!("Node {} stopped sending heartbeats.", name);
+            }
+            update(tracked);
+        }
+    }
+
+    fn health(&self) -> SystemHealth {
+        let mut nodes = Vec::new();
+        for name in &self.settings.expected_nodes {
+            if !self.nodes.contains_key(name) {
+                nodes.push(NodeHealth {
+                    name: name.clone(),
+                    status: NodeHealthStatus::Missing,
+                    state: NodeState::Starting,
+                    pid: 0,
+                    version: String::new(),
+                    uptime: 0.0,
+                    last_error: String::new(),
+                    age: -1.0
+                });
+            }
+        }
+        for (name, tracked) in &self.nodes {
+            let age = tracked.received.elapsed().as_secs_f64();
+            let heartbeat = &tracked.heartbeat;
+            let status = if heartbeat.state == NodeState::Stopping && (tracked.gone || age > self.settings.stale_after) {
+                NodeHealthStatus::Stopped
+            } else if tracked.gone {
+                NodeHealthStatus::Missing
+            } else if age <= self.settings.stale_after && !tracked.late {
+                NodeHealthStatus::Alive
+            } else if age <= self.settings.missing_after {
+                NodeHealthStatus::Stale
+            } else {
+                NodeHealthStatus::Missing
+            };
+            nodes.push(NodeHealth {
+                name: name.clone(),
+                status,
+                state: heartbeat.state,
+                pid: heartbeat.pid,
+                version: heartbeat.version.clone(),
+                uptime: heartbeat.uptime + age,
+                last_error: heartbeat.last_error.clone(),
+                age
+            });
+        }
+        nodes.sort_by(|a, b| a.name.cmp(&b.name));
+
+        // Nodes that aren't expected may come and go as long as they shut down cleanly.
+        let healthy = nodes.iter().all(|n| {
+            (n.status == NodeHealthStatus::Alive && n.state != NodeState::Degraded)
+                || (n.status == NodeHealthStatus::Stopped && !self.settings.expected_nodes.contains(&n.name))
+        });
+
+        SystemHealth {
+            id: self.vehicle_id.clone(),
+            healthy,
+            nodes
+        }
+    }
+}
@@ -22,6 +22,7 @@ use kingfisher_data_types::microcontroller_types::{MicroControlMessages, MicroSt
 //     PIN_CHANGED.store(true, Ordering::SeqCst);
 // }
 
+/// Above this the RC transmitter has taken over, and the throttles from the topside are ignored
 const OVERRIDE_SWITCH_ON: u16 = 6000;
 
 #[arduino_hal::entry]
@@ -156,12 +157,12 @@ fn main() -> ! {
                                 }
                             },
                             Output::PortThrottle(val) => {
-                                if switch.analog_read(&mut adc) > OVERRIDE_SWITCH_ON {
+                                if switch.analog_read(&mut adc) <= OVERRIDE_SWITCH_ON {
                                     port_ctrl.set_duty((val as i16 + 127) as u8);
                                 }
                             },
                             Output::StarboardThrottle(val) => {
-                                if switch.analog_read(&mut adc) > OVERRIDE_SWITCH_ON {
+                                if switch.analog_read(&mut adc) <= OVERRIDE_SWITCH_ON {
                                     stb_ctrl.set_duty((val as i16 + 127) as u8);
                                 }
                             },